@@ -1,15 +1,54 @@
-use rodio::{OutputStream, Sink};
+use anyhow::{anyhow, Result};
+use rodio::{OutputStream, OutputStreamHandle, Sink};
+use std::{
+    sync::mpsc::{self, Sender},
+    thread,
+};
 
 mod media_controls;
 
-use crate::PlaybackSettings;
+pub use media_controls::{MediaControls, MEDIA_CONTROLS_CHECK_INTERVAL};
 
-pub fn new_sink(settings: PlaybackSettings) -> Sink {
-    let (stream, stream_handle) = OutputStream::try_default().unwrap();
-    Box::leak(Box::new(stream));
-    let sink = Sink::try_new(&stream_handle).unwrap();
-    sink.set_volume(settings.volume);
-    sink.set_speed(settings.speed);
+use crate::player::PlaybackSettings;
 
-    sink
+/// The default audio device, kept open for as long as this lives. An `OutputStream` has to
+/// stay on the thread that opened it, so a thread of its own holds it while the handle is
+/// shared with whoever plays.
+pub struct AudioOutput {
+    handle: OutputStreamHandle,
+    _close: Sender<()>, // dropping it lets the thread close the stream
+}
+
+impl AudioOutput {
+    pub fn open() -> Result<AudioOutput> {
+        let (opened, handle) = mpsc::channel();
+        let (close, closed) = mpsc::channel::<()>();
+        thread::Builder::new()
+            .name(String::from("audio output"))
+            .spawn(move || match OutputStream::try_default() {
+                Ok((_stream, handle)) => {
+                    let _ = opened.send(Ok(handle));
+                    // returns once the sender is dropped, closing the stream
+                    let _ = closed.recv();
+                }
+                Err(e) => {
+                    let _ = opened.send(Err(e));
+                }
+            })?;
+        let handle = handle
+            .recv()
+            .map_err(|_| anyhow!("audio output thread exited"))?
+            .map_err(|e| anyhow!("no audio output: {}", e))?;
+        Ok(AudioOutput {
+            handle,
+            _close: close,
+        })
+    }
+
+    pub fn new_sink(&self, settings: PlaybackSettings) -> Result<Sink> {
+        let sink = Sink::try_new(&self.handle)?;
+        sink.set_volume(settings.volume);
+        sink.set_speed(settings.speed);
+        Ok(sink)
+    }
 }
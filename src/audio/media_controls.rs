@@ -1,237 +1,429 @@
-// #![allow(unused_imports)]
-// use iced::window;
-// use souvlaki::MediaPosition;
-// use souvlaki::{MediaControlEvent, MediaControls, MediaMetadata, MediaPlayback, PlatformConfig};
-
-// use crate::Message;
-
-// // yoink: https://github.com/aome510/spotify-player/blob/master/spotify_player/src/media_control.rs
-
-// // fn update_control_metadata(
-// //     controls: &mut MediaControls,
-// //     prev_track_info: &mut String,
-// // ) -> Result<(), souvlaki::Error> {
-// //     let player = state.player.read();
-
-// //     match player.current_playing_track() {
-// //         None => {}
-// //         Some(track) => {
-// //             if let Some(ref playback) = player.playback {
-// //                 let progress = player
-// //                     .playback_progress()
-// //                     .and_then(|p| Some(MediaPosition(p.to_std().ok()?)));
-
-// //                 if playback.is_playing {
-// //                     controls.set_playback(MediaPlayback::Playing { progress })?;
-// //                 } else {
-// //                     controls.set_playback(MediaPlayback::Paused { progress })?;
-// //                 }
-// //             }
-
-// //             // only update metadata when the track information is changed
-// //             let track_info = format!("{}/{}", track.name, track.album.name);
-// //             if track_info != *prev_track_info {
-// //                 controls.set_metadata(MediaMetadata {
-// //                     title: Some(&track.name),
-// //                     album: Some(&track.album.name),
-// //                     artist: Some(&map_join(&track.artists, |a| &a.name, ", ")),
-// //                     duration: track.duration.to_std().ok(),
-// //                     cover_url: utils::get_track_album_image_url(track),
-// //                 })?;
-
-// //                 *prev_track_info = track_info;
-// //             }
-// //         }
-// //     }
-
-// //     Ok(())
-// // }
-
-// /// Start the application's media control event watcher
-// pub fn start_event_watcher() -> Result<(), souvlaki::Error> {
-//     #[cfg(not(target_os = "windows"))]
-//     let hwnd = None;
-
-//     #[cfg(target_os = "windows")]
-//     let (hwnd, _dummy_window) = {
-//         let dummy_window = window::fetch_id(window::Id::MAIN, |_| 0);
-//         let handle = Some(dummy_window as _);
-//         (handle, dummy_window)
-//     };
-
-//     let config = PlatformConfig {
-//         dbus_name: "spotify_player",
-//         display_name: "Spotify Player",
-//         hwnd,
-//     };
-//     let mut controls = MediaControls::new(config)?;
-
-//     controls.attach(move |e| {
-//         tracing::info!("Got a media control event: {e:?}");
-//         match e {
-//             MediaControlEvent::Play => {
-//                 client_pub
-//                     .send(ClientRequest::Player(PlayerRequest::Resume))
-//                     .unwrap_or_default();
-//             }
-//             MediaControlEvent::Pause => {
-//                 client_pub
-//                     .send(ClientRequest::Player(PlayerRequest::Pause))
-//                     .unwrap_or_default();
-//             }
-//             MediaControlEvent::Toggle => {
-//                 client_pub
-//                     .send(ClientRequest::Player(PlayerRequest::ResumePause))
-//                     .unwrap_or_default();
-//             }
-//             MediaControlEvent::SetPosition(MediaPosition(dur)) => {
-//                 if let Ok(dur) = chrono::Duration::from_std(dur) {
-//                     client_pub
-//                         .send(ClientRequest::Player(PlayerRequest::SeekTrack(dur)))
-//                         .unwrap_or_default();
-//                 }
-//             }
-//             MediaControlEvent::Next => {
-//                 client_pub
-//                     .send(ClientRequest::Player(PlayerRequest::NextTrack))
-//                     .unwrap_or_default();
-//             }
-//             MediaControlEvent::Previous => {
-//                 client_pub
-//                     .send(ClientRequest::Player(PlayerRequest::PreviousTrack))
-//                     .unwrap_or_default();
-//             }
-//             MediaControlEvent::SetVolume(volume) => client_pub
-//                 .send(ClientRequest::Player(PlayerRequest::Volume(
-//                     (volume * 100.0) as u8,
-//                 )))
-//                 .unwrap_or_default(),
-//             _ => {}
-//         }
-//     })?;
-//     // For some reason, on startup, media playback needs to be initialized with `Playing`
-//     // for the track metadata to be shown up on the MacOS media status bar.
-//     controls.set_playback(MediaPlayback::Playing { progress: None })?;
-
-//     // The below refresh duration should be no less than 1s to avoid **overloading** linux dbus
-//     // handler provided by the souvlaki library, which only handles an event every 1s.
-//     // [1]: https://github.com/Sinono3/souvlaki/blob/b4d47bb2797ffdd625c17192df640510466762e1/src/platform/linux/mod.rs#L450
-//     let refresh_duration = std::time::Duration::from_millis(1000);
-//     let mut track_info = String::new();
-//     loop {
-//         update_control_metadata(&state, &mut controls, &mut track_info)?;
-//         std::thread::sleep(refresh_duration);
-
-//         // this must be run repeatedly to ensure that
-//         // the Windows event queue is processed by the app
-//         // #[cfg(target_os = "windows")]
-//         // windows::pump_event_queue();
-//     }
-// }
-
-// // demonstrates how to make a minimal window to allow use of media keys on the command line
-// // ref: https://github.com/Sinono3/souvlaki/blob/master/examples/print_events.rs
-// // #[cfg(target_os = "windows")]
-// // mod windows {
-// //     use std::io::Error;
-// //     use std::mem;
-
-// //     use windows::core::w;
-// //     use windows::core::PCWSTR;
-// //     use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
-// //     use windows::Win32::System::LibraryLoader::GetModuleHandleW;
-// //     use windows::Win32::UI::WindowsAndMessaging::{
-// //         CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetAncestor,
-// //         IsDialogMessageW, PeekMessageW, RegisterClassExW, TranslateMessage, GA_ROOT, MSG,
-// //         PM_REMOVE, WINDOW_EX_STYLE, WINDOW_STYLE, WM_QUIT, WNDCLASSEXW,
-// //     };
-
-// //     pub struct DummyWindow {
-// //         pub handle: HWND,
-// //     }
-
-// //     impl DummyWindow {
-// //         pub fn new() -> Result<DummyWindow, String> {
-// //             let class_name = w!("SimpleTray");
-
-// //             let handle_result = unsafe {
-// //                 let instance = GetModuleHandleW(None)
-// //                     .map_err(|e| (format!("Getting module handle failed: {e}")))?;
-
-// //                 let wnd_class = WNDCLASSEXW {
-// //                     cbSize: mem::size_of::<WNDCLASSEXW>() as u32,
-// //                     hInstance: instance.into(),
-// //                     lpszClassName: class_name,
-// //                     lpfnWndProc: Some(Self::wnd_proc),
-// //                     ..Default::default()
-// //                 };
-
-// //                 if RegisterClassExW(&wnd_class) == 0 {
-// //                     return Err(format!(
-// //                         "Registering class failed: {}",
-// //                         Error::last_os_error()
-// //                     ));
-// //                 }
-
-// //                 let handle = CreateWindowExW(
-// //                     WINDOW_EX_STYLE::default(),
-// //                     class_name,
-// //                     w!(""),
-// //                     WINDOW_STYLE::default(),
-// //                     0,
-// //                     0,
-// //                     0,
-// //                     0,
-// //                     None,
-// //                     None,
-// //                     instance,
-// //                     None,
-// //                 );
-
-// //                 if handle.0 == 0 {
-// //                     Err(format!(
-// //                         "Message only window creation failed: {}",
-// //                         Error::last_os_error()
-// //                     ))
-// //                 } else {
-// //                     Ok(handle)
-// //                 }
-// //             };
-
-// //             handle_result.map(|handle| DummyWindow { handle })
-// //         }
-// //         extern "system" fn wnd_proc(
-// //             hwnd: HWND,
-// //             msg: u32,
-// //             wparam: WPARAM,
-// //             lparam: LPARAM,
-// //         ) -> LRESULT {
-// //             unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) }
-// //         }
-// //     }
-
-// //     impl Drop for DummyWindow {
-// //         fn drop(&mut self) {
-// //             unsafe {
-// //                 DestroyWindow(self.handle).unwrap();
-// //             }
-// //         }
-// //     }
-
-// //     pub fn pump_event_queue() -> bool {
-// //         unsafe {
-// //             let mut msg: MSG = std::mem::zeroed();
-// //             let mut has_message = PeekMessageW(&mut msg, None, 0, 0, PM_REMOVE).as_bool();
-// //             while msg.message != WM_QUIT && has_message {
-// //                 if !IsDialogMessageW(GetAncestor(msg.hwnd, GA_ROOT), &msg).as_bool() {
-// //                     let _ = TranslateMessage(&msg);
-// //                     let _ = DispatchMessageW(&msg);
-// //                 }
-
-// //                 has_message = PeekMessageW(&mut msg, None, 0, 0, PM_REMOVE).as_bool();
-// //             }
-
-// //             msg.message == WM_QUIT
-// //         }
-// //     }
-// // }
+use anyhow::{anyhow, Result};
+use souvlaki::{
+    MediaControlEvent, MediaMetadata, MediaPlayback, MediaPosition, PlatformConfig, SeekDirection,
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::library::{cover_art, Song};
+use crate::player::{NowPlaying, Player, PlayerCommand, SEEK_STEP};
+
+/// How often requests from the desktop's media controls are picked up
+pub const MEDIA_CONTROLS_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the position is sent again while playing. MPRIS clients such as `playerctl
+/// position` read it as it was last sent instead of counting on from there.
+const POSITION_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The player as `org.mpris.MediaPlayer2.jukebox` on the D-Bus session bus. Uses whichever bus
+/// `DBUS_SESSION_BUS_ADDRESS` points to, so `dbus-run-session -- cargo run` gives it a private
+/// one to poke at with `dbus-send` or `busctl --user`.
+pub struct MediaControls {
+    controls: souvlaki::MediaControls,
+    events: mpsc::Receiver<MediaControlEvent>,
+    song_key: Option<Uuid>, // `Song::key` of the song whose metadata was last sent
+    playback: Option<(bool, Duration, Instant)>, // last sent playing state, position and when
+    volume: Option<f32>,
+    cover_file: Option<PathBuf>, // the cover art last written for clients to show
+}
+
+impl MediaControls {
+    pub fn new() -> Result<MediaControls> {
+        let config = PlatformConfig {
+            dbus_name: "jukebox",
+            display_name: "Jukebox",
+            hwnd: None,
+        };
+        // souvlaki's errors are not `Sync`, so they cannot be wrapped by anyhow directly
+        let mut controls = souvlaki::MediaControls::new(config).map_err(|e| anyhow!("{}", e))?;
+
+        // events arrive on souvlaki's D-Bus thread and are picked up by `update`
+        let (sender, events) = mpsc::channel();
+        controls
+            .attach(move |event| {
+                let _ = sender.send(event);
+            })
+            .map_err(|e| anyhow!("{}", e))?;
+
+        Ok(MediaControls {
+            controls,
+            events,
+            song_key: None,
+            playback: None,
+            volume: None,
+            cover_file: None,
+        })
+    }
+
+    /// Carries out what the media controls asked for since the last call, then tells them
+    /// what is playing
+    pub fn update(&mut self, player: &mut Player) -> Result<()> {
+        for command in self.events.try_iter().filter_map(to_command) {
+            if let Err(e) = player.execute(command) {
+                println!("Media control request failed: {}", e);
+            }
+        }
+        self.publish(&player.now_playing())
+    }
+
+    /// Sends whatever changed since the last call
+    fn publish(&mut self, now_playing: &NowPlaying) -> Result<()> {
+        let song_key = now_playing.song.as_ref().map(Song::key);
+        if song_key != self.song_key {
+            self.publish_metadata(now_playing.song.as_ref())?;
+            self.song_key = song_key;
+            self.playback = None;
+        }
+
+        let playback_changed = match self.playback {
+            None => true,
+            Some((playing, position, sent_at)) => {
+                playing != now_playing.playing
+                    || (playing && sent_at.elapsed() >= POSITION_UPDATE_INTERVAL)
+                    || (!playing && position != now_playing.position)
+            }
+        };
+        if playback_changed {
+            let progress = Some(MediaPosition(now_playing.position));
+            let playback = match (&now_playing.song, now_playing.playing) {
+                (None, _) => MediaPlayback::Stopped,
+                (Some(_), true) => MediaPlayback::Playing { progress },
+                (Some(_), false) => MediaPlayback::Paused { progress },
+            };
+            self.controls
+                .set_playback(playback)
+                .map_err(|e| anyhow!("{}", e))?;
+            self.playback = Some((now_playing.playing, now_playing.position, Instant::now()));
+        }
+
+        if self.volume != Some(now_playing.volume) {
+            self.controls
+                .set_volume(now_playing.volume.into())
+                .map_err(|e| anyhow!("{}", e))?;
+            self.volume = Some(now_playing.volume);
+        }
+
+        Ok(())
+    }
+
+    fn publish_metadata(&mut self, song: Option<&Song>) -> Result<()> {
+        let Some(song) = song else {
+            return self
+                .controls
+                .set_metadata(MediaMetadata::default())
+                .map_err(|e| anyhow!("{}", e));
+        };
+
+        let artist = song
+            .artists
+            .iter()
+            .map(|artist| artist.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let cover_url = self.cover_url(song);
+        self.controls
+            .set_metadata(MediaMetadata {
+                title: Some(&song.title),
+                album: song.album.as_ref().map(|album| album.title.as_str()),
+                artist: (!artist.is_empty()).then_some(artist.as_str()),
+                cover_url: cover_url.as_deref(),
+                duration: Some(song.duration),
+            })
+            .map_err(|e| anyhow!("{}", e))
+    }
+
+    /// MPRIS only takes cover art as a URL, so covers are written to a file
+    fn cover_url(&mut self, song: &Song) -> Option<String> {
+        let path = write_cover(&cover_dir()?, song, &mut self.cover_file)?;
+        Some(format!("file://{}", path.display()))
+    }
+}
+
+impl Drop for MediaControls {
+    fn drop(&mut self) {
+        if let Some(cover_file) = &self.cover_file {
+            let _ = fs::remove_file(cover_file);
+        }
+    }
+}
+
+/// Where covers are written for clients to read: the user's runtime directory, or their cache
+/// directory on systems without one. Unlike the shared temporary directory, other users can
+/// neither read these files nor put files of their own in their place.
+fn cover_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("XDG_CACHE_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+        })
+        .or_else(|| Some(Path::new(&env::var_os("HOME")?).join(".cache")))?;
+    Some(dir.join("jukebox"))
+}
+
+/// Writes the cover of `song`, if it has one, into `dir` in place of `written`, the cover
+/// written before. Each album gets a file name of its own, so clients that keep covers by URL
+/// notice the change.
+fn write_cover(dir: &Path, song: &Song, written: &mut Option<PathBuf>) -> Option<PathBuf> {
+    let key = song.album.as_ref().map_or(song.key(), |album| album.id);
+    let path = dir.join(format!("cover-{}", key));
+    if let Some(previous) = written.take().filter(|previous| *previous != path) {
+        let _ = fs::remove_file(previous);
+    }
+    let cover = cover_art(song)?;
+    fs::create_dir_all(dir).ok()?;
+    fs::write(&path, cover).ok()?;
+    *written = Some(path.clone());
+    Some(path)
+}
+
+fn to_command(event: MediaControlEvent) -> Option<PlayerCommand> {
+    let forward = |direction| direction == SeekDirection::Forward;
+    match event {
+        MediaControlEvent::Play => Some(PlayerCommand::SetPaused(false)),
+        MediaControlEvent::Pause | MediaControlEvent::Stop => Some(PlayerCommand::SetPaused(true)),
+        MediaControlEvent::Toggle => Some(PlayerCommand::TogglePlayback),
+        MediaControlEvent::Next => Some(PlayerCommand::NextSong),
+        MediaControlEvent::Previous => Some(PlayerCommand::PreviousSong),
+        MediaControlEvent::Seek(direction) => {
+            Some(PlayerCommand::SeekBy(SEEK_STEP, forward(direction)))
+        }
+        MediaControlEvent::SeekBy(direction, step) => {
+            Some(PlayerCommand::SeekBy(step, forward(direction)))
+        }
+        MediaControlEvent::SetPosition(MediaPosition(position)) => {
+            Some(PlayerCommand::SeekTo(position))
+        }
+        MediaControlEvent::SetVolume(volume) => Some(PlayerCommand::SetVolume(volume as f32)),
+        MediaControlEvent::OpenUri(_) | MediaControlEvent::Raise | MediaControlEvent::Quit => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{Album, Artist};
+    use std::process::Command;
+
+    const BUS_NAME: &str = "org.mpris.MediaPlayer2.jukebox";
+    const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+    #[test]
+    fn writes_covers_in_place_of_the_last_one() {
+        let dir = env::temp_dir().join(format!("jukebox-covers-{}", Uuid::new_v4()));
+        let covers = dir.join("covers");
+        let song = |album: &str| {
+            let folder = dir.join(album);
+            fs::create_dir_all(&folder).unwrap();
+            let artist = Artist::new(String::from("Low"));
+            Song {
+                file_path: folder.join("01.flac"),
+                album: Album::new(Some(album.to_string()), artist),
+                ..Song::default()
+            }
+        };
+        let (first, second) = (song("First"), song("Second"));
+        fs::write(dir.join("First").join("cover.jpg"), "first cover").unwrap();
+        let mut written = None;
+
+        let first_path = write_cover(&covers, &first, &mut written).unwrap();
+        assert_eq!(written.as_ref(), Some(&first_path));
+        assert!(first_path.starts_with(&covers));
+        assert_eq!(fs::read_to_string(&first_path).unwrap(), "first cover");
+
+        // written again, not kept from before
+        fs::write(dir.join("First").join("Cover.JPG"), "new cover").unwrap();
+        fs::remove_file(dir.join("First").join("cover.jpg")).unwrap();
+        assert_eq!(
+            write_cover(&covers, &first, &mut written),
+            Some(first_path.clone())
+        );
+        assert_eq!(fs::read_to_string(&first_path).unwrap(), "new cover");
+
+        // another album replaces it
+        fs::write(dir.join("Second").join("folder.png"), "second cover").unwrap();
+        let second_path = write_cover(&covers, &second, &mut written).unwrap();
+        assert_ne!(first_path, second_path);
+        assert!(!first_path.exists());
+        assert_eq!(fs::read_to_string(&second_path).unwrap(), "second cover");
+
+        // as does a song without a cover
+        let single = Song {
+            album: None,
+            ..song("Singles")
+        };
+        assert_eq!(write_cover(&covers, &single, &mut written), None);
+        assert!(!second_path.exists());
+        assert_eq!(written, None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn turns_events_into_player_commands() {
+        let step = Duration::from_secs(12);
+        let cases = [
+            (
+                MediaControlEvent::Play,
+                Some(PlayerCommand::SetPaused(false)),
+            ),
+            (
+                MediaControlEvent::Pause,
+                Some(PlayerCommand::SetPaused(true)),
+            ),
+            (
+                MediaControlEvent::Stop,
+                Some(PlayerCommand::SetPaused(true)),
+            ),
+            (
+                MediaControlEvent::Toggle,
+                Some(PlayerCommand::TogglePlayback),
+            ),
+            (MediaControlEvent::Next, Some(PlayerCommand::NextSong)),
+            (
+                MediaControlEvent::Previous,
+                Some(PlayerCommand::PreviousSong),
+            ),
+            (
+                MediaControlEvent::Seek(SeekDirection::Forward),
+                Some(PlayerCommand::SeekBy(SEEK_STEP, true)),
+            ),
+            (
+                MediaControlEvent::Seek(SeekDirection::Backward),
+                Some(PlayerCommand::SeekBy(SEEK_STEP, false)),
+            ),
+            (
+                MediaControlEvent::SeekBy(SeekDirection::Backward, step),
+                Some(PlayerCommand::SeekBy(step, false)),
+            ),
+            (
+                MediaControlEvent::SetPosition(MediaPosition(step)),
+                Some(PlayerCommand::SeekTo(step)),
+            ),
+            (
+                MediaControlEvent::SetVolume(0.5),
+                Some(PlayerCommand::SetVolume(0.5)),
+            ),
+            (
+                MediaControlEvent::OpenUri(String::from("file:///a.flac")),
+                None,
+            ),
+            (MediaControlEvent::Raise, None),
+            (MediaControlEvent::Quit, None),
+        ];
+        for (event, command) in cases {
+            assert_eq!(to_command(event.clone()), command, "{:?}", event);
+        }
+    }
+
+    /// Sends a method call to the player on the session bus, returning the printed reply
+    fn dbus_send(interface_method: &str, args: &[&str]) -> String {
+        let output = Command::new("dbus-send")
+            .args(["--session", "--print-reply", "--reply-timeout=2000"])
+            .arg(format!("--dest={}", BUS_NAME))
+            .arg("/org/mpris/MediaPlayer2")
+            .arg(interface_method)
+            .args(args)
+            .output()
+            .expect("dbus-send is needed");
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    fn property(name: &str) -> String {
+        dbus_send(
+            "org.freedesktop.DBus.Properties.Get",
+            &[
+                &format!("string:{}", PLAYER_INTERFACE),
+                &format!("string:{}", name),
+            ],
+        )
+    }
+
+    /// The next request that came in over the bus
+    fn next_command(controls: &MediaControls) -> Option<PlayerCommand> {
+        let event = controls
+            .events
+            .recv_timeout(Duration::from_secs(2))
+            .expect("no event arrived");
+        to_command(event)
+    }
+
+    /// Needs a session bus of its own and `dbus-send`:
+    /// `dbus-run-session -- cargo test -- --ignored media_controls`
+    #[test]
+    #[ignore]
+    fn talks_mpris_on_the_session_bus() {
+        let mut controls = MediaControls::new().unwrap();
+        let artist = Artist::new(String::from("Test Artist"));
+        let song = Song {
+            title: String::from("Test Song"),
+            album: Album::new(Some(String::from("Test Album")), artist.clone()),
+            artists: vec![artist],
+            duration: Duration::from_secs(200),
+            ..Song::default()
+        };
+        controls
+            .publish(&NowPlaying {
+                song: Some(song),
+                playing: true,
+                position: Duration::from_secs(5),
+                volume: 0.5,
+            })
+            .unwrap();
+        // the properties are updated on souvlaki's thread
+        std::thread::sleep(Duration::from_millis(200));
+
+        let metadata = property("Metadata");
+        for expected in ["Test Song", "Test Artist", "Test Album"] {
+            assert!(
+                metadata.contains(expected),
+                "{} missing: {}",
+                expected,
+                metadata
+            );
+        }
+        assert!(property("PlaybackStatus").contains("\"Playing\""));
+        assert!(property("Volume").contains("double 0.5"));
+
+        let player_method = |method: &str| format!("{}.{}", PLAYER_INTERFACE, method);
+        dbus_send(&player_method("Pause"), &[]);
+        assert_eq!(
+            next_command(&controls),
+            Some(PlayerCommand::SetPaused(true))
+        );
+        dbus_send(&player_method("Play"), &[]);
+        assert_eq!(
+            next_command(&controls),
+            Some(PlayerCommand::SetPaused(false))
+        );
+        dbus_send(&player_method("Seek"), &["int64:-3000000"]);
+        assert_eq!(
+            next_command(&controls),
+            Some(PlayerCommand::SeekBy(Duration::from_secs(3), false))
+        );
+        dbus_send(
+            "org.freedesktop.DBus.Properties.Set",
+            &[
+                &format!("string:{}", PLAYER_INTERFACE),
+                "string:Volume",
+                "variant:double:0.25",
+            ],
+        );
+        assert_eq!(
+            next_command(&controls),
+            Some(PlayerCommand::SetVolume(0.25))
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use parking_lot::Mutex;
+use std::{collections::HashSet, fs, path::PathBuf, time::Duration};
+
+use crate::library::{
+    current_year, read_history, unix_now, write_playlist, Library, Play, SearchQuery,
+};
+use crate::player::{OpenRequest, Player};
+use crate::server::{
+    claim, is_running, send_request, wait_until_running, ControlRequest, ControlSocket,
+};
+use crate::ui::format_duration;
+use crate::GlobalSettings;
+
+/// How many songs `stats` lists as the most played
+const TOP_SONGS: usize = 10;
+/// How far back `stats` looks in the play history for recent listening
+const RECENT_DAYS: u64 = 7;
+
+/// Without a command the window opens. Only one jukebox runs per control socket, launching
+/// another one hands its files over to the running one.
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "A music player for local libraries",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    /// Play without a window, controlled over the servers and media controls
+    #[arg(long)]
+    pub daemon: bool,
+    /// Replace the queue with the files and play them, instead of adding them to it
+    #[arg(long)]
+    pub play: bool,
+    /// Also add the files to the library when they are not in it yet
+    #[arg(long)]
+    pub add: bool,
+    /// Songs or folders to queue, from the library or anywhere else
+    pub files: Vec<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+/// What the window or the daemon starts with
+pub struct Launch {
+    pub control: Option<ControlSocket>, // `None` when it is turned off or unavailable
+    pub open: OpenRequest,
+}
+
+impl Cli {
+    /// Claims the control socket so that this is the only jukebox running. When another one
+    /// already runs, hands it the files to open instead and returns `None`.
+    pub async fn launch(&self, settings: &GlobalSettings) -> Result<Option<Launch>> {
+        let mut open = OpenRequest {
+            paths: Vec::new(),
+            play: self.play,
+            add_to_library: self.add || settings.add_opened_files,
+        };
+        for file in &self.files {
+            match fs::canonicalize(file) {
+                Ok(path) => open.paths.push(path),
+                Err(e) => println!("Cannot open {}: {}", file.display(), e),
+            }
+        }
+        if settings.control_socket.is_empty() {
+            return Ok(Some(Launch {
+                control: None,
+                open,
+            }));
+        }
+
+        let control = match claim(&settings.control_socket) {
+            Ok(Some(socket)) => Some(socket),
+            Ok(None) if open.paths.is_empty() => {
+                println!("jukebox is already running");
+                return Ok(None);
+            }
+            Ok(None) => {
+                wait_until_running(&settings.control_socket).await?;
+                let request = ControlRequest::Open(open);
+                println!(
+                    "{}",
+                    send_request(&settings.control_socket, &request).await?
+                );
+                return Ok(None);
+            }
+            Err(e) => {
+                println!("Control socket unavailable: {}", e);
+                None
+            }
+        };
+        Ok(Some(Launch { control, open }))
+    }
+}
+
+/// Opens the files a jukebox was launched with, once its library is loaded
+pub fn open_files(player: &Mutex<Player>, request: &OpenRequest) {
+    if request.paths.is_empty() {
+        return;
+    }
+    match Player::open(player, request) {
+        Ok((_, missing)) => {
+            for path in missing {
+                println!("No songs in {}", path.display());
+            }
+        }
+        Err(e) => println!("Opening files failed: {}", e),
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Scan the music folder again, in the running jukebox if there is one
+    Scan,
+    /// List the songs matching a query, one per line: title, artists, album and file
+    Search { query: Vec<String> },
+    /// Replace the queue of the running jukebox with the songs matching a query and play them
+    Play { query: Vec<String> },
+    /// Add the songs matching a query to the queue of the running jukebox
+    Enqueue { query: Vec<String> },
+    /// Pause the running jukebox, or resume it when paused
+    Pause,
+    /// Skip to the next song in the running jukebox
+    Next,
+    /// Show what the running jukebox is playing
+    Status,
+    /// Write a playlist to a file, as M3U, PLS or XSPF depending on the extension
+    ExportPlaylist { name: String, file: PathBuf },
+    /// Show how big the library is and what is played the most
+    Stats,
+    /// Sum up a year of listening as JSON, printed or written to a file
+    Wrapped {
+        /// The current year when left out
+        #[arg(long)]
+        year: Option<i32>,
+        file: Option<PathBuf>,
+    },
+}
+
+impl CliCommand {
+    pub async fn run(self, settings: GlobalSettings) -> Result<()> {
+        let request = match self {
+            CliCommand::Scan => ControlRequest::Scan,
+            CliCommand::Play { query } => ControlRequest::Play(query.join(" ")),
+            CliCommand::Enqueue { query } => ControlRequest::Enqueue(query.join(" ")),
+            CliCommand::Pause => ControlRequest::Pause,
+            CliCommand::Next => ControlRequest::Next,
+            CliCommand::Status => ControlRequest::Status,
+            CliCommand::Search { query } => return search(&settings, &query.join(" ")),
+            CliCommand::ExportPlaylist { name, file } => {
+                return export_playlist(&settings, &name, file)
+            }
+            CliCommand::Stats => return stats(&settings),
+            CliCommand::Wrapped { year, file } => {
+                return wrapped(&settings, year.unwrap_or_else(current_year), file)
+            }
+        };
+
+        // without a running jukebox, scanning updates the library file instead
+        if matches!(request, ControlRequest::Scan) && !is_running(&settings.control_socket).await {
+            return scan(&settings);
+        }
+        println!(
+            "{}",
+            send_request(&settings.control_socket, &request).await?
+        );
+        Ok(())
+    }
+}
+
+fn read_library(settings: &GlobalSettings) -> Result<Library> {
+    Library::read_from_file(&settings.library_file)
+        .map_err(|e| anyhow!("cannot read {}: {}", settings.library_file, e))
+}
+
+fn scan(settings: &GlobalSettings) -> Result<()> {
+    let mut library =
+        Library::read_from_file(&settings.library_file).unwrap_or_else(|_| Library::new());
+    library.rescan(&settings.folder_to_scan)?;
+    library.save_to_file(&settings.library_file)?;
+    println!("Scanned {} songs", library.songs.len());
+    Ok(())
+}
+
+fn search(settings: &GlobalSettings, query: &str) -> Result<()> {
+    let library = read_library(settings)?;
+    for id in library.search(&SearchQuery::parse(query)) {
+        let Some(song) = library.songs.get(&id) else {
+            continue;
+        };
+        let artists: Vec<&str> = song.artists.iter().map(|a| a.name.as_str()).collect();
+        let album = song.album.as_ref().map_or("", |album| album.title.as_str());
+        println!(
+            "{}\t{}\t{}\t{}",
+            song.title,
+            artists.join(", "),
+            album,
+            song.file_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Finds the playlist by name, smart playlists included, ignoring case
+fn export_playlist(settings: &GlobalSettings, name: &str, file: PathBuf) -> Result<()> {
+    let library = read_library(settings)?;
+    let regular = library
+        .playlists
+        .iter()
+        .map(|playlist| (playlist.id, &playlist.name));
+    let smart = library
+        .smart_playlists
+        .iter()
+        .map(|playlist| (playlist.id, &playlist.name));
+    let (id, name) = regular
+        .chain(smart)
+        .find(|(_, playlist_name)| playlist_name.eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow!("there is no playlist called {}", name))?;
+
+    let songs = library.playlist_songs(id)?;
+    write_playlist(&file, name, &songs)?;
+    println!("Exported {} songs to {}", songs.len(), file.display());
+    Ok(())
+}
+
+fn stats(settings: &GlobalSettings) -> Result<()> {
+    let library = read_library(settings)?;
+    let songs: Vec<_> = library.songs.values().collect();
+    let total: Duration = songs.iter().map(|song| song.duration).sum();
+    let plays: u32 = songs.iter().map(|song| song.play_count).sum();
+    let skips: u32 = songs.iter().map(|song| song.skip_count).sum();
+    let listened: Duration = songs
+        .iter()
+        .map(|song| song.duration * song.play_count)
+        .sum();
+    let artists: HashSet<_> = songs
+        .iter()
+        .flat_map(|song| song.artists.iter().map(|artist| artist.id))
+        .collect();
+
+    println!("Songs:    {}", songs.len());
+    println!("Albums:   {}", library.all_albums().len());
+    println!("Artists:  {}", artists.len());
+    println!("Genres:   {}", library.genres().len());
+    println!("Length:   {}", format_duration(total));
+    println!(
+        "Plays:    {} ({} listened)",
+        plays,
+        format_duration(listened)
+    );
+    println!("Skips:    {}", skips);
+    if !settings.history_file.is_empty() {
+        let since = unix_now().saturating_sub(RECENT_DAYS * 24 * 60 * 60);
+        let recent: Vec<_> = history(settings)?
+            .into_iter()
+            .filter(|play| play.started_at >= since)
+            .collect();
+        let recent_time: Duration = recent.iter().map(|play| play.played).sum();
+        println!(
+            "Last {} days: {} plays, {} skips ({} listened)",
+            RECENT_DAYS,
+            recent.iter().filter(|play| !play.skipped).count(),
+            recent.iter().filter(|play| play.skipped).count(),
+            format_duration(recent_time)
+        );
+    }
+
+    let mut most_played: Vec<_> = songs.iter().filter(|song| song.play_count > 0).collect();
+    most_played.sort_by_key(|song| std::cmp::Reverse(song.play_count));
+    if !most_played.is_empty() {
+        println!("\nMost played:");
+    }
+    for song in most_played.into_iter().take(TOP_SONGS) {
+        let artists: Vec<&str> = song.artists.iter().map(|a| a.name.as_str()).collect();
+        println!(
+            "{:>5}  {} - {}",
+            song.play_count,
+            artists.join(", "),
+            song.title
+        );
+    }
+    Ok(())
+}
+
+fn history(settings: &GlobalSettings) -> Result<Vec<Play>> {
+    if settings.history_file.is_empty() {
+        return Ok(Vec::new());
+    }
+    read_history(&settings.history_file)
+}
+
+fn wrapped(settings: &GlobalSettings, year: i32, file: Option<PathBuf>) -> Result<()> {
+    let library = read_library(settings)?;
+    let history = history(settings)?;
+    match file {
+        Some(file) => {
+            library.export_wrapped(&history, year, &file)?;
+            println!("Exported {} summary to {}", year, file.display());
+        }
+        None => println!(
+            "{}",
+            serde_json::to_string_pretty(&library.wrapped(&history, year))?
+        ),
+    }
+    Ok(())
+}
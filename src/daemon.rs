@@ -0,0 +1,59 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use tokio::{signal, time};
+
+use crate::audio::{MediaControls, MEDIA_CONTROLS_CHECK_INTERVAL};
+use crate::cli::{open_files, Launch};
+use crate::library::Library;
+use crate::player::{Player, TICK_INTERVAL};
+use crate::server;
+use crate::GlobalSettings;
+
+/// Plays without a window, e.g. on a machine that only has speakers attached (`--daemon`).
+/// Drives the same `Player` as the window does, with the desktop's media controls and the servers as clients.
+pub async fn run(settings: GlobalSettings, launch: Launch) -> Result<()> {
+    let player = Arc::new(Mutex::new(Player::new(settings.clone())));
+    match Library::read_from_file(&settings.library_file) {
+        Ok(library) => {
+            *player.lock().library().lock() = library;
+            println!("Library loaded successfully.");
+        }
+        Err(e) => println!("Load failed: {}", e),
+    }
+    open_files(&player, &launch.open);
+
+    let mut media_controls = match MediaControls::new() {
+        Ok(media_controls) => Some(media_controls),
+        Err(e) => {
+            println!("Media controls unavailable: {}", e);
+            None
+        }
+    };
+
+    server::start(&player, &settings, launch.control);
+
+    let mut tick = time::interval(TICK_INTERVAL);
+    let mut media_controls_check = time::interval(MEDIA_CONTROLS_CHECK_INTERVAL);
+    println!("jukebox daemon running, ctrl+c to stop");
+    loop {
+        tokio::select! {
+            _ = tick.tick() => player.lock().update_time(),
+            _ = media_controls_check.tick() => {
+                if let Some(Err(e)) = media_controls
+                    .as_mut()
+                    .map(|media_controls| media_controls.update(&mut player.lock()))
+                {
+                    println!("Media controls stopped working: {}", e);
+                    media_controls = None;
+                }
+            }
+            _ = signal::ctrl_c() => break,
+        }
+    }
+
+    // pick up where playback stopped next time
+    let mut player = player.lock();
+    player.save_resume_position();
+    player.save_library()
+}
@@ -0,0 +1,356 @@
+use iced::keyboard::{Key, Modifiers};
+use std::collections::{BTreeMap, HashMap};
+
+/// Something a keyboard shortcut can do, from any screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    TogglePlayback,
+    NextSong,
+    PreviousSong,
+    SeekForward,
+    SeekBackward,
+    VolumeUp,
+    VolumeDown,
+    FocusSearch,
+    NavigateBack,
+    NavigateForward,
+    ToggleShortcutHelp,
+    CloseShortcutHelp,
+}
+
+impl Action {
+    /// Action, name in the `[keymap]` config table, default key and description
+    const ALL: [(Action, &'static str, &'static str, &'static str); 12] = [
+        (
+            Action::TogglePlayback,
+            "toggle_playback",
+            "space",
+            "play or pause",
+        ),
+        (Action::NextSong, "next_song", "n", "next song"),
+        (Action::PreviousSong, "previous_song", "p", "previous song"),
+        (
+            Action::SeekForward,
+            "seek_forward",
+            "right",
+            "skip ahead 5 seconds",
+        ),
+        (
+            Action::SeekBackward,
+            "seek_backward",
+            "left",
+            "skip back 5 seconds",
+        ),
+        (Action::VolumeUp, "volume_up", "up", "volume up"),
+        (Action::VolumeDown, "volume_down", "down", "volume down"),
+        (
+            Action::FocusSearch,
+            "focus_search",
+            "/",
+            "search the library",
+        ),
+        (
+            Action::NavigateBack,
+            "navigate_back",
+            "alt+left",
+            "previous screen",
+        ),
+        (
+            Action::NavigateForward,
+            "navigate_forward",
+            "alt+right",
+            "next screen",
+        ),
+        (
+            Action::ToggleShortcutHelp,
+            "shortcut_help",
+            "?",
+            "show or hide this help",
+        ),
+        (
+            Action::CloseShortcutHelp,
+            "close_shortcut_help",
+            "escape",
+            "hide this help",
+        ),
+    ];
+
+    fn name(&self) -> &'static str {
+        Action::ALL
+            .iter()
+            .find(|(action, ..)| action == self)
+            .map_or("?", |(_, name, ..)| name)
+    }
+
+    fn description(&self) -> &'static str {
+        Action::ALL
+            .iter()
+            .find(|(action, ..)| action == self)
+            .map_or("?", |(.., description)| description)
+    }
+}
+
+/// The default keymap, as it is written to the config file
+pub fn default_keymap() -> BTreeMap<String, String> {
+    Action::ALL
+        .iter()
+        .map(|(_, name, key, _)| (name.to_string(), key.to_string()))
+        .collect()
+}
+
+/// A key together with the modifiers that have to be held
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct KeyCombo {
+    key: String, // lowercase, e.g. "n", "/" or "arrowleft"
+    ctrl: bool,
+    alt: bool,
+    shift: bool, // only for named keys, typed characters already include shift ("?" vs "/")
+    logo: bool,
+}
+
+impl KeyCombo {
+    /// Parses `ctrl+shift+left` style bindings
+    fn parse(binding: &str) -> Result<KeyCombo, String> {
+        let binding = binding.trim().to_lowercase();
+        let (modifiers, key) = match binding.rsplit_once('+') {
+            // a trailing "+" is the plus key itself, as in "ctrl++"
+            Some((modifiers, "")) => (modifiers.strip_suffix('+').unwrap_or(modifiers), "+"),
+            Some((modifiers, key)) => (modifiers, key),
+            None => ("", binding.as_str()),
+        };
+
+        let mut combo = KeyCombo {
+            key: key_alias(key).to_string(),
+            ctrl: false,
+            alt: false,
+            shift: false,
+            logo: false,
+        };
+        for modifier in modifiers.split('+').filter(|modifier| !modifier.is_empty()) {
+            match modifier {
+                "ctrl" | "control" => combo.ctrl = true,
+                "alt" => combo.alt = true,
+                "shift" => combo.shift = true,
+                "super" | "logo" | "cmd" => combo.logo = true,
+                _ => return Err(format!("unknown modifier {} in {}", modifier, binding)),
+            }
+        }
+        if combo.key.is_empty() {
+            return Err(format!("no key in {}", binding));
+        }
+        Ok(combo)
+    }
+
+    fn pressed(key: &Key, modifiers: Modifiers) -> Option<KeyCombo> {
+        let (key, shift) = match key {
+            Key::Named(named) => (format!("{:?}", named).to_lowercase(), modifiers.shift()),
+            Key::Character(character) => (character.to_lowercase(), false),
+            Key::Unidentified => return None,
+        };
+        Some(KeyCombo {
+            key,
+            ctrl: modifiers.control(),
+            alt: modifiers.alt(),
+            shift,
+            logo: modifiers.logo(),
+        })
+    }
+}
+
+/// Short names for keys whose iced names are long
+fn key_alias(key: &str) -> &str {
+    match key {
+        "left" => "arrowleft",
+        "right" => "arrowright",
+        "up" => "arrowup",
+        "down" => "arrowdown",
+        "esc" => "escape",
+        "return" => "enter",
+        "del" => "delete",
+        "plus" => "+",
+        key => key,
+    }
+}
+
+/// Maps pressed keys to actions. Built from the `[keymap]` config table, with defaults for
+/// actions it leaves out and an empty string to unbind one.
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    bindings: HashMap<KeyCombo, Action>,
+    help: Vec<(String, Action)>, // binding as configured, in the order of `Action::ALL`
+    pub errors: Vec<String>,     // unknown actions, bad bindings and conflicts
+}
+
+impl Keymap {
+    pub fn new(config: &BTreeMap<String, String>) -> Keymap {
+        let mut keymap = Keymap::default();
+
+        for name in config.keys() {
+            if !Action::ALL
+                .iter()
+                .any(|(_, action_name, ..)| action_name == name)
+            {
+                keymap
+                    .errors
+                    .push(format!("unknown shortcut action {}", name));
+            }
+        }
+
+        for (action, name, default, _) in Action::ALL {
+            let binding = config.get(name).map_or(default, String::as_str);
+            if binding.trim().is_empty() {
+                continue;
+            }
+            let combo = match KeyCombo::parse(binding) {
+                Ok(combo) => combo,
+                Err(e) => {
+                    keymap.errors.push(format!("{}: {}", name, e));
+                    continue;
+                }
+            };
+            if let Some(other) = keymap.bindings.get(&combo) {
+                keymap.errors.push(format!(
+                    "{} is bound to both {} and {}, only {} will be used",
+                    binding,
+                    other.name(),
+                    name,
+                    other.name()
+                ));
+                continue;
+            }
+            keymap.bindings.insert(combo, action);
+            keymap.help.push((binding.to_string(), action));
+        }
+
+        keymap
+    }
+
+    pub fn action(&self, key: &Key, modifiers: Modifiers) -> Option<Action> {
+        KeyCombo::pressed(key, modifiers).and_then(|combo| self.bindings.get(&combo).copied())
+    }
+
+    /// Bindings and what they do, for the help overlay
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        self.help
+            .iter()
+            .map(|(binding, action)| (binding.clone(), action.description()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iced::keyboard::key::Named;
+
+    fn keymap(config: &[(&str, &str)]) -> Keymap {
+        Keymap::new(
+            &config
+                .iter()
+                .map(|(name, key)| (name.to_string(), key.to_string()))
+                .collect(),
+        )
+    }
+
+    fn character(key: &str) -> Key {
+        Key::Character(key.into())
+    }
+
+    #[test]
+    fn defaults_cover_every_action() {
+        let keymap = Keymap::new(&default_keymap());
+        assert!(keymap.errors.is_empty(), "{:?}", keymap.errors);
+        assert_eq!(keymap.help().len(), Action::ALL.len());
+
+        let none = Modifiers::empty();
+        assert_eq!(
+            keymap.action(&Key::Named(Named::Space), none),
+            Some(Action::TogglePlayback)
+        );
+        assert_eq!(keymap.action(&character("N"), none), Some(Action::NextSong));
+        assert_eq!(
+            keymap.action(&Key::Named(Named::ArrowLeft), none),
+            Some(Action::SeekBackward)
+        );
+        assert_eq!(
+            keymap.action(&Key::Named(Named::ArrowLeft), Modifiers::ALT),
+            Some(Action::NavigateBack)
+        );
+        // typed characters already include shift
+        assert_eq!(
+            keymap.action(&character("?"), Modifiers::SHIFT),
+            Some(Action::ToggleShortcutHelp)
+        );
+        assert_eq!(keymap.action(&character("n"), Modifiers::CTRL), None);
+        assert_eq!(
+            keymap.action(&Key::Named(Named::ArrowUp), Modifiers::SHIFT),
+            None
+        );
+        assert_eq!(keymap.action(&Key::Unidentified, none), None);
+    }
+
+    #[test]
+    fn parses_bindings() {
+        let combo = KeyCombo::parse(" Ctrl+Shift+Left ").unwrap();
+        assert_eq!(combo.key, "arrowleft");
+        assert!(combo.ctrl && combo.shift && !combo.alt && !combo.logo);
+        assert_eq!(KeyCombo::parse("ctrl++").unwrap().key, "+");
+        assert!(KeyCombo::parse("ctrl++").unwrap().ctrl);
+        assert_eq!(KeyCombo::parse("+").unwrap().key, "+");
+        assert_eq!(
+            KeyCombo::parse("ctrl+").unwrap(),
+            KeyCombo::parse("ctrl++").unwrap()
+        );
+        assert_eq!(
+            KeyCombo::parse("super+plus").unwrap(),
+            KeyCombo::parse("cmd++").unwrap()
+        );
+
+        assert_eq!(
+            KeyCombo::parse("hyper+x").unwrap_err(),
+            "unknown modifier hyper in hyper+x"
+        );
+        assert_eq!(KeyCombo::parse("").unwrap_err(), "no key in ");
+    }
+
+    #[test]
+    fn reports_unknown_actions_bad_keys_and_conflicts() {
+        let keymap = keymap(&[
+            ("next_song", "ctrl+right"),
+            ("previous_song", ""),
+            ("seek_forward", "ctrl+Right"),
+            ("volume_up", "meta+up"),
+            ("play_everything", "x"),
+        ]);
+        assert_eq!(
+            keymap.errors,
+            [
+                "unknown shortcut action play_everything",
+                "ctrl+Right is bound to both next_song and seek_forward, only next_song will be used",
+                "volume_up: unknown modifier meta in meta+up",
+            ]
+        );
+
+        // the first binding wins, the others keep their defaults or stay unbound
+        let right = Key::Named(Named::ArrowRight);
+        assert_eq!(
+            keymap.action(&right, Modifiers::CTRL),
+            Some(Action::NextSong)
+        );
+        assert_eq!(keymap.action(&right, Modifiers::empty()), None);
+        assert_eq!(keymap.action(&character("n"), Modifiers::empty()), None);
+        assert_eq!(keymap.action(&character("p"), Modifiers::empty()), None);
+        assert_eq!(
+            keymap.action(&Key::Named(Named::ArrowUp), Modifiers::empty()),
+            None
+        );
+        assert_eq!(
+            keymap.action(&Key::Named(Named::ArrowDown), Modifiers::empty()),
+            Some(Action::VolumeDown)
+        );
+        assert!(!keymap
+            .help()
+            .iter()
+            .any(|(_, description)| *description == "previous song"));
+    }
+}
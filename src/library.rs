@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use lofty::{
     file::{AudioFile, TaggedFileExt},
     probe::Probe,
@@ -7,17 +7,42 @@ use lofty::{
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::{
-    collections::HashMap,
-    fs::File,
+    collections::{HashMap, HashSet},
+    fs::{self, File},
     io::{Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::Duration,
 };
 use uuid::Uuid;
 use walkdir::WalkDir;
 
+pub use browse::AlbumSummary;
+pub use cover::cover_art;
+use cue::CueSheet;
+pub use history::{append_play, read_history, unix_now, Play};
+pub use playlist::Playlist;
+pub use playlist_file::write_playlist;
+pub use search::{SearchIndex, SearchQuery};
+pub use smart_playlist::{SmartPlaylist, SmartQuery, SongField};
+pub use stats::{current_year, LibraryComposition, ListeningStats, Ranked, StatsPeriod};
+
+mod browse;
+mod cover;
+mod cue;
+mod history;
+mod playlist;
+mod playlist_file;
+mod search;
+mod smart_playlist;
+mod stats;
+
+/// File extensions that are imported as songs
+pub const AUDIO_EXTENSIONS: [&str; 5] = ["flac", "ogg", "mp3", "wav", "acc"];
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Song {
+    #[serde(default)]
+    pub id: Uuid, // mirrors the key in `Library.songs`, nil for songs outside the library
     pub title: String,
     // pub artist: String, // TODO refer to actual artists (and deal with multiple)
     pub artists: Vec<Artist>,
@@ -26,6 +51,45 @@ pub struct Song {
     pub file_path: PathBuf,
     pub year: u16,
     pub genre: String,
+    #[serde(default)]
+    pub bookmarks: Vec<Bookmark>, // kept sorted by position
+    #[serde(default)]
+    pub remember_position: Option<bool>, // `None` lets the player decide from duration and genre
+    #[serde(default)]
+    pub resume_position: Option<Duration>,
+    #[serde(default)]
+    pub cue_range: Option<CueRange>, // set for virtual tracks from a cue sheet
+    #[serde(default)]
+    pub date_added: u64, // unix seconds of the first scan that found the song, 0 if unknown
+    #[serde(default)]
+    pub rating: Option<u8>, // 1 to 5 stars
+    #[serde(default)]
+    pub play_count: u32,
+    #[serde(default)]
+    pub last_played: u64, // unix seconds of the last play to the end, 0 if never
+    #[serde(default)]
+    pub skip_count: u32,
+    #[serde(default)]
+    pub track_number: Option<u32>,
+    #[serde(default)]
+    pub disc_number: Option<u32>,
+    #[serde(default)]
+    pub bitrate: Option<u32>, // audio bitrate in kbps
+    #[serde(default)]
+    pub file_size: u64, // bytes, of the whole file for cue sheet tracks
+}
+
+/// Where a cue sheet track sits inside its (shared) audio file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CueRange {
+    pub start: Duration,
+    pub end: Option<Duration>, // `None` plays until the end of the file
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub position: Duration,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -52,6 +116,12 @@ pub struct Library {
     pub songs: HashMap<Uuid, Song>,
     pub albums: HashMap<Uuid, Album>,
     pub artists: HashMap<Uuid, Artist>,
+    #[serde(default)]
+    pub playlists: Vec<Playlist>,
+    #[serde(default)]
+    pub smart_playlists: Vec<SmartPlaylist>,
+    #[serde(skip)]
+    pub search_index: SearchIndex,
 }
 
 impl Song {
@@ -60,6 +130,8 @@ impl Song {
             Ok(tagged_file) => {
                 match tagged_file.read() {
                     Ok(tagged_file) => {
+                        let bitrate = tagged_file.properties().audio_bitrate();
+                        let file_size = fs::metadata(&file_path).map_or(0, |meta| meta.len());
                         match tagged_file
                             .primary_tag()
                             .or_else(|| tagged_file.first_tag())
@@ -71,6 +143,8 @@ impl Song {
                                 let tag_artist =
                                     tag.artist().unwrap_or(unknown_tag.clone()).to_string();
                                 let tag_year = tag.year().unwrap_or(0) as u16;
+                                let tag_track_number = tag.track();
+                                let tag_disc_number = tag.disk();
                                 let tag_genre =
                                     tag.genre().unwrap_or(unknown_tag.clone()).to_string();
                                 let tag_duration = tagged_file.properties().duration();
@@ -83,6 +157,7 @@ impl Song {
                                 );
 
                                 Song {
+                                    id: Uuid::nil(),
                                     title: tag_title,
                                     album,
                                     artists: vec![artist],
@@ -90,12 +165,36 @@ impl Song {
                                     file_path,
                                     year: tag_year,
                                     genre: tag_genre,
+                                    bookmarks: Vec::new(),
+                                    remember_position: None,
+                                    resume_position: None,
+                                    cue_range: None,
+                                    date_added: 0,
+                                    rating: None,
+                                    play_count: 0,
+                                    last_played: 0,
+                                    skip_count: 0,
+                                    track_number: tag_track_number,
+                                    disc_number: tag_disc_number,
+                                    bitrate,
+                                    file_size,
                                 }
                             }
                             None => {
-                                // TODO handle valid songs that have no tags
-                                // TODO this is a shitty hack, properly deal with errors
-                                Song::default()
+                                // untagged files are named after the file
+                                let title = file_path.file_stem().map_or(String::new(), |stem| {
+                                    stem.to_string_lossy().to_string()
+                                });
+                                Song {
+                                    title,
+                                    artists: vec![Artist::new(String::from("Unknown"))],
+                                    duration: tagged_file.properties().duration(),
+                                    genre: String::from("Unknown"),
+                                    file_path,
+                                    bitrate,
+                                    file_size,
+                                    ..Song::default()
+                                }
                             }
                         }
                     }
@@ -113,35 +212,82 @@ impl Song {
             }
         }
     }
+
+    /// Tells songs apart, including the ones outside the library that all have the nil id: those
+    /// get an id derived from their file and start offset
+    pub fn key(&self) -> Uuid {
+        if !self.id.is_nil() {
+            return self.id;
+        }
+        let name = format!(
+            "{}#{}",
+            self.file_path.display(),
+            self.start_offset().as_millis()
+        );
+        Uuid::new_v5(&Uuid::NAMESPACE_URL, name.as_bytes())
+    }
+
+    /// Offset of the song within its file, non-zero only for cue sheet tracks
+    pub fn start_offset(&self) -> Duration {
+        self.cue_range.map_or(Duration::ZERO, |range| range.start)
+    }
+
+    pub fn same_album_as(&self, other: &Song) -> bool {
+        match (&self.album, &other.album) {
+            (Some(album), Some(other_album)) => album.id == other_album.id,
+            _ => false,
+        }
+    }
+
+    pub fn add_bookmark(&mut self, bookmark: Bookmark) {
+        let index = self
+            .bookmarks
+            .partition_point(|existing| existing.position <= bookmark.position);
+        self.bookmarks.insert(index, bookmark);
+    }
 }
 
 impl Album {
     pub fn new(title: Option<String>, artist: Artist) -> Option<Self> {
-        match title {
-            Some(title) => Some(Album {
-                id: Uuid::new_v4(),
-                title,
-                artist,
-            }),
-            None => None,
-        }
+        title.map(|title| Album {
+            id: Album::id_for(&title, &artist.name),
+            title,
+            artist,
+        })
+    }
+
+    pub fn id_for(title: &str, artist_name: &str) -> Uuid {
+        name_id(&["album", title, artist_name])
     }
 
     pub fn try_to_get_title(maybe_album: Option<Cow<str>>) -> Option<String> {
-        match maybe_album {
-            Some(title) => Some(title.to_string()),
-            None => None,
-        }
+        maybe_album.map(|title| title.to_string())
     }
 }
 
 impl Artist {
     pub fn new(name: String) -> Self {
         Artist {
-            id: Uuid::new_v4(),
+            id: Artist::id_for(&name),
             name,
         }
     }
+
+    pub fn id_for(name: &str) -> Uuid {
+        name_id(&["artist", name])
+    }
+}
+
+/// Albums and artists are identified by their names, so all songs of an album share its id,
+/// and it stays the same between runs and builds
+fn name_id(names: &[&str]) -> Uuid {
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, names.join("\0").as_bytes())
+}
+
+/// Whether `id` came from `name_id`, rather than being one of the random ids of libraries
+/// saved before ids were name based
+fn is_name_id(id: Uuid) -> bool {
+    id.get_version_num() == 5
 }
 
 impl Library {
@@ -150,13 +296,99 @@ impl Library {
             songs: HashMap::new(),
             albums: HashMap::new(),
             artists: HashMap::new(),
+            playlists: Vec::new(),
+            smart_playlists: Vec::new(),
+            search_index: SearchIndex::default(),
         }
     }
 
-    fn add_song(&mut self, song: Song) -> Result<()> {
+    fn add_song(&mut self, mut song: Song) -> Result<Uuid> {
         // TODO check for duplicates (by name, possibly album, and artist)
-        self.songs.insert(Uuid::new_v4(), song);
+        song.id = Uuid::new_v4();
+        song.date_added = unix_now();
+        let id = song.id;
+        self.songs.insert(song.id, song);
+
+        Ok(id)
+    }
 
+    /// Adds songs from outside the music folder, e.g. opened from the command line, and gives
+    /// them their library ids
+    pub fn add_songs(&mut self, songs: &mut [Song]) -> Result<()> {
+        for song in songs.iter_mut().filter(|song| song.id.is_nil()) {
+            let id = self.add_song(song.clone())?;
+            song.clone_from(&self.songs[&id]);
+        }
+        self.index_albums_and_artists();
+        self.refresh_smart_playlists();
+        self.rebuild_search_index();
+        Ok(())
+    }
+
+    /// Keeps the ids and user data (bookmarks etc.) of songs that were already in `previous`,
+    /// matched by file path (and cue offset), so a rescan does not throw them away.
+    /// Playlists refer to songs by id, so they stay valid as well.
+    pub fn carry_over_user_data(&mut self, previous: &Library) {
+        self.playlists = previous.playlists.clone();
+        self.smart_playlists = previous.smart_playlists.clone();
+
+        let previous_songs: HashMap<(&PathBuf, Duration), &Song> = previous
+            .songs
+            .values()
+            .map(|song| ((&song.file_path, song.start_offset()), song))
+            .collect();
+
+        let scanned_songs = std::mem::take(&mut self.songs);
+        for (_, mut song) in scanned_songs {
+            if let Some(previous_song) = previous_songs.get(&(&song.file_path, song.start_offset()))
+            {
+                song.id = previous_song.id;
+                song.bookmarks = previous_song.bookmarks.clone();
+                song.remember_position = previous_song.remember_position;
+                song.resume_position = previous_song.resume_position;
+                song.date_added = previous_song.date_added;
+                song.rating = previous_song.rating;
+                song.play_count = previous_song.play_count;
+                song.last_played = previous_song.last_played;
+                song.skip_count = previous_song.skip_count;
+            }
+            self.songs.insert(song.id, song);
+        }
+    }
+
+    /// Scans `folder_path` again and replaces the songs with what is there now, keeping the
+    /// user data of the songs that are still there
+    pub fn rescan(&mut self, folder_path: &str) -> Result<()> {
+        let scanned = Library::scan(folder_path)?;
+        self.apply_scan(scanned, folder_path)
+    }
+
+    /// The songs in `folder_path`, for `apply_scan`. Takes a while, and needs no library to
+    /// be locked meanwhile.
+    pub fn scan(folder_path: &str) -> Result<Library> {
+        let mut scanned = Library::new();
+        scanned.import_dir(folder_path)?;
+        Ok(scanned)
+    }
+
+    /// Replaces the songs with the ones `scan` found in `folder_path`, keeping the user data
+    /// of the songs that are still there
+    pub fn apply_scan(&mut self, mut scanned: Library, folder_path: &str) -> Result<()> {
+        scanned.carry_over_user_data(self);
+        // songs added from elsewhere stay for as long as their files do
+        let folder = fs::canonicalize(folder_path)
+            .map_err(|e| anyhow!("cannot scan {}: {}", folder_path, e))?;
+        for song in self.songs.values() {
+            let outside = fs::canonicalize(&song.file_path)
+                .is_ok_and(|file_path| !file_path.starts_with(&folder));
+            if outside {
+                scanned.songs.entry(song.id).or_insert_with(|| song.clone());
+            }
+        }
+        scanned.index_albums_and_artists();
+        scanned.refresh_smart_playlists();
+        scanned.rebuild_search_index();
+        *self = scanned;
         Ok(())
     }
 
@@ -181,25 +413,21 @@ impl Library {
     // }
 
     pub fn import_dir(&mut self, folder_path: &str) -> Result<()> {
+        let mut audio_files = Vec::new();
+        let mut cue_files = Vec::new();
+
         for entry in WalkDir::new(folder_path) {
             // TODO check for existing dupes based on filepath, duration, other tags, and ideally AcoustID but I have *no* clue how to implement that.
             match entry {
                 Ok(file) => {
                     // println!("entry: {:?}", file);
                     if file.file_type().is_file() {
-                        match file.clone().into_path().extension() {
-                            Some(extension) => {
-                                if extension == "flac"
-                                    || extension == "ogg"
-                                    || extension == "mp3"
-                                    || extension == "wav"
-                                    || extension == "acc"
-                                {
-                                    println!("ADDING SONG: {:?}", file.clone().file_name());
-                                    self.add_song(Song::new(file.into_path()))?;
-                                }
+                        if let Some(extension) = file.path().extension() {
+                            if extension == "cue" {
+                                cue_files.push(file.into_path());
+                            } else if AUDIO_EXTENSIONS.iter().any(|audio| extension == *audio) {
+                                audio_files.push(file.into_path());
                             }
-                            None => (),
                         }
                     }
                 }
@@ -207,9 +435,53 @@ impl Library {
             }
         }
 
+        // files described by a cue sheet are imported as their individual tracks instead
+        let mut covered_files = HashSet::new();
+        for cue_path in cue_files {
+            match CueSheet::read(&cue_path) {
+                Ok(sheet) => {
+                    let (songs, files) = sheet.songs(&cue_path);
+                    for song in songs {
+                        println!("ADDING CUE TRACK: {:?}", song.title);
+                        self.add_song(song)?;
+                    }
+                    covered_files.extend(files);
+                }
+                Err(e) => println!("Reading cue sheet {:?} failed: {}", cue_path, e),
+            }
+        }
+
+        for file_path in audio_files {
+            if !covered_files.contains(&file_path) {
+                println!("ADDING SONG: {:?}", file_path.file_name());
+                self.add_song(Song::new(file_path))?;
+            }
+        }
+
         Ok(())
     }
 
+    /// The songs in `path`, a file or a folder, in file order. They are read from their tags
+    /// like a scan would, but not added to the library.
+    pub fn read_songs(path: &Path) -> Result<Vec<Song>> {
+        let mut scanned = Library::new();
+        scanned.import_dir(&path.to_string_lossy())?;
+        let mut songs: Vec<Song> = scanned
+            .songs
+            .into_values()
+            // files that could not be read come back without a path
+            .filter(|song| !song.file_path.as_os_str().is_empty())
+            .collect();
+        for song in songs.iter_mut() {
+            song.id = Uuid::nil();
+            song.date_added = 0;
+        }
+        songs.sort_by(|a, b| {
+            (&a.file_path, a.start_offset()).cmp(&(&b.file_path, b.start_offset()))
+        });
+        Ok(songs)
+    }
+
     pub fn save_to_file(&self, file_path: &str) -> Result<()> {
         let toml = toml::to_string(self)?;
         let mut file = File::create(file_path)?;
@@ -221,7 +493,90 @@ impl Library {
         let mut file = File::open(file_path)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
-        let library: Library = toml::from_str(&contents)?;
+        let mut library: Library = toml::from_str(&contents)?;
+        // libraries saved before songs stored their own id
+        for (id, song) in library.songs.iter_mut() {
+            song.id = *id;
+        }
+        library.index_albums_and_artists();
+        library.refresh_smart_playlists();
+        library.rebuild_search_index();
         Ok(library)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn album_and_artist_ids_come_from_their_names() {
+        assert_eq!(
+            Album::id_for("Blue", "Joni Mitchell"),
+            Album::id_for("Blue", "Joni Mitchell")
+        );
+        assert_ne!(
+            Album::id_for("Blue", "Joni Mitchell"),
+            Album::id_for("Blue", "Weezer")
+        );
+        // the parts of the name do not run into each other
+        assert_ne!(Album::id_for("ab", "c"), Album::id_for("a", "bc"));
+        assert_ne!(Artist::id_for("Blue"), Album::id_for("Blue", ""));
+        assert_eq!(Artist::id_for("Low").get_version_num(), 5);
+    }
+
+    fn song(title: &str, album: &str, artist: &str) -> Song {
+        let artist = Artist::new(artist.to_string());
+        Song {
+            id: Uuid::new_v4(),
+            title: title.to_string(),
+            artists: vec![artist.clone()],
+            album: Album::new(Some(album.to_string()), artist),
+            ..Song::default()
+        }
+    }
+
+    #[test]
+    fn compares_albums_by_id() {
+        let closer = song("Closer", "Things We Lost", "Low");
+        let embrace = song("Embrace", "Things We Lost", "Low");
+        assert!(closer.same_album_as(&embrace));
+        assert!(!closer.same_album_as(&song("Closer", "Things We Lost", "Mimi Parker")));
+        assert!(!closer.same_album_as(&song("Closer", "Secret Name", "Low")));
+        let single = Song {
+            album: None,
+            ..closer.clone()
+        };
+        assert!(!single.same_album_as(&single));
+    }
+
+    #[test]
+    fn keeps_saved_ids_and_replaces_random_ones() {
+        let mut library = Library::new();
+        let saved = song("Closer", "Things We Lost", "Low");
+        // as scanned before ids were name based
+        let mut old = song("Embrace", "Things We Lost", "Low");
+        old.artists[0].id = Uuid::new_v4();
+        let album = old.album.as_mut().unwrap();
+        album.id = Uuid::new_v4();
+        album.artist.id = Uuid::new_v4();
+        let (saved_id, old_id) = (saved.id, old.id);
+        library.songs.insert(saved_id, saved);
+        library.songs.insert(old_id, old);
+
+        library.index_albums_and_artists();
+        let album_id = Album::id_for("Things We Lost", "Low");
+        for id in [saved_id, old_id] {
+            let song = &library.songs[&id];
+            assert_eq!(song.album.as_ref().unwrap().id, album_id);
+            assert_eq!(
+                song.album.as_ref().unwrap().artist.id,
+                Artist::id_for("Low")
+            );
+            assert_eq!(song.artists[0].id, Artist::id_for("Low"));
+        }
+        assert!(library.songs[&saved_id].same_album_as(&library.songs[&old_id]));
+        assert_eq!(library.albums.len(), 1);
+        assert_eq!(library.artists.len(), 1);
+    }
+}
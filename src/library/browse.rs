@@ -0,0 +1,189 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use uuid::Uuid;
+
+use super::{is_name_id, Album, Artist, Library, Song};
+
+/// An album as listed on the artist and genre pages
+#[derive(Debug, Clone)]
+pub struct AlbumSummary {
+    pub album: Album,
+    pub year: u16,
+    pub song_count: usize,
+    pub duration: Duration,
+}
+
+impl Song {
+    fn is_by(&self, artist_id: Uuid) -> bool {
+        self.artists.iter().any(|artist| artist.id == artist_id)
+            || self
+                .album
+                .as_ref()
+                .is_some_and(|album| album.artist.id == artist_id)
+    }
+
+    /// Disc, then track number, then position in the file (cue sheets), then title
+    fn album_order(&self, other: &Song) -> Ordering {
+        self.disc_number
+            .cmp(&other.disc_number)
+            .then_with(|| self.track_number.cmp(&other.track_number))
+            .then_with(|| self.start_offset().cmp(&other.start_offset()))
+            .then_with(|| self.title.cmp(&other.title))
+    }
+}
+
+impl Library {
+    /// Collects every song's artists and albums into `artists` and `albums`. Songs keep the
+    /// name based ids they were scanned with, only libraries saved before ids were name based
+    /// have random ones, which are replaced.
+    pub fn index_albums_and_artists(&mut self) {
+        self.artists.clear();
+        self.albums.clear();
+
+        for song in self.songs.values_mut() {
+            for artist in song.artists.iter_mut() {
+                if !is_name_id(artist.id) {
+                    artist.id = Artist::id_for(&artist.name);
+                }
+                self.artists.insert(artist.id, artist.clone());
+            }
+            if let Some(album) = song.album.as_mut() {
+                if !is_name_id(album.artist.id) {
+                    album.artist.id = Artist::id_for(&album.artist.name);
+                }
+                if !is_name_id(album.id) {
+                    album.id = Album::id_for(&album.title, &album.artist.name);
+                }
+                self.artists.insert(album.artist.id, album.artist.clone());
+                self.albums.insert(album.id, album.clone());
+            }
+        }
+    }
+
+    /// All artists by name, with the number of albums they appear on
+    pub fn artists_with_album_counts(&self) -> Vec<(Artist, usize)> {
+        let mut artist_albums: HashMap<Uuid, HashSet<Uuid>> = HashMap::new();
+        for song in self.songs.values() {
+            let Some(album) = &song.album else {
+                continue;
+            };
+            for artist in song.artists.iter().chain([&album.artist]) {
+                artist_albums.entry(artist.id).or_default().insert(album.id);
+            }
+        }
+
+        let mut artists: Vec<(Artist, usize)> = self
+            .artists
+            .values()
+            .map(|artist| {
+                let album_count = artist_albums.get(&artist.id).map_or(0, HashSet::len);
+                (artist.clone(), album_count)
+            })
+            .collect();
+        artists.sort_by_cached_key(|(artist, _)| artist.name.to_lowercase());
+        artists
+    }
+
+    /// Every album, oldest first
+    pub fn all_albums(&self) -> Vec<AlbumSummary> {
+        self.album_summaries(|_| true)
+    }
+
+    /// Albums the artist made or appears on, oldest first
+    pub fn artist_albums(&self, artist_id: Uuid) -> Vec<AlbumSummary> {
+        self.album_summaries(|song| song.is_by(artist_id))
+    }
+
+    /// The artist's songs that are not on any album
+    pub fn artist_singles(&self, artist_id: Uuid) -> Vec<Song> {
+        let mut songs: Vec<Song> = self
+            .songs
+            .values()
+            .filter(|song| song.album.is_none() && song.is_by(artist_id))
+            .cloned()
+            .collect();
+        songs.sort_by_cached_key(|song| song.title.to_lowercase());
+        songs
+    }
+
+    /// The album's songs in disc and track order
+    pub fn album_songs(&self, album_id: Uuid) -> Vec<Song> {
+        let mut songs: Vec<Song> = self
+            .songs
+            .values()
+            .filter(|song| {
+                song.album
+                    .as_ref()
+                    .is_some_and(|album| album.id == album_id)
+            })
+            .cloned()
+            .collect();
+        songs.sort_by(Song::album_order);
+        songs
+    }
+
+    /// All genres by name, with their number of songs
+    pub fn genres(&self) -> Vec<(String, usize)> {
+        let mut genres: HashMap<&str, usize> = HashMap::new();
+        for song in self.songs.values() {
+            *genres.entry(&song.genre).or_default() += 1;
+        }
+
+        let mut genres: Vec<(String, usize)> = genres
+            .into_iter()
+            .map(|(genre, count)| (genre.to_string(), count))
+            .collect();
+        genres.sort_by_cached_key(|(genre, _)| genre.to_lowercase());
+        genres
+    }
+
+    /// Albums with at least one song of the genre, oldest first
+    pub fn genre_albums(&self, genre: &str) -> Vec<AlbumSummary> {
+        self.album_summaries(|song| song.genre == genre)
+    }
+
+    /// The genre's songs grouped by album, in track order
+    pub fn genre_songs(&self, genre: &str) -> Vec<Song> {
+        let mut songs: Vec<Song> = self
+            .songs
+            .values()
+            .filter(|song| song.genre == genre)
+            .cloned()
+            .collect();
+        songs.sort_by(|a, b| {
+            let a_album = a.album.as_ref().map(|album| &album.title);
+            let b_album = b.album.as_ref().map(|album| &album.title);
+            a_album.cmp(&b_album).then_with(|| a.album_order(b))
+        });
+        songs
+    }
+
+    fn album_summaries(&self, include: impl Fn(&Song) -> bool) -> Vec<AlbumSummary> {
+        let mut summaries: HashMap<Uuid, AlbumSummary> = HashMap::new();
+        for song in self.songs.values().filter(|song| include(song)) {
+            let Some(album) = &song.album else {
+                continue;
+            };
+            let summary = summaries.entry(album.id).or_insert_with(|| AlbumSummary {
+                album: album.clone(),
+                year: song.year,
+                song_count: 0,
+                duration: Duration::ZERO,
+            });
+            summary.year = summary.year.max(song.year);
+            summary.song_count += 1;
+            summary.duration += song.duration;
+        }
+
+        let mut summaries: Vec<AlbumSummary> = summaries.into_values().collect();
+        summaries.sort_by(|a, b| {
+            a.year
+                .cmp(&b.year)
+                .then_with(|| a.album.title.cmp(&b.album.title))
+        });
+        summaries
+    }
+}
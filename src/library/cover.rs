@@ -0,0 +1,46 @@
+use lofty::{file::TaggedFileExt, picture::PictureType, probe::Probe};
+use std::{fs, path::Path};
+
+use super::Song;
+
+/// Image files next to the audio files that are used when there is no embedded cover
+const COVER_FILE_NAMES: [&str; 4] = ["cover", "folder", "front", "album"];
+const COVER_FILE_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+/// The song's embedded front cover, or else a cover image in its folder
+pub fn cover_art(song: &Song) -> Option<Vec<u8>> {
+    embedded_cover(&song.file_path).or_else(|| folder_cover(&song.file_path))
+}
+
+fn embedded_cover(file_path: &Path) -> Option<Vec<u8>> {
+    let tagged_file = Probe::open(file_path).ok()?.read().ok()?;
+    let pictures: Vec<_> = tagged_file
+        .tags()
+        .iter()
+        .flat_map(|tag| tag.pictures())
+        .collect();
+
+    pictures
+        .iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or(pictures.first())
+        .map(|picture| picture.data().to_vec())
+}
+
+fn folder_cover(file_path: &Path) -> Option<Vec<u8>> {
+    let is_cover = |path: &Path| {
+        let matches = |part: Option<&std::ffi::OsStr>, names: &[&str]| {
+            part.and_then(|part| part.to_str())
+                .is_some_and(|part| names.iter().any(|name| part.eq_ignore_ascii_case(name)))
+        };
+        matches(path.file_stem(), &COVER_FILE_NAMES)
+            && matches(path.extension(), &COVER_FILE_EXTENSIONS)
+    };
+
+    fs::read_dir(file_path.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| is_cover(path))
+        .and_then(|path| fs::read(path).ok())
+}
@@ -0,0 +1,328 @@
+use anyhow::{anyhow, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use super::{Album, Artist, CueRange, Song, AUDIO_EXTENSIONS};
+
+/// Cue sheet timestamps are `mm:ss:ff`, with 75 frames per second
+const FRAMES_PER_SECOND: u64 = 75;
+
+#[derive(Debug, Default, Clone)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u16>,
+    pub files: Vec<CueFile>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CueFile {
+    pub path: PathBuf, // as written in the sheet, usually relative to it
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub start: Duration, // INDEX 01
+}
+
+impl CueSheet {
+    pub fn read(cue_path: &Path) -> Result<CueSheet> {
+        // cue sheets are frequently not UTF-8, so don't fail on stray Latin-1 bytes
+        let bytes = fs::read(cue_path)?;
+        CueSheet::parse(&String::from_utf8_lossy(&bytes))
+    }
+
+    pub fn parse(contents: &str) -> Result<CueSheet> {
+        let mut sheet = CueSheet::default();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let fields = split_fields(line.trim_start_matches('\u{feff}'));
+            let Some(command) = fields.first() else {
+                continue;
+            };
+
+            match (command.to_ascii_uppercase().as_str(), &fields[1..]) {
+                ("FILE", [path, ..]) => sheet.files.push(CueFile {
+                    path: PathBuf::from(path),
+                    tracks: Vec::new(),
+                }),
+                ("TRACK", [number, ..]) => {
+                    let file = sheet.files.last_mut().ok_or_else(|| {
+                        anyhow!("line {}: TRACK before any FILE", line_number + 1)
+                    })?;
+                    file.tracks.push(CueTrack {
+                        number: number.parse()?,
+                        ..CueTrack::default()
+                    });
+                }
+                ("INDEX", [number, time]) if number.parse::<u32>() == Ok(1) => {
+                    let track = sheet.current_track().ok_or_else(|| {
+                        anyhow!("line {}: INDEX outside of a TRACK", line_number + 1)
+                    })?;
+                    track.start = parse_time(time)
+                        .ok_or_else(|| anyhow!("line {}: bad time {}", line_number + 1, time))?;
+                }
+                ("TITLE", [title, ..]) => match sheet.current_track() {
+                    Some(track) => track.title = Some(title.clone()),
+                    None => sheet.title = Some(title.clone()),
+                },
+                ("PERFORMER", [performer, ..]) => match sheet.current_track() {
+                    Some(track) => track.performer = Some(performer.clone()),
+                    None => sheet.performer = Some(performer.clone()),
+                },
+                ("REM", [key, value, ..]) => match key.to_ascii_uppercase().as_str() {
+                    "GENRE" => sheet.genre = Some(value.clone()),
+                    "DATE" => sheet.year = value.get(..4).and_then(|year| year.parse().ok()),
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+
+        Ok(sheet)
+    }
+
+    fn current_track(&mut self) -> Option<&mut CueTrack> {
+        self.files
+            .last_mut()
+            .and_then(|file| file.tracks.last_mut())
+    }
+
+    /// Builds one song per track, reading the remaining tags from the underlying audio files.
+    /// Returns the songs together with the audio files they cover.
+    pub fn songs(&self, cue_path: &Path) -> (Vec<Song>, Vec<PathBuf>) {
+        let cue_dir = cue_path.parent().unwrap_or(Path::new(""));
+        let mut songs = Vec::new();
+        let mut covered_files = Vec::new();
+
+        for file in &self.files {
+            let Some(audio_path) = resolve_audio_file(cue_dir, &file.path) else {
+                println!(
+                    "cue sheet {:?} refers to missing file {:?}",
+                    cue_path, file.path
+                );
+                continue;
+            };
+            let file_song = Song::new(audio_path.clone());
+
+            for (index, track) in file.tracks.iter().enumerate() {
+                let end = file.tracks.get(index + 1).map(|next| next.start);
+                songs.push(self.track_song(&file_song, track, end));
+            }
+            covered_files.push(audio_path);
+        }
+
+        (songs, covered_files)
+    }
+
+    fn track_song(&self, file_song: &Song, track: &CueTrack, end: Option<Duration>) -> Song {
+        let artist = match track.performer.as_ref().or(self.performer.as_ref()) {
+            Some(name) => Artist::new(name.clone()),
+            None => file_song.artists.first().cloned().unwrap_or_default(),
+        };
+        let album_artist = match &self.performer {
+            Some(name) => Artist::new(name.clone()),
+            None => artist.clone(),
+        };
+        let album = match &self.title {
+            Some(title) => Album::new(Some(title.clone()), album_artist),
+            None => file_song.album.clone(),
+        };
+
+        Song {
+            title: track
+                .title
+                .clone()
+                .unwrap_or_else(|| format!("Track {:02}", track.number)),
+            artists: vec![artist],
+            duration: end
+                .unwrap_or(file_song.duration)
+                .saturating_sub(track.start),
+            album,
+            year: self.year.unwrap_or(file_song.year),
+            genre: self.genre.clone().unwrap_or(file_song.genre.clone()),
+            cue_range: Some(CueRange {
+                start: track.start,
+                end,
+            }),
+            track_number: Some(track.number),
+            ..file_song.clone()
+        }
+    }
+}
+
+/// Splits a cue line into whitespace separated fields, keeping "quoted strings" together
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            fields.push(chars.by_ref().take_while(|&c| c != '"').collect());
+        } else {
+            let mut field = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                field.push(c);
+            }
+            fields.push(field);
+        }
+    }
+
+    fields
+}
+
+fn parse_time(time: &str) -> Option<Duration> {
+    let mut parts = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    let millis = (minutes * 60 + seconds) * 1000 + frames * 1000 / FRAMES_PER_SECOND;
+    Some(Duration::from_millis(millis))
+}
+
+/// Rips are often re-encoded without updating the sheet ("album.wav" next to "album.flac"),
+/// so fall back to a file with the same name and any supported audio extension
+fn resolve_audio_file(cue_dir: &Path, file: &Path) -> Option<PathBuf> {
+    let path = cue_dir.join(file);
+    if path.is_file() {
+        return Some(path);
+    }
+
+    AUDIO_EXTENSIONS
+        .iter()
+        .map(|extension| path.with_extension(extension))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use uuid::Uuid;
+
+    const SHEET: &str = "\u{feff}REM GENRE \"Slowcore\"
+REM DATE 2001-02-03
+PERFORMER \"Low\"
+TITLE \"Things We Lost in the Fire\"
+FILE \"Things We Lost.flac\" WAVE
+  TRACK 01 AUDIO
+    TITLE \"Sunflower\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE \"Whitetail\"
+    PERFORMER \"Alan Sparhawk\"
+    INDEX 00 04:20:00
+    INDEX 01 04:22:37
+FILE missing.flac WAVE
+  TRACK 03 AUDIO
+    INDEX 01 00:00:74
+";
+
+    #[test]
+    fn parses_sheets() {
+        let sheet = CueSheet::parse(SHEET).unwrap();
+        assert_eq!(sheet.title.as_deref(), Some("Things We Lost in the Fire"));
+        assert_eq!(sheet.performer.as_deref(), Some("Low"));
+        assert_eq!(sheet.genre.as_deref(), Some("Slowcore"));
+        assert_eq!(sheet.year, Some(2001));
+
+        assert_eq!(sheet.files.len(), 2);
+        assert_eq!(sheet.files[0].path, Path::new("Things We Lost.flac"));
+        assert_eq!(sheet.files[1].path, Path::new("missing.flac"));
+        let tracks = &sheet.files[0].tracks;
+        assert_eq!(tracks[0].title.as_deref(), Some("Sunflower"));
+        assert_eq!(tracks[0].performer, None);
+        assert_eq!(tracks[1].number, 2);
+        assert_eq!(tracks[1].performer.as_deref(), Some("Alan Sparhawk"));
+        // INDEX 01 starts the track, frames are 1/75 s
+        assert_eq!(tracks[1].start, Duration::from_millis(262_493));
+        assert_eq!(sheet.files[1].tracks[0].start, Duration::from_millis(986));
+    }
+
+    #[test]
+    fn rejects_broken_sheets() {
+        let error = |contents| CueSheet::parse(contents).unwrap_err().to_string();
+        assert_eq!(
+            error("TITLE x\nTRACK 01 AUDIO"),
+            "line 2: TRACK before any FILE"
+        );
+        assert_eq!(
+            error("INDEX 01 00:00:00"),
+            "line 1: INDEX outside of a TRACK"
+        );
+        assert_eq!(
+            error("FILE a.wav WAVE\nTRACK 01 AUDIO\nINDEX 01 00:xx:00"),
+            "line 3: bad time 00:xx:00"
+        );
+        assert!(CueSheet::parse("FILE a.wav WAVE\nTRACK one AUDIO").is_err());
+        // only the start of the track matters, other indexes may appear anywhere
+        assert!(CueSheet::parse("INDEX 00 00:00:00").is_ok());
+    }
+
+    #[test]
+    fn splits_fields_and_times() {
+        assert_eq!(
+            split_fields("  FILE \"My Album.flac\"  WAVE "),
+            ["FILE", "My Album.flac", "WAVE"]
+        );
+        assert_eq!(
+            split_fields("TITLE \"unterminated"),
+            ["TITLE", "unterminated"]
+        );
+        assert!(split_fields("   ").is_empty());
+
+        assert_eq!(parse_time("01:02:00"), Some(Duration::from_secs(62)));
+        assert_eq!(parse_time("90:00:75"), Some(Duration::from_secs(5401)));
+        assert_eq!(parse_time("01:02"), None);
+        assert_eq!(parse_time("-1:00:00"), None);
+    }
+
+    #[test]
+    fn builds_songs_for_the_files_that_exist() {
+        let dir = env::temp_dir().join(format!("jukebox-cue-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        // five minutes of 8-bit silence at 1 kHz, converted since the sheet was written
+        let samples = 5 * 60 * 1000u32;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + samples).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        for field in [16u32, 0x0001_0001, 1000, 1000, 0x0008_0001] {
+            wav.extend_from_slice(&field.to_le_bytes());
+        }
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&samples.to_le_bytes());
+        wav.resize(wav.len() + samples as usize, 0x80);
+        fs::write(dir.join("Things We Lost.wav"), wav).unwrap();
+        let cue_path = dir.join("album.cue");
+
+        let (songs, files) = CueSheet::parse(SHEET).unwrap().songs(&cue_path);
+        assert_eq!(files, [dir.join("Things We Lost.wav")]);
+        assert_eq!(songs.len(), 2);
+        assert!(songs.iter().all(|song| song.file_path == files[0]));
+        assert_eq!(songs[0].title, "Sunflower");
+        assert_eq!(songs[0].duration, Duration::from_millis(262_493));
+        assert_eq!(
+            songs[0].cue_range.as_ref().unwrap().end,
+            Some(songs[1].cue_range.as_ref().unwrap().start)
+        );
+        assert_eq!(songs[1].artists[0].name, "Alan Sparhawk");
+        assert_eq!(songs[1].album.as_ref().unwrap().artist.name, "Low");
+        assert_eq!(songs[1].cue_range.as_ref().unwrap().end, None);
+        assert_eq!(songs[1].duration, Duration::from_millis(37_507));
+        assert_eq!(songs[1].year, 2001);
+        assert_eq!(songs[1].genre, "Slowcore");
+        assert_eq!(songs[1].track_number, Some(2));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+use super::Song;
+
+/// One listen to a library song, from when it started until playback moved on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Play {
+    pub song_id: Uuid,
+    pub started_at: u64,  // unix seconds
+    pub played: Duration, // time spent playing, pauses and seeks left out
+    pub skipped: bool,    // left before the end
+}
+
+impl Play {
+    /// A listen starting now
+    pub fn start(song_id: Uuid) -> Self {
+        Play {
+            song_id,
+            started_at: unix_now(),
+            played: Duration::ZERO,
+            skipped: false,
+        }
+    }
+}
+
+impl Song {
+    /// Counts `play` in the song's statistics
+    pub fn record(&mut self, play: &Play) {
+        if play.skipped {
+            self.skip_count += 1;
+        } else {
+            self.play_count += 1;
+            self.last_played = self.last_played.max(play.started_at);
+        }
+    }
+}
+
+/// Adds `play` to the end of the history file, which has one JSON object per line so that it
+/// never has to be rewritten
+pub fn append_play(path: &str, play: &Play) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(play)?)?;
+    Ok(())
+}
+
+/// Every play in the history file, oldest first. A missing file is an empty history.
+pub fn read_history(path: &str) -> Result<Vec<Play>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(anyhow!("cannot read {}: {}", path, e)),
+    };
+    let mut plays = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(play) => plays.push(play),
+            // e.g. a line cut short by a crash, the rest of the history is still good
+            Err(e) => println!("Skipping line {} of {}: {}", number + 1, path, e),
+        }
+    }
+    Ok(plays)
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn play(started_at: u64, skipped: bool) -> Play {
+        Play {
+            song_id: Uuid::new_v4(),
+            started_at,
+            played: Duration::from_secs(30),
+            skipped,
+        }
+    }
+
+    #[test]
+    fn records_plays_and_skips() {
+        let mut song = Song::default();
+        song.record(&play(1_000, false));
+        assert_eq!((song.play_count, song.skip_count), (1, 0));
+        assert_eq!(song.last_played, 1_000);
+
+        // a skip counts, but is not the song's last play
+        song.record(&play(2_000, true));
+        assert_eq!((song.play_count, song.skip_count), (1, 1));
+        assert_eq!(song.last_played, 1_000);
+
+        song.record(&play(3_000, false));
+        assert_eq!(song.play_count, 2);
+        assert_eq!(song.last_played, 3_000);
+
+        // plays recorded out of order, e.g. counted from a phone later, keep the latest
+        song.record(&play(500, false));
+        assert_eq!(song.play_count, 3);
+        assert_eq!(song.last_played, 3_000);
+    }
+
+    #[test]
+    fn starts_plays_now() {
+        let id = Uuid::new_v4();
+        let play = Play::start(id);
+        assert_eq!(play.song_id, id);
+        assert!(unix_now() - play.started_at < 5);
+        assert_eq!(play.played, Duration::ZERO);
+        assert!(!play.skipped);
+    }
+
+    #[test]
+    fn appends_and_reads_the_history_file() {
+        let path = env::temp_dir().join(format!("jukebox-history-{}.jsonl", Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        assert!(read_history(path).unwrap().is_empty());
+
+        let (first, second) = (play(1_000, false), play(2_000, true));
+        append_play(path, &first).unwrap();
+        append_play(path, &second).unwrap();
+        // a line cut short by a crash and an empty line are left out
+        fs::write(
+            path,
+            fs::read_to_string(path).unwrap() + "{\"song_id\": \"\n\n",
+        )
+        .unwrap();
+        append_play(path, &first).unwrap();
+
+        let plays = read_history(path).unwrap();
+        let read: Vec<(Uuid, u64, bool)> = plays
+            .iter()
+            .map(|play| (play.song_id, play.started_at, play.skipped))
+            .collect();
+        assert_eq!(
+            read,
+            [
+                (first.song_id, 1_000, false),
+                (second.song_id, 2_000, true),
+                (first.song_id, 1_000, false),
+            ]
+        );
+        assert_eq!(plays[0].played, Duration::from_secs(30));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{Library, Song};
+
+/// A named, ordered list of songs, stored in the library file by song id
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Playlist {
+    pub id: Uuid,
+    pub name: String,
+    pub song_ids: Vec<Uuid>, // may refer to songs that are missing after a rescan
+}
+
+impl Playlist {
+    pub fn new(name: String, song_ids: Vec<Uuid>) -> Self {
+        Playlist {
+            id: Uuid::new_v4(),
+            name,
+            song_ids,
+        }
+    }
+}
+
+impl Library {
+    pub fn playlist(&self, id: Uuid) -> Result<&Playlist> {
+        self.playlists
+            .iter()
+            .find(|playlist| playlist.id == id)
+            .ok_or_else(|| anyhow!("playlist {} does not exist", id))
+    }
+
+    fn playlist_mut(&mut self, id: Uuid) -> Result<&mut Playlist> {
+        self.playlists
+            .iter_mut()
+            .find(|playlist| playlist.id == id)
+            .ok_or_else(|| anyhow!("playlist {} does not exist", id))
+    }
+
+    /// Name of a playlist or smart playlist
+    pub fn playlist_name(&self, id: Uuid) -> Result<String> {
+        match self.smart_playlist(id) {
+            Some(smart_playlist) => Ok(smart_playlist.name.clone()),
+            None => Ok(self.playlist(id)?.name.clone()),
+        }
+    }
+
+    /// The songs of a playlist or smart playlist in order, skipping any that are no longer in
+    /// the library
+    pub fn playlist_songs(&self, id: Uuid) -> Result<Vec<Song>> {
+        let song_ids = match self.smart_playlist(id) {
+            Some(smart_playlist) => &smart_playlist.song_ids,
+            None => &self.playlist(id)?.song_ids,
+        };
+        Ok(song_ids
+            .iter()
+            .filter_map(|song_id| self.songs.get(song_id).cloned())
+            .collect())
+    }
+
+    pub fn create_playlist(&mut self, name: String, song_ids: Vec<Uuid>) -> Uuid {
+        let playlist = Playlist::new(name, song_ids);
+        let id = playlist.id;
+        self.playlists.push(playlist);
+        id
+    }
+
+    pub fn rename_playlist(&mut self, id: Uuid, name: String) -> Result<()> {
+        match self
+            .smart_playlists
+            .iter_mut()
+            .find(|playlist| playlist.id == id)
+        {
+            Some(smart_playlist) => smart_playlist.name = name,
+            None => self.playlist_mut(id)?.name = name,
+        }
+        Ok(())
+    }
+
+    pub fn delete_playlist(&mut self, id: Uuid) -> Result<()> {
+        let count = self.playlists.len() + self.smart_playlists.len();
+        self.playlists.retain(|playlist| playlist.id != id);
+        self.smart_playlists.retain(|playlist| playlist.id != id);
+        if count == self.playlists.len() + self.smart_playlists.len() {
+            return Err(anyhow!("playlist {} does not exist", id));
+        }
+        Ok(())
+    }
+
+    pub fn duplicate_playlist(&mut self, id: Uuid) -> Result<Uuid> {
+        if let Some(smart_playlist) = self.smart_playlist(id) {
+            let name = format!("{} (copy)", smart_playlist.name);
+            let query = smart_playlist.query.clone();
+            return Ok(self.create_smart_playlist(name, query));
+        }
+
+        let original = self.playlist(id)?;
+        let name = format!("{} (copy)", original.name);
+        let song_ids = original.song_ids.clone();
+        Ok(self.create_playlist(name, song_ids))
+    }
+
+    pub fn add_to_playlist(&mut self, id: Uuid, song_id: Uuid) -> Result<()> {
+        if !self.songs.contains_key(&song_id) {
+            return Err(anyhow!("song {} is not in the library", song_id));
+        }
+        self.playlist_mut(id)?.song_ids.push(song_id);
+        Ok(())
+    }
+
+    pub fn remove_from_playlist(&mut self, id: Uuid, index: usize) -> Result<()> {
+        let song_ids = &mut self.playlist_mut(id)?.song_ids;
+        if index >= song_ids.len() {
+            return Err(anyhow!("playlist has no song at position {}", index));
+        }
+        song_ids.remove(index);
+        Ok(())
+    }
+
+    pub fn move_in_playlist(&mut self, id: Uuid, from: usize, to: usize) -> Result<()> {
+        let song_ids = &mut self.playlist_mut(id)?.song_ids;
+        if from >= song_ids.len() || to >= song_ids.len() {
+            return Err(anyhow!("cannot move position {} to {}", from, to));
+        }
+        let song_id = song_ids.remove(from);
+        song_ids.insert(to, song_id);
+        Ok(())
+    }
+}
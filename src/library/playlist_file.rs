@@ -0,0 +1,675 @@
+use anyhow::{anyhow, Result};
+use quick_xml::{escape::escape, events::Event, Reader};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use uuid::Uuid;
+
+use super::{Library, Song};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u,
+    M3u8,
+    Pls,
+    Xspf,
+}
+
+/// One line/track of a playlist file, before it is matched against the library
+#[derive(Debug, Default, Clone)]
+pub struct PlaylistEntry {
+    pub location: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ImportedPlaylist {
+    pub name: String,
+    pub song_ids: Vec<Uuid>,
+    pub unresolved: Vec<PlaylistEntry>,
+}
+
+impl PlaylistFormat {
+    pub fn from_path(path: &Path) -> Result<PlaylistFormat> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("m3u") => Ok(PlaylistFormat::M3u),
+            Some("m3u8") => Ok(PlaylistFormat::M3u8),
+            Some("pls") => Ok(PlaylistFormat::Pls),
+            Some("xspf") => Ok(PlaylistFormat::Xspf),
+            _ => Err(anyhow!(
+                "{:?} is not a .m3u, .m3u8, .pls or .xspf file",
+                path
+            )),
+        }
+    }
+}
+
+/// Reads a playlist file, returning its title (if the format has one) and its entries
+pub fn read_playlist(path: &Path) -> Result<(Option<String>, Vec<PlaylistEntry>)> {
+    // plain .m3u files are often Latin-1, so don't fail on invalid UTF-8
+    let bytes = fs::read(path)?;
+    let contents = String::from_utf8_lossy(&bytes);
+    let contents = contents.trim_start_matches('\u{feff}');
+
+    match PlaylistFormat::from_path(path)? {
+        PlaylistFormat::M3u | PlaylistFormat::M3u8 => Ok((None, parse_m3u(contents))),
+        PlaylistFormat::Pls => Ok((None, parse_pls(contents))),
+        PlaylistFormat::Xspf => parse_xspf(contents),
+    }
+}
+
+/// Writes `songs` to `path`, in the format matching its extension
+pub fn write_playlist(path: &Path, name: &str, songs: &[Song]) -> Result<()> {
+    let contents = match PlaylistFormat::from_path(path)? {
+        PlaylistFormat::M3u | PlaylistFormat::M3u8 => write_m3u(songs),
+        PlaylistFormat::Pls => write_pls(songs),
+        PlaylistFormat::Xspf => write_xspf(name, songs),
+    };
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+fn parse_m3u(contents: &str) -> Vec<PlaylistEntry> {
+    let mut entries = Vec::new();
+    let mut pending_info = PlaylistEntry::default();
+
+    for line in contents.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:<seconds>,<artist> - <title>
+            let (seconds, display) = info.split_once(',').unwrap_or((info, ""));
+            pending_info.duration = seconds.trim().parse::<u64>().ok().map(Duration::from_secs);
+            (pending_info.artist, pending_info.title) = split_display_title(display);
+        } else if !line.is_empty() && !line.starts_with('#') {
+            entries.push(PlaylistEntry {
+                location: Some(line.to_string()),
+                ..std::mem::take(&mut pending_info)
+            });
+        }
+    }
+
+    entries
+}
+
+fn parse_pls(contents: &str) -> Vec<PlaylistEntry> {
+    let mut entries: HashMap<usize, PlaylistEntry> = HashMap::new();
+
+    for line in contents.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim().to_string();
+        let numbered = |prefix: &str| key.strip_prefix(prefix)?.parse::<usize>().ok();
+
+        if let Some(number) = numbered("file") {
+            entries.entry(number).or_default().location = Some(value);
+        } else if let Some(number) = numbered("title") {
+            let entry = entries.entry(number).or_default();
+            (entry.artist, entry.title) = split_display_title(&value);
+        } else if let Some(number) = numbered("length") {
+            // streams use -1 for an unknown length
+            entries.entry(number).or_default().duration =
+                value.parse::<u64>().ok().map(Duration::from_secs);
+        }
+    }
+
+    let mut entries: Vec<(usize, PlaylistEntry)> = entries.into_iter().collect();
+    entries.sort_by_key(|(number, _)| *number);
+    entries
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .filter(|entry| entry.location.is_some())
+        .collect()
+}
+
+fn parse_xspf(contents: &str) -> Result<(Option<String>, Vec<PlaylistEntry>)> {
+    let mut reader = Reader::from_str(contents);
+    reader.trim_text(true);
+
+    let mut title = None;
+    let mut entries = Vec::new();
+    let mut current: Option<PlaylistEntry> = None;
+    let mut element = Vec::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => {
+                element = start.name().as_ref().to_vec();
+                if element == b"track" {
+                    current = Some(PlaylistEntry::default());
+                }
+            }
+            Event::End(end) => {
+                if end.name().as_ref() == b"track" {
+                    entries.extend(current.take());
+                }
+                element.clear();
+            }
+            Event::Text(text) => {
+                let text = text.unescape()?.to_string();
+                match (current.as_mut(), element.as_slice()) {
+                    (Some(entry), b"location") => entry.location = Some(text),
+                    (Some(entry), b"title") => entry.title = Some(text),
+                    (Some(entry), b"creator") => entry.artist = Some(text),
+                    (Some(entry), b"album") => entry.album = Some(text),
+                    (Some(entry), b"duration") => {
+                        entry.duration = text.parse::<u64>().ok().map(Duration::from_millis)
+                    }
+                    (None, b"title") => title = Some(text),
+                    _ => (),
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+
+    Ok((title, entries))
+}
+
+fn write_m3u(songs: &[Song]) -> String {
+    let mut contents = String::from("#EXTM3U\n");
+    for song in songs {
+        let _ = writeln!(
+            contents,
+            "#EXTINF:{},{} - {}\n{}",
+            song.duration.as_secs(),
+            song_artist(song),
+            song.title,
+            song_location(song).display()
+        );
+    }
+    contents
+}
+
+fn write_pls(songs: &[Song]) -> String {
+    let mut contents = String::from("[playlist]\n");
+    for (index, song) in songs.iter().enumerate() {
+        let number = index + 1;
+        let _ = writeln!(
+            contents,
+            "File{number}={}\nTitle{number}={} - {}\nLength{number}={}",
+            song_location(song).display(),
+            song_artist(song),
+            song.title,
+            song.duration.as_secs()
+        );
+    }
+    let _ = writeln!(contents, "NumberOfEntries={}\nVersion=2", songs.len());
+    contents
+}
+
+fn write_xspf(name: &str, songs: &[Song]) -> String {
+    let mut contents = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+    let _ = writeln!(contents, "  <title>{}</title>\n  <trackList>", escape(name));
+    for song in songs {
+        let _ = writeln!(
+            contents,
+            "    <track>\n      <location>{}</location>\n      <title>{}</title>\n      <creator>{}</creator>",
+            escape(&path_to_file_uri(&song_location(song))),
+            escape(&song.title),
+            escape(song_artist(song))
+        );
+        if let Some(album) = &song.album {
+            let _ = writeln!(contents, "      <album>{}</album>", escape(&album.title));
+        }
+        let _ = writeln!(
+            contents,
+            "      <duration>{}</duration>\n    </track>",
+            song.duration.as_millis()
+        );
+    }
+    contents.push_str("  </trackList>\n</playlist>\n");
+    contents
+}
+
+impl Library {
+    /// Reads a playlist file and matches its entries against `songs`: first by path (absolute
+    /// or relative to the playlist), then by tags for files that have moved since
+    pub fn import_playlist(&self, path: &Path) -> Result<ImportedPlaylist> {
+        let (title, entries) = read_playlist(path)?;
+        let playlist_dir = path.parent().unwrap_or(Path::new(""));
+        let name = title.unwrap_or_else(|| {
+            path.file_stem()
+                .map_or(String::from("Imported playlist"), |stem| {
+                    stem.to_string_lossy().to_string()
+                })
+        });
+
+        let mut by_path: HashMap<PathBuf, Uuid> = HashMap::new();
+        for song in self.songs.values() {
+            if song.cue_range.is_some() {
+                by_path.insert(cue_track_location(&song.file_path, song), song.id);
+            }
+            // cue sheet tracks share a file, so a bare path refers to the first of them
+            by_path
+                .entry(song.file_path.clone())
+                .and_modify(|id| {
+                    if self.songs[&*id].start_offset() > song.start_offset() {
+                        *id = song.id;
+                    }
+                })
+                .or_insert(song.id);
+        }
+        let mut by_canonical_path: Option<HashMap<PathBuf, Uuid>> = None;
+
+        let mut imported = ImportedPlaylist {
+            name,
+            ..ImportedPlaylist::default()
+        };
+        for entry in entries {
+            let resolved = entry
+                .location
+                .as_deref()
+                .map(|location| playlist_dir.join(file_uri_to_path(location)))
+                .and_then(|entry_path| {
+                    if let Some(id) = by_path.get(&entry_path) {
+                        return Some(*id);
+                    }
+                    let canonical = canonical_location(&entry_path)?;
+                    by_canonical_path
+                        .get_or_insert_with(|| {
+                            by_path
+                                .iter()
+                                .filter_map(|(path, id)| Some((canonical_location(path)?, *id)))
+                                .collect()
+                        })
+                        .get(&canonical)
+                        .copied()
+                })
+                .or_else(|| self.match_by_tags(&entry));
+
+            match resolved {
+                Some(id) => imported.song_ids.push(id),
+                None => imported.unresolved.push(entry),
+            }
+        }
+
+        Ok(imported)
+    }
+
+    fn match_by_tags(&self, entry: &PlaylistEntry) -> Option<Uuid> {
+        // entries without any tags can still be matched by their file name
+        let title = entry.title.clone().or_else(|| {
+            let location = file_uri_to_path(entry.location.as_deref()?);
+            let stem = location.file_stem()?.to_string_lossy().to_string();
+            Some(split_display_title(&stem).1.unwrap_or(stem))
+        })?;
+
+        let same = |a: &str, b: &str| a.trim().eq_ignore_ascii_case(b.trim());
+        self.songs
+            .values()
+            .filter(|song| same(&song.title, &title))
+            .filter(|song| {
+                entry
+                    .artist
+                    .as_ref()
+                    .map_or(true, |artist| same(song_artist(song), artist))
+            })
+            // prefer the right album when the same song is on several
+            .max_by_key(|song| {
+                let album_matches = match (&entry.album, &song.album) {
+                    (Some(entry_album), Some(album)) => same(entry_album, &album.title),
+                    _ => false,
+                };
+                let duration_matches = entry.duration.is_some_and(|duration| {
+                    duration.as_secs().abs_diff(song.duration.as_secs()) <= 2
+                });
+                (album_matches, duration_matches)
+            })
+            .map(|song| song.id)
+    }
+}
+
+fn song_artist(song: &Song) -> &str {
+    song.artists
+        .first()
+        .map_or("Unknown", |artist| artist.name.as_str())
+}
+
+/// Splits the usual "Artist - Title" display string
+fn split_display_title(display: &str) -> (Option<String>, Option<String>) {
+    let display = display.trim();
+    match display.split_once(" - ") {
+        Some((artist, title)) => (Some(artist.trim().into()), Some(title.trim().into())),
+        None if display.is_empty() => (None, None),
+        None => (None, Some(display.into())),
+    }
+}
+
+fn absolute_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Where playlists point to a song: its file, or for cue sheet tracks the track number below
+/// the shared file, the way MPD names them (e.g. `/music/album.flac/track0003`)
+fn song_location(song: &Song) -> PathBuf {
+    let path = absolute_path(&song.file_path);
+    match song.cue_range {
+        Some(_) => cue_track_location(&path, song),
+        None => path,
+    }
+}
+
+fn cue_track_location(file_path: &Path, song: &Song) -> PathBuf {
+    file_path.join(format!("track{:04}", song.track_number.unwrap_or(0)))
+}
+
+/// `fs::canonicalize`, also for cue sheet track locations, which are no files themselves
+fn canonical_location(path: &Path) -> Option<PathBuf> {
+    fs::canonicalize(path).ok().or_else(|| {
+        let file = fs::canonicalize(path.parent()?).ok()?;
+        let track = path.file_name()?;
+        file.is_file().then(|| file.join(track))
+    })
+}
+
+fn file_uri_to_path(location: &str) -> PathBuf {
+    let Some(path) = location.strip_prefix("file://") else {
+        return PathBuf::from(location);
+    };
+    // "file:///C:/Music" on Windows
+    let path = match path.strip_prefix('/') {
+        Some(rest) if rest.get(1..2) == Some(":") => rest,
+        _ => path,
+    };
+
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&decoded).to_string())
+}
+
+fn path_to_file_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from(if path.starts_with('/') {
+        "file://"
+    } else {
+        "file:///"
+    });
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                uri.push(byte as char)
+            }
+            _ => {
+                let _ = write!(uri, "%{:02X}", byte);
+            }
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{Artist, CueRange};
+
+    /// An empty directory of its own below the system's temporary directory
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jukebox-test-{}-{}", name, Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::canonicalize(dir).unwrap()
+    }
+
+    fn song(library: &mut Library, file_path: &Path, title: &str, track: Option<u32>) -> Uuid {
+        let id = Uuid::new_v4();
+        library.songs.insert(
+            id,
+            Song {
+                id,
+                title: title.to_string(),
+                artists: vec![Artist {
+                    id: Uuid::new_v4(),
+                    name: String::from("Artist"),
+                }],
+                file_path: file_path.to_path_buf(),
+                duration: Duration::from_secs(60),
+                track_number: track,
+                cue_range: track.map(|track| CueRange {
+                    start: Duration::from_secs(60 * (track as u64 - 1)),
+                    end: Some(Duration::from_secs(60 * track as u64)),
+                }),
+                ..Song::default()
+            },
+        );
+        id
+    }
+
+    /// A library with a single file and a cue sheet image of three tracks
+    fn cue_library(dir: &Path) -> (Library, Vec<Uuid>) {
+        let mut library = Library::new();
+        let single = dir.join("single.flac");
+        let image = dir.join("album.flac");
+        fs::write(&single, "").unwrap();
+        fs::write(&image, "").unwrap();
+        let ids = vec![
+            song(&mut library, &single, "Single", None),
+            song(&mut library, &image, "One", Some(1)),
+            song(&mut library, &image, "Two", Some(2)),
+            song(&mut library, &image, "Three", Some(3)),
+        ];
+        (library, ids)
+    }
+
+    #[test]
+    fn cue_tracks_round_trip_in_every_format() {
+        let dir = test_dir("cue");
+        let (library, ids) = cue_library(&dir);
+        // out of order, so that matching the shared file alone would be wrong
+        let order = [ids[3], ids[0], ids[2], ids[1]];
+        let songs: Vec<Song> = order.iter().map(|id| library.songs[id].clone()).collect();
+
+        for extension in ["m3u", "m3u8", "pls", "xspf"] {
+            let path = dir.join(format!("list.{}", extension));
+            write_playlist(&path, "List", &songs).unwrap();
+            let imported = library.import_playlist(&path).unwrap();
+            assert_eq!(imported.song_ids, order, "{}", extension);
+            assert!(imported.unresolved.is_empty(), "{}", extension);
+        }
+        let m3u = fs::read_to_string(dir.join("list.m3u")).unwrap();
+        assert!(m3u.contains(&format!("{}\n", dir.join("album.flac/track0003").display())));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bare_cue_image_path_means_its_first_track() {
+        let dir = test_dir("cue-image");
+        let (library, ids) = cue_library(&dir);
+        let path = dir.join("list.m3u");
+        fs::write(&path, "album.flac\n").unwrap();
+        assert_eq!(library.import_playlist(&path).unwrap().song_ids, [ids[1]]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn parses_m3u() {
+        let entries = parse_m3u(
+            "#EXTM3U\n\n#EXTINF:215,Low - Sunflower\n  Low/01.flac  \n# a comment\nLow/02.flac\n#EXTINF:-1,Radio\nhttp://radio/stream\n",
+        );
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].location.as_deref(), Some("Low/01.flac"));
+        assert_eq!(entries[0].artist.as_deref(), Some("Low"));
+        assert_eq!(entries[0].title.as_deref(), Some("Sunflower"));
+        assert_eq!(entries[0].duration, Some(Duration::from_secs(215)));
+        // tags belong to the next entry only
+        assert_eq!(entries[1].title, None);
+        assert_eq!(entries[2].title.as_deref(), Some("Radio"));
+        assert_eq!(entries[2].duration, None);
+    }
+
+    #[test]
+    fn parses_pls() {
+        let entries = parse_pls(
+            "[playlist]\nTitle2=Two\nFILE2 = b.flac\nfile1=a = b.flac\nLength1=-1\nTitle3=No file\nNumberOfEntries=3\n",
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].location.as_deref(), Some("a = b.flac"));
+        assert_eq!(entries[0].duration, None);
+        assert_eq!(entries[1].location.as_deref(), Some("b.flac"));
+        assert_eq!(entries[1].title.as_deref(), Some("Two"));
+    }
+
+    #[test]
+    fn parses_xspf() {
+        let (title, entries) = parse_xspf(
+            r#"<?xml version="1.0"?>
+<playlist version="1" xmlns="http://xspf.org/ns/0/">
+  <title>Rock &amp; Roll</title>
+  <trackList>
+    <track>
+      <location>file:///music/AC%2FDC/It%27s%20a%20Long%20Way.flac</location>
+      <title>It&apos;s a Long Way &lt;live&gt;</title>
+      <creator>AC/DC</creator>
+      <album>High Voltage</album>
+      <duration>301000</duration>
+    </track>
+    <track><title>Only a title</title></track>
+  </trackList>
+</playlist>"#,
+        )
+        .unwrap();
+        assert_eq!(title.as_deref(), Some("Rock & Roll"));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].title.as_deref(), Some("It's a Long Way <live>"));
+        assert_eq!(entries[0].artist.as_deref(), Some("AC/DC"));
+        assert_eq!(entries[0].album.as_deref(), Some("High Voltage"));
+        assert_eq!(entries[0].duration, Some(Duration::from_secs(301)));
+        assert_eq!(
+            file_uri_to_path(entries[0].location.as_deref().unwrap()),
+            Path::new("/music/AC/DC/It's a Long Way.flac")
+        );
+        assert_eq!(entries[1].location, None);
+
+        assert!(parse_xspf("<playlist><title>x</playlist>").is_err());
+    }
+
+    #[test]
+    fn converts_file_uris() {
+        for path in ["/music/Sigur Rós/(untitled) #1 & 100%.flac", "/a+b/c;d.mp3"] {
+            let uri = path_to_file_uri(Path::new(path));
+            assert!(!uri.contains([' ', '#', '&', '+', ';']), "{}", uri);
+            assert_eq!(file_uri_to_path(&uri), Path::new(path));
+        }
+        assert_eq!(path_to_file_uri(Path::new("/a b")), "file:///a%20b");
+        assert_eq!(
+            path_to_file_uri(Path::new("C:\\Music\\a.mp3")),
+            "file:///C:/Music/a.mp3"
+        );
+        assert_eq!(
+            file_uri_to_path("file:///C:/Music/a.mp3"),
+            Path::new("C:/Music/a.mp3")
+        );
+        // stray percent signs are kept, plain paths are left alone
+        assert_eq!(
+            file_uri_to_path("file:///100%.flac"),
+            Path::new("/100%.flac")
+        );
+        assert_eq!(file_uri_to_path("file:///%zz"), Path::new("/%zz"));
+        assert_eq!(file_uri_to_path("a%20b.flac"), Path::new("a%20b.flac"));
+    }
+
+    #[test]
+    fn escapes_what_each_format_needs() {
+        let dir = test_dir("escaping");
+        let mut library = Library::new();
+        let path = dir.join("Tom & Jerry <1> = \"2\".flac");
+        fs::write(&path, "").unwrap();
+        let id = song(&mut library, &path, "Cat & Mouse <\"Tom\"> = 1", None);
+        let songs = [library.songs[&id].clone()];
+
+        write_playlist(&dir.join("list.xspf"), "Tom & Jerry", &songs).unwrap();
+        let xspf = fs::read_to_string(dir.join("list.xspf")).unwrap();
+        assert!(xspf.contains("<title>Tom &amp; Jerry</title>"), "{}", xspf);
+        assert!(
+            xspf.contains("<title>Cat &amp; Mouse &lt;&quot;Tom&quot;&gt; = 1</title>"),
+            "{}",
+            xspf
+        );
+        assert!(
+            xspf.contains("Tom%20%26%20Jerry%20%3C1%3E%20%3D%20%222%22.flac"),
+            "{}",
+            xspf
+        );
+
+        for extension in ["pls", "xspf"] {
+            let list = dir.join(format!("list.{}", extension));
+            write_playlist(&list, "Tom & Jerry", &songs).unwrap();
+            let (_, entries) = read_playlist(&list).unwrap();
+            assert_eq!(
+                entries[0].title.as_deref(),
+                Some(songs[0].title.as_str()),
+                "{}",
+                extension
+            );
+            assert_eq!(
+                library.import_playlist(&list).unwrap().song_ids,
+                [id],
+                "{}",
+                extension
+            );
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resolves_relative_paths_uris_and_tags() {
+        let dir = test_dir("locations");
+        let (library, ids) = cue_library(&dir);
+        fs::create_dir(dir.join("lists")).unwrap();
+        let list = dir.join("lists/list.m3u");
+        let single_uri = path_to_file_uri(&dir.join("single.flac"));
+        fs::write(
+            &list,
+            format!(
+                "../single.flac\n{}\n../lists/../album.flac/track0002\n\
+                 #EXTINF:60,Artist - Three\n/moved/away.flac\n/moved/Artist - One.flac\n\
+                 #EXTINF:60,Nobody - Nothing\n/missing.flac\n",
+                single_uri
+            ),
+        )
+        .unwrap();
+
+        let imported = library.import_playlist(&list).unwrap();
+        assert_eq!(imported.name, "list");
+        assert_eq!(imported.song_ids, [ids[0], ids[0], ids[2], ids[3], ids[1]]);
+        assert_eq!(imported.unresolved.len(), 1);
+        assert_eq!(
+            imported.unresolved[0].location.as_deref(),
+            Some("/missing.flac")
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,370 @@
+use std::{cmp::Ordering, time::Duration};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+use uuid::Uuid;
+
+use super::{Library, Song};
+
+/// Normalized copies of every song's searchable tags, so a keystroke only has to compare
+/// strings instead of lowercasing and decomposing the whole library again
+#[derive(Debug, Default, Clone)]
+pub struct SearchIndex {
+    entries: Vec<SearchEntry>,
+}
+
+#[derive(Debug, Clone)]
+struct SearchEntry {
+    id: Uuid,
+    title: String,
+    artist: String,
+    album: String,
+    genre: String,
+    year: u16,
+    start_offset: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Title,
+    Artist,
+    Album,
+    Genre,
+}
+
+const TEXT_FIELDS: [Field; 4] = [Field::Title, Field::Artist, Field::Album, Field::Genre];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Any(String), // matches any text field
+    Field(Field, String),
+    Year(Option<u16>, Option<u16>), // inclusive, open ended when `None`
+}
+
+/// What's typed into the search box, e.g. `heroes artist:bowie year:1970..1979`.
+/// Every term has to match; quotes keep words together (`album:"low end"`).
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SearchQuery {
+    terms: Vec<Term>,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        match name {
+            "title" => Some(Field::Title),
+            "artist" => Some(Field::Artist),
+            "album" => Some(Field::Album),
+            "genre" => Some(Field::Genre),
+            _ => None,
+        }
+    }
+
+    /// A hit in the title ranks above one in the artist, and so on
+    fn weight(&self) -> u32 {
+        match self {
+            Field::Title => 4,
+            Field::Artist => 3,
+            Field::Album => 2,
+            Field::Genre => 1,
+        }
+    }
+}
+
+impl SearchEntry {
+    fn new(song: &Song) -> Self {
+        SearchEntry {
+            id: song.id,
+            title: normalize(&song.title),
+            artist: normalize(
+                &song
+                    .artists
+                    .iter()
+                    .map(|artist| artist.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            album: song
+                .album
+                .as_ref()
+                .map_or(String::new(), |album| normalize(&album.title)),
+            genre: normalize(&song.genre),
+            year: song.year,
+            start_offset: song.start_offset(),
+        }
+    }
+
+    fn field(&self, field: Field) -> &str {
+        match field {
+            Field::Title => &self.title,
+            Field::Artist => &self.artist,
+            Field::Album => &self.album,
+            Field::Genre => &self.genre,
+        }
+    }
+
+    /// Artist, then album, then position within the file, then title
+    fn library_order(&self, other: &SearchEntry) -> Ordering {
+        self.artist
+            .cmp(&other.artist)
+            .then_with(|| self.album.cmp(&other.album))
+            .then_with(|| self.start_offset.cmp(&other.start_offset))
+            .then_with(|| self.title.cmp(&other.title))
+    }
+}
+
+impl SearchQuery {
+    /// Never fails: unfinished terms such as `artist:` or `year:19..` are ignored or matched
+    /// as well as possible, since the query is re-run while it is being typed
+    pub fn parse(query: &str) -> SearchQuery {
+        SearchQuery {
+            terms: split_words(query)
+                .iter()
+                .filter_map(|word| parse_term(word))
+                .collect(),
+        }
+    }
+
+    /// Relevance of a song, `None` if any term does not match it
+    fn score(&self, entry: &SearchEntry) -> Option<u32> {
+        self.terms.iter().try_fold(0, |total, term| {
+            let score = match term {
+                Term::Any(word) => TEXT_FIELDS
+                    .iter()
+                    .filter_map(|field| {
+                        match_quality(entry.field(*field), word)
+                            .map(|quality| quality * field.weight())
+                    })
+                    .max(),
+                Term::Field(field, word) => {
+                    match_quality(entry.field(*field), word).map(|quality| quality * field.weight())
+                }
+                Term::Year(from, to) => (from.map_or(true, |from| entry.year >= from)
+                    && to.map_or(true, |to| entry.year <= to))
+                .then_some(0),
+            }?;
+            Some(total + score)
+        })
+    }
+}
+
+/// Lowercases and strips accents, so "bjork" finds "Björk" and "Sigur Rós" is "sigur ros"
+fn normalize(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    for c in text.nfd().filter(|c| !is_combining_mark(*c)) {
+        // letters that have no decomposition into a base letter and an accent
+        match c {
+            'ø' | 'Ø' => normalized.push('o'),
+            'ł' | 'Ł' => normalized.push('l'),
+            'đ' | 'Đ' => normalized.push('d'),
+            'ß' => normalized.push_str("ss"),
+            'æ' | 'Æ' => normalized.push_str("ae"),
+            'œ' | 'Œ' => normalized.push_str("oe"),
+            c => normalized.extend(c.to_lowercase()),
+        }
+    }
+    normalized
+}
+
+/// Splits on whitespace outside of double quotes, dropping the quotes
+fn split_words(query: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+fn parse_term(word: &str) -> Option<Term> {
+    let Some((prefix, value)) = word.split_once(':') else {
+        return Some(Term::Any(normalize(word)));
+    };
+
+    let (prefix, value) = (normalize(prefix), normalize(value.trim()));
+    if let Some(field) = Field::from_name(&prefix) {
+        return (!value.is_empty()).then_some(Term::Field(field, value));
+    }
+    if prefix == "year" {
+        return parse_year_range(&value);
+    }
+    // not a known field, e.g. a title like "re:stacks"
+    Some(Term::Any(normalize(word)))
+}
+
+/// `1975`, `1970..1979`, `1970..` or `..1979`
+fn parse_year_range(value: &str) -> Option<Term> {
+    let parse_bound = |bound: &str| match bound {
+        "" => Some(None),
+        bound => bound.parse().ok().map(Some),
+    };
+
+    match value.split_once("..") {
+        Some((from, to)) => Some(Term::Year(parse_bound(from)?, parse_bound(to)?)),
+        None => {
+            let year = value.parse().ok()?;
+            Some(Term::Year(Some(year), Some(year)))
+        }
+    }
+}
+
+/// 4 for the whole field, 3 for its start, 2 for the start of a word in it, 1 anywhere else
+fn match_quality(text: &str, word: &str) -> Option<u32> {
+    if text == word {
+        return Some(4);
+    }
+    if text.starts_with(word) {
+        return Some(3);
+    }
+
+    let mut quality = None;
+    for (index, _) in text.match_indices(word) {
+        let at_word_start = text[..index]
+            .chars()
+            .next_back()
+            .map_or(true, |c| !c.is_alphanumeric());
+        if at_word_start {
+            return Some(2);
+        }
+        quality = Some(1);
+    }
+    quality
+}
+
+impl Library {
+    /// Needs to run whenever songs are added, removed or retagged
+    pub fn rebuild_search_index(&mut self) {
+        self.search_index = SearchIndex {
+            entries: self.songs.values().map(SearchEntry::new).collect(),
+        };
+    }
+
+    /// Ids of the matching songs, best matches first. An empty query lists the whole library.
+    pub fn search(&self, query: &SearchQuery) -> Vec<Uuid> {
+        let mut results: Vec<(u32, &SearchEntry)> = self
+            .search_index
+            .entries
+            .iter()
+            .filter_map(|entry| query.score(entry).map(|score| (score, entry)))
+            .collect();
+
+        results.sort_by(|(score, entry), (other_score, other_entry)| {
+            other_score
+                .cmp(score)
+                .then_with(|| entry.library_order(other_entry))
+        });
+
+        results.into_iter().map(|(_, entry)| entry.id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{Album, Artist};
+
+    fn library(songs: &[(&str, &str, &str, &str, u16)]) -> (Library, Vec<Uuid>) {
+        let mut library = Library::new();
+        let mut ids = Vec::new();
+        for (title, artist, album, genre, year) in songs {
+            let id = Uuid::new_v4();
+            let artist = Artist::new(artist.to_string());
+            library.songs.insert(
+                id,
+                Song {
+                    id,
+                    title: title.to_string(),
+                    album: Album::new(Some(album.to_string()), artist.clone()),
+                    artists: vec![artist],
+                    genre: genre.to_string(),
+                    year: *year,
+                    ..Song::default()
+                },
+            );
+            ids.push(id);
+        }
+        library.rebuild_search_index();
+        (library, ids)
+    }
+
+    #[test]
+    fn parses_queries() {
+        let any = |word: &str| Term::Any(word.to_string());
+        assert_eq!(
+            SearchQuery::parse(r#"  Heroes artist:BOWIE album:"Low End" year:1970..1979 "#).terms,
+            [
+                any("heroes"),
+                Term::Field(Field::Artist, String::from("bowie")),
+                Term::Field(Field::Album, String::from("low end")),
+                Term::Year(Some(1970), Some(1979)),
+            ]
+        );
+        assert_eq!(
+            SearchQuery::parse("year:1975 year:1990.. year:..1960").terms,
+            [
+                Term::Year(Some(1975), Some(1975)),
+                Term::Year(Some(1990), None),
+                Term::Year(None, Some(1960)),
+            ]
+        );
+        // unfinished or unknown terms while typing
+        assert_eq!(
+            SearchQuery::parse("artist: year:19x year:1990..20x re:stacks \"unclosed quote").terms,
+            [any("re:stacks"), any("unclosed quote")]
+        );
+        assert_eq!(SearchQuery::parse("  "), SearchQuery::default());
+    }
+
+    #[test]
+    fn normalizes_accents_and_case() {
+        assert_eq!(normalize("Björk"), "bjork");
+        assert_eq!(normalize("Sigur Rós"), "sigur ros");
+        assert_eq!(
+            normalize("Mø Łódź Straße Æon Œuvre"),
+            "mo lodz strasse aeon oeuvre"
+        );
+    }
+
+    #[test]
+    fn rates_matches() {
+        assert_eq!(match_quality("low", "low"), Some(4));
+        assert_eq!(match_quality("lower", "low"), Some(3));
+        assert_eq!(match_quality("the low end", "low"), Some(2));
+        assert_eq!(match_quality("yellow (low)", "low"), Some(2));
+        assert_eq!(match_quality("yellow", "low"), Some(1));
+        assert_eq!(match_quality("high", "low"), None);
+    }
+
+    #[test]
+    fn ranks_and_filters_songs() {
+        let (library, ids) = library(&[
+            ("Heroes", "David Bowie", "Heroes", "Rock", 1977),
+            ("Sound and Vision", "David Bowie", "Low", "Rock", 1977),
+            ("Low", "Flo Rida", "Mail on Sunday", "Hip Hop", 2008),
+            ("Lowlands", "Björk", "Homogenic", "Electronic", 1997),
+            ("Yellow", "Coldplay", "Parachutes", "Rock", 2000),
+        ]);
+        let search = |query: &str| library.search(&SearchQuery::parse(query));
+
+        // the whole title, then the start of a title, then the whole album, then anywhere
+        assert_eq!(search("low"), [ids[2], ids[3], ids[1], ids[4]]);
+        assert_eq!(search("album:low"), [ids[1]]);
+        assert_eq!(search("bjork low"), [ids[3]]);
+        assert_eq!(search("rock year:1970..1979"), [ids[0], ids[1]]);
+        assert_eq!(search("year:..1977 genre:rock bowie"), [ids[0], ids[1]]);
+        assert!(search("bowie year:2000..").is_empty());
+        // everything in library order when nothing is typed
+        assert_eq!(search(""), [ids[3], ids[4], ids[0], ids[1], ids[2]]);
+    }
+}
@@ -0,0 +1,763 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::{Ordering, Reverse},
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+use super::{Library, Song};
+
+/// A playlist whose songs are whatever currently matches its rule, e.g.
+/// `genre = Jazz AND (year < 1970 OR rating >= 4) SORT BY year DESC LIMIT 50`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmartPlaylist {
+    pub id: Uuid,
+    pub name: String,
+    pub query: SmartQuery,
+    #[serde(skip)]
+    pub song_ids: Vec<Uuid>, // result of the last evaluation, see `Library::refresh_smart_playlists`
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SmartQuery {
+    pub rule: Rule,
+    pub sort_by: Option<SongField>,
+    #[serde(default)]
+    pub descending: bool,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Rule {
+    All(Vec<Rule>), // AND
+    Any(Vec<Rule>), // OR
+    Condition(Condition),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Condition {
+    pub field: SongField,
+    pub operator: Operator,
+    pub value: String, // numeric fields are validated when parsing
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SongField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Track, // track number, 0 when unknown
+    Year,
+    Duration,      // seconds
+    Rating,        // 0 when unrated
+    Plays,         // play count
+    DaysAgoAdded,  // days since the song was first scanned
+    DaysAgoPlayed, // days since the song was last played to the end
+    Skips,         // skip count
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operator {
+    Is,
+    IsNot,
+    Contains,
+    DoesNotContain,
+    LessThan,
+    AtMost,
+    GreaterThan,
+    AtLeast,
+}
+
+impl SongField {
+    const ALL: [(SongField, &'static str); 12] = [
+        (SongField::Title, "title"),
+        (SongField::Artist, "artist"),
+        (SongField::Album, "album"),
+        (SongField::Genre, "genre"),
+        (SongField::Track, "track"),
+        (SongField::Year, "year"),
+        (SongField::Duration, "duration"),
+        (SongField::Rating, "rating"),
+        (SongField::Plays, "plays"),
+        (SongField::DaysAgoAdded, "added"),
+        (SongField::DaysAgoPlayed, "played"),
+        (SongField::Skips, "skips"),
+    ];
+
+    fn from_name(name: &str) -> Option<SongField> {
+        SongField::ALL
+            .iter()
+            .find(|(_, field_name)| field_name.eq_ignore_ascii_case(name))
+            .map(|(field, _)| *field)
+    }
+
+    pub fn name(&self) -> &'static str {
+        SongField::ALL
+            .iter()
+            .find(|(field, _)| field == self)
+            .map_or("?", |(_, name)| name)
+    }
+
+    fn is_numeric(&self) -> bool {
+        !matches!(
+            self,
+            SongField::Title | SongField::Artist | SongField::Album | SongField::Genre
+        )
+    }
+
+    fn text(&self, song: &Song) -> String {
+        match self {
+            SongField::Title => song.title.clone(),
+            SongField::Artist => song
+                .artists
+                .iter()
+                .map(|artist| artist.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            SongField::Album => song
+                .album
+                .as_ref()
+                .map_or(String::new(), |album| album.title.clone()),
+            SongField::Genre => song.genre.clone(),
+            _ => self.number(song).to_string(),
+        }
+    }
+
+    fn number(&self, song: &Song) -> u64 {
+        match self {
+            SongField::Track => song.track_number.unwrap_or(0) as u64,
+            SongField::Year => song.year as u64,
+            SongField::Duration => song.duration.as_secs(),
+            SongField::Rating => song.rating.unwrap_or(0) as u64,
+            SongField::Plays => song.play_count as u64,
+            SongField::DaysAgoAdded => days_since(song.date_added),
+            SongField::DaysAgoPlayed => days_since(song.last_played),
+            SongField::Skips => song.skip_count as u64,
+            _ => 0,
+        }
+    }
+
+    fn compare(&self, a: &Song, b: &Song) -> Ordering {
+        if self.is_numeric() {
+            self.number(a).cmp(&self.number(b))
+        } else {
+            self.text(a)
+                .to_lowercase()
+                .cmp(&self.text(b).to_lowercase())
+        }
+    }
+
+    /// Same order as `compare`, but computed once per song when sorting many of them
+    fn sort_key(&self, song: &Song) -> (u64, String) {
+        if self.is_numeric() {
+            (self.number(song), String::new())
+        } else {
+            (0, self.text(song).to_lowercase())
+        }
+    }
+}
+
+impl Operator {
+    const ALL: [(Operator, &'static str); 8] = [
+        (Operator::IsNot, "!="),
+        (Operator::DoesNotContain, "!~"),
+        (Operator::AtMost, "<="),
+        (Operator::AtLeast, ">="),
+        (Operator::Is, "="),
+        (Operator::Contains, "~"),
+        (Operator::LessThan, "<"),
+        (Operator::GreaterThan, ">"),
+    ];
+
+    fn symbol(&self) -> &'static str {
+        Operator::ALL
+            .iter()
+            .find(|(operator, _)| operator == self)
+            .map_or("?", |(_, symbol)| symbol)
+    }
+}
+
+impl Condition {
+    fn matches(&self, song: &Song) -> bool {
+        if self.field.is_numeric() {
+            let actual = self.field.number(song);
+            let Some(expected) = parse_number(&self.value) else {
+                return false;
+            };
+            match self.operator {
+                Operator::Is => actual == expected,
+                Operator::IsNot => actual != expected,
+                Operator::LessThan => actual < expected,
+                Operator::AtMost => actual <= expected,
+                Operator::GreaterThan => actual > expected,
+                Operator::AtLeast => actual >= expected,
+                Operator::Contains | Operator::DoesNotContain => false,
+            }
+        } else {
+            let actual = self.field.text(song).to_lowercase();
+            let expected = self.value.to_lowercase();
+            match self.operator {
+                Operator::Is => actual == expected,
+                Operator::IsNot => actual != expected,
+                Operator::Contains => actual.contains(&expected),
+                Operator::DoesNotContain => !actual.contains(&expected),
+                Operator::LessThan => actual < expected,
+                Operator::AtMost => actual <= expected,
+                Operator::GreaterThan => actual > expected,
+                Operator::AtLeast => actual >= expected,
+            }
+        }
+    }
+}
+
+impl Rule {
+    pub fn matches(&self, song: &Song) -> bool {
+        match self {
+            Rule::All(rules) => rules.iter().all(|rule| rule.matches(song)),
+            Rule::Any(rules) => rules.iter().any(|rule| rule.matches(song)),
+            Rule::Condition(condition) => condition.matches(song),
+        }
+    }
+
+    fn uses(&self, field: SongField) -> bool {
+        match self {
+            Rule::All(rules) | Rule::Any(rules) => rules.iter().any(|rule| rule.uses(field)),
+            Rule::Condition(condition) => condition.field == field,
+        }
+    }
+}
+
+impl SmartQuery {
+    pub fn parse(query: &str) -> Result<SmartQuery> {
+        let mut parser = Parser {
+            tokens: tokenize(query)?,
+            position: 0,
+        };
+        let rule = parser.rule()?;
+
+        let mut smart_query = SmartQuery {
+            rule,
+            sort_by: None,
+            descending: false,
+            limit: None,
+        };
+        while let Some(keyword) = parser.next_word() {
+            match keyword.to_ascii_uppercase().as_str() {
+                "SORT" => {
+                    parser.expect_keyword("BY")?;
+                    let field = parser
+                        .next_word()
+                        .ok_or_else(|| anyhow!("expected a field"))?;
+                    smart_query.sort_by = Some(
+                        SongField::from_name(&field)
+                            .ok_or_else(|| anyhow!("unknown field {}", field))?,
+                    );
+                    if parser.peek_keyword("DESC") {
+                        smart_query.descending = true;
+                        parser.position += 1;
+                    } else if parser.peek_keyword("ASC") {
+                        parser.position += 1;
+                    }
+                }
+                "LIMIT" => {
+                    let limit = parser
+                        .next_word()
+                        .ok_or_else(|| anyhow!("expected a limit"))?;
+                    smart_query.limit = Some(limit.parse()?);
+                }
+                _ => return Err(anyhow!("unexpected {}", keyword)),
+            }
+        }
+        // words were all taken as keywords above, so only these can be left over
+        match parser.tokens.get(parser.position) {
+            Some(Token::Open) => return Err(anyhow!("unexpected (")),
+            Some(Token::Close) => return Err(anyhow!("unexpected )")),
+            Some(Token::Operator(operator)) => {
+                return Err(anyhow!("unexpected {}", operator.symbol()))
+            }
+            _ => {}
+        }
+
+        Ok(smart_query)
+    }
+
+    /// Whether the rule or the sort order looks at `field`
+    pub fn uses(&self, field: SongField) -> bool {
+        self.sort_by == Some(field) || self.rule.uses(field)
+    }
+
+    /// Ids of all matching songs, sorted and limited
+    pub fn evaluate(&self, library: &Library) -> Vec<Uuid> {
+        let mut songs: Vec<&Song> = library
+            .songs
+            .values()
+            .filter(|song| self.rule.matches(song))
+            .collect();
+
+        songs.sort_by(|a, b| {
+            let default_order = SongField::Artist
+                .compare(a, b)
+                .then_with(|| SongField::Album.compare(a, b))
+                .then_with(|| a.start_offset().cmp(&b.start_offset()))
+                .then_with(|| SongField::Title.compare(a, b));
+            match self.sort_by {
+                Some(field) if self.descending => field.compare(b, a).then(default_order),
+                Some(field) => field.compare(a, b).then(default_order),
+                None => default_order,
+            }
+        });
+
+        songs
+            .into_iter()
+            .take(self.limit.unwrap_or(usize::MAX))
+            .map(|song| song.id)
+            .collect()
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (rules, separator) = match self {
+            Rule::Condition(condition) => {
+                let value = if condition.value.is_empty()
+                    || condition
+                        .value
+                        .contains(|c: char| c.is_whitespace() || "()\"=!~<>".contains(c))
+                {
+                    format!("\"{}\"", condition.value)
+                } else {
+                    condition.value.clone()
+                };
+                return write!(
+                    f,
+                    "{} {} {}",
+                    condition.field.name(),
+                    condition.operator.symbol(),
+                    value
+                );
+            }
+            Rule::All(rules) => (rules, " AND "),
+            Rule::Any(rules) => (rules, " OR "),
+        };
+
+        for (index, rule) in rules.iter().enumerate() {
+            if index > 0 {
+                f.write_str(separator)?;
+            }
+            match rule {
+                Rule::Condition(_) => write!(f, "{}", rule)?,
+                _ => write!(f, "({})", rule)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SmartQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.rule)?;
+        if let Some(field) = self.sort_by {
+            write!(f, " SORT BY {}", field.name())?;
+            if self.descending {
+                write!(f, " DESC")?;
+            }
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " LIMIT {}", limit)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Operator(Operator),
+    Word(String),
+    Quoted(String),
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = query.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            rest = &rest[1..];
+        } else if c == '"' {
+            let end = rest[1..]
+                .find('"')
+                .ok_or_else(|| anyhow!("unterminated quote"))?;
+            tokens.push(Token::Quoted(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else if let Some((operator, symbol)) = Operator::ALL
+            .iter()
+            .find(|(_, symbol)| rest.starts_with(symbol))
+        {
+            tokens.push(Token::Operator(*operator));
+            rest = &rest[symbol.len()..];
+        } else {
+            let end = rest
+                .char_indices()
+                .find(|&(index, c)| {
+                    c.is_whitespace()
+                        || "()\"=~<>".contains(c)
+                        // a lone `!` is part of the word, only `!=` and `!~` end it
+                        || (c == '!' && rest[index + 1..].starts_with(['=', '~']))
+                })
+                .map_or(rest.len(), |(index, _)| index);
+            tokens.push(Token::Word(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if !self.peek_keyword(keyword) {
+            return Err(anyhow!("expected {}", keyword));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn next_word(&mut self) -> Option<String> {
+        match self.tokens.get(self.position)? {
+            Token::Word(word) | Token::Quoted(word) => {
+                self.position += 1;
+                Some(word.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// rule := all (OR all)*
+    fn rule(&mut self) -> Result<Rule> {
+        let mut rules = vec![self.all()?];
+        while self.peek_keyword("OR") {
+            self.position += 1;
+            rules.push(self.all()?);
+        }
+        Ok(flatten(rules, Rule::Any))
+    }
+
+    /// all := term (AND term)*
+    fn all(&mut self) -> Result<Rule> {
+        let mut rules = vec![self.term()?];
+        while self.peek_keyword("AND") {
+            self.position += 1;
+            rules.push(self.term()?);
+        }
+        Ok(flatten(rules, Rule::All))
+    }
+
+    /// term := "(" rule ")" | field operator value
+    fn term(&mut self) -> Result<Rule> {
+        if self.tokens.get(self.position) == Some(&Token::Open) {
+            self.position += 1;
+            let rule = self.rule()?;
+            if self.tokens.get(self.position) != Some(&Token::Close) {
+                return Err(anyhow!("missing )"));
+            }
+            self.position += 1;
+            return Ok(rule);
+        }
+
+        let field_name = self
+            .next_word()
+            .ok_or_else(|| anyhow!("expected a field"))?;
+        let field = SongField::from_name(&field_name).ok_or_else(|| {
+            anyhow!(
+                "unknown field {} (use title, artist, album, genre, track, year, duration, rating, plays, added, played or skips)",
+                field_name
+            )
+        })?;
+        let operator = match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) => *operator,
+            _ => return Err(anyhow!("expected an operator after {}", field_name)),
+        };
+        self.position += 1;
+        let value = self
+            .next_word()
+            .ok_or_else(|| anyhow!("expected a value after {}", operator.symbol()))?;
+
+        if field.is_numeric() {
+            if parse_number(&value).is_none() {
+                return Err(anyhow!("{} needs a number, not {}", field_name, value));
+            }
+            if matches!(operator, Operator::Contains | Operator::DoesNotContain) {
+                return Err(anyhow!(
+                    "{} cannot be used with {}",
+                    operator.symbol(),
+                    field_name
+                ));
+            }
+        }
+
+        Ok(Rule::Condition(Condition {
+            field,
+            operator,
+            value,
+        }))
+    }
+}
+
+fn flatten(mut rules: Vec<Rule>, group: fn(Vec<Rule>) -> Rule) -> Rule {
+    if rules.len() == 1 {
+        rules.remove(0)
+    } else {
+        group(rules)
+    }
+}
+
+/// Plain numbers, or `m:ss` for durations
+fn parse_number(value: &str) -> Option<u64> {
+    match value.split_once(':') {
+        Some((minutes, seconds)) => {
+            Some(minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?)
+        }
+        None => value.parse().ok(),
+    }
+}
+
+fn days_since(unix_secs: u64) -> u64 {
+    if unix_secs == 0 {
+        // never played, or scanned before the date was recorded: never counts as recent
+        return u64::MAX;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    now.saturating_sub(unix_secs) / (24 * 60 * 60)
+}
+
+impl Library {
+    pub fn smart_playlist(&self, id: Uuid) -> Option<&SmartPlaylist> {
+        self.smart_playlists
+            .iter()
+            .find(|playlist| playlist.id == id)
+    }
+
+    pub fn create_smart_playlist(&mut self, name: String, query: SmartQuery) -> Uuid {
+        let mut playlist = SmartPlaylist {
+            id: Uuid::new_v4(),
+            name,
+            query,
+            song_ids: Vec::new(),
+        };
+        playlist.song_ids = playlist.query.evaluate(self);
+        let id = playlist.id;
+        self.smart_playlists.push(playlist);
+        id
+    }
+
+    pub fn set_smart_query(&mut self, id: Uuid, query: SmartQuery) -> Result<()> {
+        let song_ids = query.evaluate(self);
+        let playlist = self
+            .smart_playlists
+            .iter_mut()
+            .find(|playlist| playlist.id == id)
+            .ok_or_else(|| anyhow!("smart playlist {} does not exist", id))?;
+        playlist.query = query;
+        playlist.song_ids = song_ids;
+        Ok(())
+    }
+
+    /// Stable, so songs that tie keep their previous (e.g. relevance) order
+    pub fn sort_songs(&self, song_ids: &mut [Uuid], field: SongField, descending: bool) {
+        let key = |id: &Uuid| {
+            self.songs
+                .get(id)
+                .map_or((0, String::new()), |song| field.sort_key(song))
+        };
+        if descending {
+            song_ids.sort_by_cached_key(|id| Reverse(key(id)));
+        } else {
+            song_ids.sort_by_cached_key(key);
+        }
+    }
+
+    /// Re-runs every smart playlist's rule, needed whenever the songs change
+    pub fn refresh_smart_playlists(&mut self) {
+        self.refresh_smart_playlists_where(|_| true);
+    }
+
+    /// Re-runs the smart playlists that look at one of `fields`, enough when only those
+    /// fields of some songs changed
+    pub fn refresh_smart_playlists_using(&mut self, fields: &[SongField]) {
+        self.refresh_smart_playlists_where(|query| fields.iter().any(|field| query.uses(*field)));
+    }
+
+    fn refresh_smart_playlists_where(&mut self, outdated: impl Fn(&SmartQuery) -> bool) {
+        let results: Vec<Option<Vec<Uuid>>> = self
+            .smart_playlists
+            .iter()
+            .map(|playlist| outdated(&playlist.query).then(|| playlist.query.evaluate(self)))
+            .collect();
+        for (playlist, song_ids) in self.smart_playlists.iter_mut().zip(results) {
+            if let Some(song_ids) = song_ids {
+                playlist.song_ids = song_ids;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::Artist;
+
+    fn condition(field: SongField, operator: Operator, value: &str) -> Rule {
+        Rule::Condition(Condition {
+            field,
+            operator,
+            value: value.to_string(),
+        })
+    }
+
+    fn song(title: &str, artist: &str, year: u16) -> Song {
+        Song {
+            title: title.to_string(),
+            artists: vec![Artist {
+                id: Uuid::new_v4(),
+                name: artist.to_string(),
+            }],
+            year,
+            ..Song::default()
+        }
+    }
+
+    #[test]
+    fn lone_exclamation_mark_is_part_of_a_word() {
+        let query = SmartQuery::parse("title ~ wow!").unwrap();
+        assert_eq!(
+            query.rule,
+            condition(SongField::Title, Operator::Contains, "wow!")
+        );
+
+        let query = SmartQuery::parse("title = ! AND artist != !!!").unwrap();
+        assert_eq!(
+            query.rule,
+            Rule::All(vec![
+                condition(SongField::Title, Operator::Is, "!"),
+                condition(SongField::Artist, Operator::IsNot, "!!!"),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_nested_rules_sort_and_limit() {
+        let query = SmartQuery::parse(
+            "genre = Jazz AND (year < 1970 OR rating >= 4) SORT BY year DESC LIMIT 50",
+        )
+        .unwrap();
+        assert_eq!(
+            query,
+            SmartQuery {
+                rule: Rule::All(vec![
+                    condition(SongField::Genre, Operator::Is, "Jazz"),
+                    Rule::Any(vec![
+                        condition(SongField::Year, Operator::LessThan, "1970"),
+                        condition(SongField::Rating, Operator::AtLeast, "4"),
+                    ]),
+                ]),
+                sort_by: Some(SongField::Year),
+                descending: true,
+                limit: Some(50),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_quoted_values_and_negated_operators() {
+        let query = SmartQuery::parse(r#"artist != "Miles Davis" AND title !~ "(live)""#).unwrap();
+        assert_eq!(
+            query.rule,
+            Rule::All(vec![
+                condition(SongField::Artist, Operator::IsNot, "Miles Davis"),
+                condition(SongField::Title, Operator::DoesNotContain, "(live)"),
+            ])
+        );
+
+        let query = SmartQuery::parse("album=\"\"").unwrap();
+        assert_eq!(query.rule, condition(SongField::Album, Operator::Is, ""));
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        for text in [
+            "genre = Jazz AND (year < 1970 OR rating >= 4) SORT BY year DESC LIMIT 50",
+            r#"artist != "Miles Davis" OR title !~ "a=b""#,
+            "title ~ wow! SORT BY plays",
+            "duration <= 3:30 AND played > 30 AND skips >= 2",
+        ] {
+            let query = SmartQuery::parse(text).unwrap();
+            assert_eq!(SmartQuery::parse(&query.to_string()).unwrap(), query);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        for text in [
+            "",
+            "title",
+            "title =",
+            "colour = red",
+            "year = soon",
+            "year ~ 19",
+            "(title = a",
+            "title = a)",
+            "title = \"open",
+            "title = a SORT BY colour",
+            "title = a LIMIT many",
+            "title = a AND",
+        ] {
+            assert!(SmartQuery::parse(text).is_err(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn knows_the_fields_it_uses() {
+        let query = SmartQuery::parse("genre = Jazz AND (plays > 3 OR rating >= 4) SORT BY played")
+            .unwrap();
+        for field in [
+            SongField::Genre,
+            SongField::Plays,
+            SongField::Rating,
+            SongField::DaysAgoPlayed,
+        ] {
+            assert!(query.uses(field), "{:?}", field);
+        }
+        assert!(!query.uses(SongField::Skips));
+        assert!(!query.uses(SongField::Title));
+    }
+
+    #[test]
+    fn matches_songs() {
+        let kind_of_blue = song("So What", "Miles Davis", 1959);
+        let rule = SmartQuery::parse("artist ~ miles AND year < 1960 AND title !~ live")
+            .unwrap()
+            .rule;
+        assert!(rule.matches(&kind_of_blue));
+        assert!(!rule.matches(&song("So What (live)", "Miles Davis", 1959)));
+        assert!(!rule.matches(&song("Tutu", "Miles Davis", 1986)));
+    }
+}
@@ -0,0 +1,544 @@
+use anyhow::Result;
+use serde::{Serialize, Serializer};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    hash::Hash,
+    path::Path,
+    time::Duration,
+};
+use uuid::Uuid;
+
+use super::{unix_now, Library, Play, Song};
+
+/// How many entries each top list keeps
+const TOP_COUNT: usize = 10;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// Bitrate groups, by the bitrate in kbps they stay below
+const BITRATE_GROUPS: [(u32, &str); 5] = [
+    (128, "under 128 kbps"),
+    (192, "128 - 191 kbps"),
+    (256, "192 - 255 kbps"),
+    (320, "256 - 319 kbps"),
+    (u32::MAX, "320 kbps and up"),
+];
+
+/// How far back the stats screen looks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatsPeriod {
+    Week,
+    #[default]
+    Month,
+    Year,
+    AllTime,
+}
+
+impl StatsPeriod {
+    pub const ALL: [StatsPeriod; 4] = [
+        StatsPeriod::Week,
+        StatsPeriod::Month,
+        StatsPeriod::Year,
+        StatsPeriod::AllTime,
+    ];
+
+    /// Unix seconds the period starts at
+    pub fn start(&self) -> u64 {
+        let days = match self {
+            StatsPeriod::Week => 7,
+            StatsPeriod::Month => 30,
+            StatsPeriod::Year => 365,
+            StatsPeriod::AllTime => return 0,
+        };
+        unix_now().saturating_sub(days * DAY_SECS)
+    }
+}
+
+impl fmt::Display for StatsPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StatsPeriod::Week => "last 7 days",
+            StatsPeriod::Month => "last 30 days",
+            StatsPeriod::Year => "last 365 days",
+            StatsPeriod::AllTime => "all time",
+        })
+    }
+}
+
+/// An artist, album, song or genre in a top list
+#[derive(Debug, Clone, Serialize)]
+pub struct Ranked {
+    pub name: String,
+    pub plays: usize, // skips left out
+    #[serde(rename = "listened_seconds", serialize_with = "as_seconds")]
+    pub listened: Duration,
+}
+
+/// What was listened to during a period, according to the play history
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ListeningStats {
+    pub plays: usize,
+    pub skips: usize,
+    #[serde(rename = "listened_seconds", serialize_with = "as_seconds")]
+    pub listened: Duration,
+    pub top_artists: Vec<Ranked>,
+    pub top_albums: Vec<Ranked>,
+    pub top_songs: Vec<Ranked>,
+    pub top_genres: Vec<Ranked>,
+}
+
+/// What the library is made of
+#[derive(Debug, Clone, Default)]
+pub struct LibraryComposition {
+    pub songs: usize,
+    pub duration: Duration,
+    pub size: u64, // bytes, files shared by cue sheet tracks counted once
+    pub formats: Vec<(String, usize)>, // file extension and song count, most songs first
+    pub bitrates: Vec<(String, usize)>, // bitrate group and song count, lowest bitrate first
+}
+
+/// A year of listening, summed up
+#[derive(Debug, Clone, Serialize)]
+pub struct Wrapped {
+    pub year: i32,
+    pub days_listened: usize,
+    pub songs_discovered: usize, // songs first played this year
+    #[serde(flatten)]
+    pub listening: ListeningStats,
+}
+
+/// Plays and listening time of one top list entry
+#[derive(Default)]
+struct Tally {
+    plays: usize,
+    listened: Duration,
+}
+
+impl Tally {
+    fn add(&mut self, play: &Play) {
+        if !play.skipped {
+            self.plays += 1;
+        }
+        self.listened += play.played;
+    }
+}
+
+impl Library {
+    /// Sums up the plays that started in `since..until`, unix seconds. Plays of songs that
+    /// are no longer in the library count towards the totals but not the top lists.
+    pub fn listening_stats(&self, history: &[Play], since: u64, until: u64) -> ListeningStats {
+        let mut stats = ListeningStats::default();
+        let mut artists: HashMap<Uuid, (String, Tally)> = HashMap::new();
+        let mut albums: HashMap<Uuid, (String, Tally)> = HashMap::new();
+        let mut songs: HashMap<Uuid, (String, Tally)> = HashMap::new();
+        let mut genres: HashMap<String, (String, Tally)> = HashMap::new();
+
+        let plays = history
+            .iter()
+            .filter(|play| (since..until).contains(&play.started_at));
+        for play in plays {
+            if play.skipped {
+                stats.skips += 1;
+            } else {
+                stats.plays += 1;
+            }
+            stats.listened += play.played;
+
+            let Some(song) = self.songs.get(&play.song_id) else {
+                continue;
+            };
+            for artist in &song.artists {
+                tally(&mut artists, artist.id, || artist.name.clone(), play);
+            }
+            if let Some(album) = &song.album {
+                let name = || format!("{} - {}", album.title, album.artist.name);
+                tally(&mut albums, album.id, name, play);
+            }
+            tally(&mut songs, song.id, || song_name(song), play);
+            tally(&mut genres, song.genre.clone(), || song.genre.clone(), play);
+        }
+
+        stats.top_artists = top(artists);
+        stats.top_albums = top(albums);
+        stats.top_songs = top(songs);
+        stats.top_genres = top(genres);
+        stats
+    }
+
+    pub fn composition(&self) -> LibraryComposition {
+        let mut formats: HashMap<String, usize> = HashMap::new();
+        let mut bitrates = vec![0; BITRATE_GROUPS.len()];
+        let mut unknown_bitrates = 0;
+        let mut files = HashSet::new();
+        let mut composition = LibraryComposition {
+            songs: self.songs.len(),
+            ..LibraryComposition::default()
+        };
+
+        for song in self.songs.values() {
+            composition.duration += song.duration;
+            if files.insert(&song.file_path) {
+                composition.size += song.file_size;
+            }
+            let format = song
+                .file_path
+                .extension()
+                .map_or(String::from("none"), |extension| {
+                    extension.to_string_lossy().to_uppercase()
+                });
+            *formats.entry(format).or_default() += 1;
+            match song.bitrate {
+                Some(bitrate) => {
+                    let group = BITRATE_GROUPS
+                        .iter()
+                        .position(|(below, _)| bitrate < *below)
+                        .unwrap_or(BITRATE_GROUPS.len() - 1); // u32::MAX itself
+                    bitrates[group] += 1;
+                }
+                None => unknown_bitrates += 1,
+            }
+        }
+
+        composition.formats = formats.into_iter().collect();
+        composition
+            .formats
+            .sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+        composition.bitrates = BITRATE_GROUPS
+            .iter()
+            .map(|(_, name)| name.to_string())
+            .chain([String::from("unknown")])
+            .zip(bitrates.into_iter().chain([unknown_bitrates]))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        composition
+    }
+
+    /// The year's listening, for sharing as JSON
+    pub fn wrapped(&self, history: &[Play], year: i32) -> Wrapped {
+        let (start, end) = (year_start(year), year_start(year + 1));
+        let in_year = |play: &&Play| (start..end).contains(&play.started_at);
+
+        let days: HashSet<u64> = history
+            .iter()
+            .filter(in_year)
+            .map(|play| play.started_at / DAY_SECS)
+            .collect();
+        let mut first_plays: HashMap<Uuid, u64> = HashMap::new();
+        for play in history.iter().filter(|play| !play.skipped) {
+            let first = first_plays.entry(play.song_id).or_insert(play.started_at);
+            *first = (*first).min(play.started_at);
+        }
+
+        Wrapped {
+            year,
+            days_listened: days.len(),
+            songs_discovered: first_plays
+                .values()
+                .filter(|first| (start..end).contains(*first))
+                .count(),
+            listening: self.listening_stats(history, start, end),
+        }
+    }
+
+    /// Writes the year's summary to `path` as JSON
+    pub fn export_wrapped(&self, history: &[Play], year: i32, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.wrapped(history, year))?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+fn tally<K: Hash + Eq>(
+    tallies: &mut HashMap<K, (String, Tally)>,
+    key: K,
+    name: impl FnOnce() -> String,
+    play: &Play,
+) {
+    tallies
+        .entry(key)
+        .or_insert_with(|| (name(), Tally::default()))
+        .1
+        .add(play);
+}
+
+/// The most played entries, then the longest listened to, leaving out ones only ever skipped
+fn top<K>(tallies: HashMap<K, (String, Tally)>) -> Vec<Ranked> {
+    let mut ranked: Vec<Ranked> = tallies
+        .into_values()
+        .filter(|(_, tally)| tally.plays > 0)
+        .map(|(name, tally)| Ranked {
+            name,
+            plays: tally.plays,
+            listened: tally.listened,
+        })
+        .collect();
+    ranked.sort_by(|a, b| {
+        b.plays
+            .cmp(&a.plays)
+            .then_with(|| b.listened.cmp(&a.listened))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    ranked.truncate(TOP_COUNT);
+    ranked
+}
+
+fn song_name(song: &Song) -> String {
+    match song.artists.first() {
+        Some(artist) => format!("{} - {}", song.title, artist.name),
+        None => song.title.clone(),
+    }
+}
+
+fn as_seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_secs())
+}
+
+/// Unix seconds of midnight on the first of January (UTC)
+fn year_start(year: i32) -> u64 {
+    // days from 1970 to the year, counting the leap days of the years before it
+    let years = year as i64 - 1970;
+    let leap_days = |year: i64| year / 4 - year / 100 + year / 400;
+    let days = years * 365 + leap_days(year as i64 - 1) - leap_days(1969);
+    (days.max(0) as u64) * DAY_SECS
+}
+
+/// The year it is now (UTC)
+pub fn current_year() -> i32 {
+    let now = unix_now();
+    let mut year = 1970 + (now / (365 * DAY_SECS)) as i32;
+    while year_start(year) > now {
+        year -= 1;
+    }
+    while year_start(year + 1) <= now {
+        year += 1;
+    }
+    year
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{Album, Artist};
+    use std::{env, path::PathBuf};
+
+    const DAY: u64 = DAY_SECS;
+
+    /// Low's "Things We Lost" (two cue sheet tracks sharing a file) and Talk Talk's
+    /// "Laughing Stock" (one song)
+    fn test_library() -> (Library, [Uuid; 3]) {
+        let mut library = Library::new();
+        let low = Artist::new(String::from("Low"));
+        let talk_talk = Artist::new(String::from("Talk Talk"));
+        let songs = [
+            (
+                "Closer",
+                &low,
+                "Things We Lost",
+                "slowcore",
+                "album.flac",
+                Some(900),
+            ),
+            (
+                "Embrace",
+                &low,
+                "Things We Lost",
+                "slowcore",
+                "album.flac",
+                Some(900),
+            ),
+            (
+                "Myrrhman",
+                &talk_talk,
+                "Laughing Stock",
+                "post-rock",
+                "02.mp3",
+                Some(192),
+            ),
+        ];
+        let ids = songs.map(|(title, artist, album, genre, file, bitrate)| {
+            let song = Song {
+                id: Uuid::new_v4(),
+                title: title.to_string(),
+                artists: vec![artist.clone()],
+                album: Album::new(Some(album.to_string()), artist.clone()),
+                genre: genre.to_string(),
+                file_path: PathBuf::from("/music").join(album).join(file),
+                file_size: 1_000,
+                bitrate,
+                duration: Duration::from_secs(300),
+                ..Song::default()
+            };
+            let id = song.id;
+            library.songs.insert(id, song);
+            id
+        });
+        (library, ids)
+    }
+
+    fn play(song_id: Uuid, started_at: u64, played: u64, skipped: bool) -> Play {
+        Play {
+            song_id,
+            started_at,
+            played: Duration::from_secs(played),
+            skipped,
+        }
+    }
+
+    fn names(ranked: &[Ranked]) -> Vec<(&str, usize, u64)> {
+        ranked
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.plays, entry.listened.as_secs()))
+            .collect()
+    }
+
+    #[test]
+    fn sums_up_plays_within_the_period() {
+        let (library, [closer, embrace, myrrhman]) = test_library();
+        let history = [
+            play(closer, 10 * DAY, 300, false), // before the period
+            play(closer, 20 * DAY, 300, false),
+            play(embrace, 21 * DAY, 300, false),
+            play(myrrhman, 22 * DAY, 300, false),
+            play(myrrhman, 22 * DAY + 400, 20, true),
+            play(Uuid::new_v4(), 23 * DAY, 100, false), // no longer in the library
+            play(embrace, 30 * DAY, 300, false),        // the period's end is left out
+        ];
+
+        let stats = library.listening_stats(&history, 20 * DAY, 30 * DAY);
+        assert_eq!((stats.plays, stats.skips), (4, 1));
+        assert_eq!(stats.listened, Duration::from_secs(1_020));
+
+        assert_eq!(
+            names(&stats.top_artists),
+            [("Low", 2, 600), ("Talk Talk", 1, 320)]
+        );
+        assert_eq!(
+            names(&stats.top_albums),
+            [
+                ("Things We Lost - Low", 2, 600),
+                ("Laughing Stock - Talk Talk", 1, 320)
+            ]
+        );
+        assert_eq!(
+            names(&stats.top_songs),
+            [
+                ("Myrrhman - Talk Talk", 1, 320),
+                ("Closer - Low", 1, 300),
+                ("Embrace - Low", 1, 300)
+            ]
+        );
+        assert_eq!(
+            names(&stats.top_genres),
+            [("slowcore", 2, 600), ("post-rock", 1, 320)]
+        );
+
+        let all_time = library.listening_stats(&history, 0, u64::MAX);
+        assert_eq!(all_time.plays, 6);
+        assert_eq!(names(&all_time.top_artists)[0], ("Low", 4, 1_200));
+    }
+
+    #[test]
+    fn leaves_out_entries_only_skipped() {
+        let (library, [closer, _, myrrhman]) = test_library();
+        let history = [
+            play(closer, DAY, 300, false),
+            play(myrrhman, DAY, 10, true),
+            play(myrrhman, DAY, 10, true),
+        ];
+        let stats = library.listening_stats(&history, 0, 2 * DAY);
+        assert_eq!((stats.plays, stats.skips), (1, 2));
+        assert_eq!(names(&stats.top_artists), [("Low", 1, 300)]);
+        assert_eq!(names(&stats.top_genres), [("slowcore", 1, 300)]);
+    }
+
+    #[test]
+    fn starts_periods_in_the_past() {
+        let now = unix_now();
+        assert_eq!(StatsPeriod::AllTime.start(), 0);
+        assert!((now - 7 * DAY..=now - 7 * DAY + 5).contains(&StatsPeriod::Week.start()));
+        assert!(StatsPeriod::Year.start() < StatsPeriod::Month.start());
+        assert!(StatsPeriod::Month.start() < StatsPeriod::Week.start());
+    }
+
+    #[test]
+    fn finds_the_start_of_years() {
+        assert_eq!(year_start(1970), 0);
+        assert_eq!(year_start(1971), 365 * DAY);
+        assert_eq!(year_start(2000), 946_684_800);
+        assert_eq!(year_start(2001), 978_307_200); // 2000 was a leap year
+        assert_eq!(year_start(2024), 1_704_067_200);
+        assert_eq!(year_start(2101), 4_133_980_800); // 2100 is not a leap year
+
+        let year = current_year();
+        assert!((year_start(year)..year_start(year + 1)).contains(&unix_now()));
+    }
+
+    #[test]
+    fn exports_the_yearly_summary() {
+        let (library, [closer, embrace, myrrhman]) = test_library();
+        let year = year_start(2024);
+        let history = [
+            play(closer, year - DAY, 300, false), // 2023
+            play(closer, year + 3_600, 300, false),
+            play(embrace, year + 7_200, 300, false),
+            play(myrrhman, year + 40 * DAY, 30, true),
+            play(embrace, year_start(2025), 300, false), // 2025
+        ];
+
+        let wrapped = library.wrapped(&history, 2024);
+        assert_eq!(wrapped.days_listened, 2);
+        // Closer was first played the year before and Myrrhman was only skipped
+        assert_eq!(wrapped.songs_discovered, 1);
+        assert_eq!((wrapped.listening.plays, wrapped.listening.skips), (2, 1));
+
+        let path = env::temp_dir().join(format!("jukebox-wrapped-{}.json", Uuid::new_v4()));
+        library.export_wrapped(&history, 2024, &path).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(json["year"], 2024);
+        assert_eq!(json["days_listened"], 2);
+        assert_eq!(json["songs_discovered"], 1);
+        assert_eq!(json["plays"], 2);
+        assert_eq!(json["listened_seconds"], 630);
+        assert_eq!(json["top_artists"][0]["name"], "Low");
+        assert_eq!(json["top_artists"][0]["listened_seconds"], 600);
+        assert_eq!(json["top_albums"][0]["name"], "Things We Lost - Low");
+    }
+
+    #[test]
+    fn describes_the_library() {
+        let (mut library, [_, _, myrrhman]) = test_library();
+        let mut unknown = library.songs[&myrrhman].clone();
+        unknown.id = Uuid::new_v4();
+        unknown.file_path = PathBuf::from("/music/untitled");
+        unknown.bitrate = None;
+        library.songs.insert(unknown.id, unknown);
+
+        let composition = library.composition();
+        assert_eq!(composition.songs, 4);
+        assert_eq!(composition.duration, Duration::from_secs(1_200));
+        // the cue tracks share a file
+        assert_eq!(composition.size, 3_000);
+        let formats: Vec<(&str, usize)> = composition
+            .formats
+            .iter()
+            .map(|(format, count)| (format.as_str(), *count))
+            .collect();
+        assert_eq!(formats, [("FLAC", 2), ("MP3", 1), ("none", 1)]);
+        let bitrates: Vec<(&str, usize)> = composition
+            .bitrates
+            .iter()
+            .map(|(group, count)| (group.as_str(), *count))
+            .collect();
+        assert_eq!(
+            bitrates,
+            [
+                ("192 - 255 kbps", 1),
+                ("320 kbps and up", 2),
+                ("unknown", 1)
+            ]
+        );
+    }
+}
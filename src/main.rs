@@ -2,29 +2,77 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod audio;
+mod cli;
+mod daemon;
+mod keymap;
 mod library;
+mod player;
+mod scrobble;
+mod server;
 mod ui;
 
-use anyhow::Result;
-use library::{Library, Song};
+use anyhow::{anyhow, Result};
+use audio::{MediaControls, MEDIA_CONTROLS_CHECK_INTERVAL};
+use clap::Parser;
+use cli::{open_files, Cli, Launch};
+use keymap::{default_keymap, Action, Keymap};
+use library::{
+    cover_art, current_year, read_history, Library, LibraryComposition, ListeningStats,
+    SearchQuery, SmartQuery, Song, SongField, StatsPeriod,
+};
 use parking_lot::Mutex;
-use rodio::Sink;
+use player::{OpenRequest, Player, PlayerCommand, SEEK_STEP, TICK_INTERVAL};
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
-// use souvlaki::{MediaControlEvent, MediaControls, MediaMetadata, PlatformConfig};
 use std::{
-    collections::VecDeque, fs, io::BufReader, path::PathBuf, str::FromStr, sync::Arc,
-    time::Duration,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
-use ui::{loading_ui, main_ui, settings_ui};
+use tokio::task;
+use ui::{
+    album_ui, artist_ui, artists_ui, genre_ui, genres_ui, load_user_themes, loading_ui, main_ui,
+    playlists_ui, search_input_id, settings_ui, shortcut_help_ui, song_table_id, stats_ui,
+    theme_files_state, ColorTheme, USER_THEMES_DIR,
+};
+use uuid::Uuid;
 
+use iced::keyboard::{self, Key, Modifiers};
+use iced::widget::image;
+use iced::widget::scrollable::{self, RelativeOffset, Viewport};
+use iced::widget::text_input;
+use iced::window;
 use iced::{executor, Application, Command, Element, Settings, Subscription, Theme};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 struct GlobalSettings {
-    folder_to_scan: String, // TODO add ability to scan multiple folders
-    library_file: String,   // where the serialized library is saved
-                            // theme: VisualTheme
+    folder_to_scan: String,        // TODO add ability to scan multiple folders
+    library_file: String,          // where the serialized library is saved
+    history_file: String,          // log of every play, empty to keep none
+    theme: String,                 // name of a built-in or user theme
+    resume_min_duration_mins: u64, // songs at least this long remember their position
+    resume_genres: Vec<String>,    // songs in these genres always remember their position
+    add_opened_files: bool,        // add songs opened from elsewhere to the library
+    control_socket: String,        // where the command line reaches a running jukebox
+    mpd_address: String,           // where the MPD server listens, empty to disable it
+    http_address: String,          // where the HTTP API listens, empty to disable it
+    subsonic_address: String,      // where the Subsonic API listens, empty to disable it
+    subsonic_username: String,
+    subsonic_password: String, // kept as it is, Subsonic tokens are made from it
+    subsonic_transcoder: String, // ffmpeg or a compatible command, empty to never transcode
+    lastfm_api_key: String,    // from a Last.fm API account, empty to not scrobble there
+    lastfm_api_secret: String,
+    lastfm_session_key: String, // asked for with the username and password when empty
+    lastfm_username: String,
+    lastfm_password: String,
+    lastfm_endpoint: String,
+    listenbrainz_token: String, // empty to not submit listens to ListenBrainz
+    listenbrainz_endpoint: String,
+    scrobble_queue_file: String, // scrobbles waiting to be sent again, e.g. while offline
+    keymap: BTreeMap<String, String>, // action name to key, e.g. `next_song = "ctrl+right"`
 }
 
 impl Default for GlobalSettings {
@@ -32,21 +80,41 @@ impl Default for GlobalSettings {
         Self {
             folder_to_scan: String::from("./"),
             library_file: String::from("library.toml"),
+            history_file: String::from("history.jsonl"),
+            theme: String::from("Dark"),
+            resume_min_duration_mins: 20,
+            resume_genres: vec![String::from("Audiobook"), String::from("Podcast")],
+            add_opened_files: false,
+            control_socket: server::default_socket_path(),
+            mpd_address: String::from("127.0.0.1:6600"),
+            http_address: String::from("127.0.0.1:6680"),
+            subsonic_address: String::new(),
+            subsonic_username: String::from("jukebox"),
+            subsonic_password: String::new(),
+            subsonic_transcoder: String::from("ffmpeg"),
+            lastfm_api_key: String::new(),
+            lastfm_api_secret: String::new(),
+            lastfm_session_key: String::new(),
+            lastfm_username: String::new(),
+            lastfm_password: String::new(),
+            lastfm_endpoint: String::from("https://ws.audioscrobbler.com/2.0/"),
+            listenbrainz_token: String::new(),
+            listenbrainz_endpoint: String::from("https://api.listenbrainz.org"),
+            scrobble_queue_file: String::from("scrobble_queue.json"),
+            keymap: default_keymap(),
         }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-struct PlaybackSettings {
-    volume: f32, // lets leave this at 1.0 for now
-    speed: f32,  // lets leave this at 1.0 for now
-}
-
-impl Default for PlaybackSettings {
-    fn default() -> Self {
-        Self {
-            volume: 1.0,
-            speed: 1.0,
+impl GlobalSettings {
+    fn read_or_create() -> GlobalSettings {
+        let settings = fs::read_to_string("Settings.toml");
+        match settings {
+            Ok(settings) => toml::from_str(&settings).unwrap_or(GlobalSettings::default()),
+            Err(err) => {
+                println!("No Settings File: {}", err);
+                GlobalSettings::default()
+            }
         }
     }
 }
@@ -56,169 +124,472 @@ impl Default for PlaybackSettings {
 //     Dracula,
 // }
 
+/// How much the volume shortcuts change the volume, 1.0 being full volume
+const VOLUME_STEP: f32 = 0.1;
+
+/// How often the themes directory is checked for edited theme files
+const THEME_FILES_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Clone)]
 enum Message {
-    TogglePlayback,
-    PreviousSong,
-    NextSong,
+    Player(PlayerCommand),
+    CheckMediaControls,
     AddTestSongToQueue,
     PickSong(Uuid),
     Scan,
     ScanComplete(Result<(), String>),
     LoadComplete(Result<(), String>),
-    SaveSettings(GlobalSettings),
+    #[allow(dead_code)] // TODO hook up to the settings screen
+    SaveSettings(Box<GlobalSettings>),
     ChangeUI(UIState),
     TickUpdate,
+    BookmarkNameChanged(String),
+    AddBookmark,
+    RemoveBookmark(usize),
+    SetRememberPosition(Option<bool>), // `None` decides based on duration and genre
+    PlaylistPathChanged(String),
+    ImportPlaylist,
+    ExportQueue,
+    PlaylistNameChanged(String),
+    CreatePlaylist,
+    SaveQueueAsPlaylist,
+    SelectPlaylist(Uuid),
+    RenamePlaylist,
+    DeletePlaylist,
+    DuplicatePlaylist,
+    ExportPlaylist,
+    RemoveFromPlaylist(usize),    // position in the selected playlist
+    MoveInPlaylist(usize, usize), // from, to
+    EnqueuePlaylist,
+    PlayPlaylist,
+    SmartQueryChanged(String),
+    CreateSmartPlaylist,
+    UpdateSmartQuery, // replaces the selected smart playlist's rule
+    RateSong(Option<u8>),
+    ThemeChanged(String),
+    ExportTheme,
+    CheckThemeFiles,
+    KeyPressed(Key, Modifiers),
+    ShowShortcutHelp(bool),
+    SearchChanged(String),
+    SongTableScrolled(Viewport),
+    SortSongsBy(SongField), // ascending, then descending, then back to relevance
+    ToggleSongSelected(Uuid),
+    SelectAllSongs,
+    ClearSongSelection,
+    EnqueueSelection,
+    AddSelectionToPlaylist, // adds to the selected playlist
+    NavigateBack,
+    NavigateForward,
+    EnqueueAlbum(Uuid),
+    EnqueueGenre(String),
+    FileDropped(PathBuf), // onto the window, one message per file or folder
+    FilesOpened(bool),    // whether they were added to the library
+    CloseRequested,
+    StatsPeriodChanged(StatsPeriod),
+    WrappedYearChanged(i32),
+    WrappedPathChanged(String),
+    ExportWrapped,
 }
 
 #[derive(Debug, Clone)]
 enum UIState {
     Loading,
     Main, //current screen
+    Playlists,
     Settings,
-    // Artist(id) // not sure how to best implement
-    // Album(id) // not sure how to best implement
+    Artists,
+    Artist(Uuid),
+    Album(Uuid),
+    Genres,
+    Genre(String),
+    Stats,
     // Song?(id) // not sure how to best implement
 }
 
 #[derive(Clone)]
 struct Jukebox {
-    sink: Arc<Mutex<Option<Sink>>>,
+    player: Arc<Mutex<Player>>,
     global_settings: GlobalSettings,
-    playback_settings: PlaybackSettings,
     ui_state: UIState,
     theme: Theme,
-    music_library: Arc<Mutex<Library>>,
-    playback_queue: Arc<Mutex<VecDeque<(Song, bool)>>>,
-    playback_index: usize,
+    music_library: Arc<Mutex<Library>>, // the player's library
+    bookmark_name: String,
+    playlist_path: String, // file used by playlist import/export
+    selected_playlist: Option<Uuid>,
+    playlist_name: String, // name for new/renamed playlists
+    smart_query: String,
+    smart_query_error: Option<String>,
+    search_query: String,
+    search_results: Vec<Uuid>, // song ids, best match first unless `song_sort` is set
+    song_sort: Option<(SongField, bool)>, // descending when true
+    selected_songs: HashSet<Uuid>,
+    song_table_offset: f32, // scroll position of the song table, decides which rows are built
+    song_table_height: f32,
+    back_history: Vec<UIState>,
+    forward_history: Vec<UIState>,
+    cover_art: Arc<Mutex<HashMap<Uuid, Option<image::Handle>>>>, // by album id, or `Song::key` without album
+    user_themes: Vec<ColorTheme>,
+    theme_errors: Vec<String>, // one per theme file that could not be loaded
+    theme_files: Vec<(PathBuf, Option<SystemTime>)>, // to notice when the theme files change
+    keymap: Keymap,
+    show_shortcut_help: bool,
+    media_controls: Arc<Mutex<Option<MediaControls>>>, // `None` without a D-Bus session
+    launch_files: OpenRequest,                         // opened once the library has loaded
+    stats_period: StatsPeriod,
+    listening_stats: ListeningStats, // for `stats_period`, worked out when the stats screen opens
+    library_composition: LibraryComposition,
+    wrapped_year: i32,
+    wrapped_path: String, // file the yearly summary is exported to
 }
 
 impl Default for Jukebox {
     fn default() -> Self {
-        Self {
-            sink: Arc::new(Mutex::new(None)),
-            global_settings: Self::read_or_create_config(),
-            playback_settings: PlaybackSettings::default(), // TODO fetch
+        let global_settings = GlobalSettings::read_or_create();
+        let player = Player::new(global_settings.clone());
+        let mut jukebox = Self {
+            music_library: player.library(),
+            player: Arc::new(Mutex::new(player)),
+            global_settings,
             ui_state: UIState::Loading,
-            theme: Theme::Light,
-            music_library: Arc::new(Mutex::new(Library::new())),
-            playback_queue: Arc::new(Mutex::new(VecDeque::new())),
-            playback_index: 0,
+            theme: Theme::Dark,
+            bookmark_name: String::new(),
+            playlist_path: String::new(),
+            selected_playlist: None,
+            playlist_name: String::new(),
+            smart_query: String::new(),
+            smart_query_error: None,
+            search_query: String::new(),
+            search_results: Vec::new(),
+            song_sort: None,
+            selected_songs: HashSet::new(),
+            song_table_offset: 0.0,
+            song_table_height: 800.0, // until the first scroll event reports the real height
+            back_history: Vec::new(),
+            forward_history: Vec::new(),
+            cover_art: Arc::new(Mutex::new(HashMap::new())),
+            user_themes: Vec::new(),
+            theme_errors: Vec::new(),
+            theme_files: theme_files_state(Path::new(USER_THEMES_DIR)),
+            keymap: Keymap::default(),
+            show_shortcut_help: false,
+            media_controls: Arc::new(Mutex::new(None)),
+            launch_files: OpenRequest::default(),
+            stats_period: StatsPeriod::default(),
+            listening_stats: ListeningStats::default(),
+            library_composition: LibraryComposition::default(),
+            wrapped_year: current_year(),
+            wrapped_path: String::new(),
+        };
+        jukebox.reload_user_themes();
+        jukebox.keymap = Keymap::new(&jukebox.global_settings.keymap);
+        for error in &jukebox.keymap.errors {
+            println!("Keymap: {}", error);
         }
+        match MediaControls::new() {
+            Ok(media_controls) => *jukebox.media_controls.lock() = Some(media_controls),
+            Err(e) => println!("Media controls unavailable: {}", e),
+        }
+        jukebox
     }
 }
 
 // Functionality
 impl Jukebox {
-    fn toggle_sink_playback(&mut self) {
-        if self.sink.lock().as_ref().unwrap().is_paused() {
-            self.sink.lock().as_ref().unwrap().play();
-        } else {
-            self.sink.lock().as_ref().unwrap().pause()
+    fn save_library(&self) -> Result<()> {
+        self.music_library
+            .lock()
+            .save_to_file(&self.global_settings.library_file)
+    }
+
+    /// Stores the songs of a .m3u/.m3u8/.pls/.xspf file as a new playlist
+    fn import_playlist(&mut self) -> Result<()> {
+        let mut library = self.music_library.lock();
+        let imported = library.import_playlist(Path::new(&self.playlist_path))?;
+        println!(
+            "imported playlist {}: {} songs, {} not found in the library",
+            imported.name,
+            imported.song_ids.len(),
+            imported.unresolved.len()
+        );
+        let id = library.create_playlist(imported.name, imported.song_ids);
+        drop(library);
+
+        self.selected_playlist = Some(id);
+        self.save_library()
+    }
+
+    fn export_queue(&self) -> Result<()> {
+        let songs = self.player.lock().queue_songs();
+        library::write_playlist(Path::new(&self.playlist_path), "Queue", &songs)
+    }
+
+    fn selected_playlist(&self) -> Result<Uuid> {
+        self.selected_playlist
+            .ok_or_else(|| anyhow!("no playlist is selected"))
+    }
+
+    /// Runs a change against the selected playlist and saves the library afterwards
+    fn edit_selected_playlist(
+        &self,
+        change: impl FnOnce(&mut Library, Uuid) -> Result<()>,
+    ) -> Result<()> {
+        let id = self.selected_playlist()?;
+        change(&mut self.music_library.lock(), id)?;
+        self.save_library()
+    }
+
+    fn playlist_name_or(&self, fallback: &str) -> String {
+        match self.playlist_name.trim() {
+            "" => fallback.to_string(),
+            name => name.to_string(),
         }
     }
 
-    fn reorder_song_in_queue(&self, new_pos_in_queue: usize) -> Result<()> {
-        todo!()
+    fn create_playlist(&mut self, song_ids: Vec<Uuid>) -> Result<()> {
+        let name = self.playlist_name_or("New playlist");
+        let id = self.music_library.lock().create_playlist(name, song_ids);
+        self.selected_playlist = Some(id);
+        self.playlist_name.clear();
+        self.save_library()
+    }
+
+    fn save_queue_as_playlist(&mut self) -> Result<()> {
+        let song_ids = self
+            .player
+            .lock()
+            .queue
+            .iter()
+            .map(|(song, _)| song.id)
+            .filter(|id| !id.is_nil())
+            .collect();
+        self.create_playlist(song_ids)
     }
 
-    fn add_song_to_queue_end(&self, song: Song) -> Result<()> {
-        self.playback_queue.lock().push_back((song, false));
+    fn rename_playlist(&mut self) -> Result<()> {
+        let name = self.playlist_name_or("Untitled playlist");
+        self.edit_selected_playlist(|library, id| library.rename_playlist(id, name))?;
+        self.playlist_name.clear();
         Ok(())
     }
 
-    fn add_song_to_queue_start(&self, song: Song) -> Result<()> {
-        self.playback_queue.lock().push_front((song, false));
+    fn delete_playlist(&mut self) -> Result<()> {
+        self.edit_selected_playlist(|library, id| library.delete_playlist(id))?;
+        self.selected_playlist = None;
         Ok(())
     }
 
-    fn play_song_from_queue(&mut self) -> Result<()> {
-        self.replace_sink()?;
+    fn duplicate_playlist(&mut self) -> Result<()> {
+        let id = self.selected_playlist()?;
+        let copy = self.music_library.lock().duplicate_playlist(id)?;
+        self.selected_playlist = Some(copy);
+        self.save_library()
+    }
 
-        for (_song, current) in self.playback_queue.lock().iter_mut() {
-            *current = false;
-        }
+    fn export_playlist(&self) -> Result<()> {
+        let library = self.music_library.lock();
+        let id = self.selected_playlist()?;
+        let songs = library.playlist_songs(id)?;
+        library::write_playlist(
+            Path::new(&self.playlist_path),
+            &library.playlist_name(id)?,
+            &songs,
+        )
+    }
 
-        if let Some((song, _is_current)) = self.playback_queue.lock().get_mut(self.playback_index) {
-            *_is_current = true;
-
-            self.sink
-                .lock()
-                .as_ref()
-                .unwrap()
-                .append(rodio::Decoder::new(BufReader::new(std::fs::File::open(
-                    &song.file_path,
-                )?))?);
-            println!(
-                "added song: {} by {}",
-                song.title,
-                song.artists.first().unwrap().name
-            );
-        }
+    /// Parses the smart query input, keeping the error around to show next to it
+    fn parse_smart_query(&mut self) -> Result<SmartQuery> {
+        let parsed = SmartQuery::parse(&self.smart_query);
+        self.smart_query_error = parsed.as_ref().err().map(|e| e.to_string());
+        parsed
+    }
 
-        Ok(())
+    fn create_smart_playlist(&mut self) -> Result<()> {
+        let query = self.parse_smart_query()?;
+        let name = self.playlist_name_or("New smart playlist");
+        let id = self.music_library.lock().create_smart_playlist(name, query);
+        self.selected_playlist = Some(id);
+        self.playlist_name.clear();
+        self.save_library()
     }
 
-    fn replace_sink(&mut self) -> Result<()> {
-        self.kill_sink()?;
-        self.sink = Arc::new(Mutex::new(Some(audio::new_sink(self.playback_settings))));
-        println!("sink created");
+    fn update_smart_query(&mut self) -> Result<()> {
+        let query = self.parse_smart_query()?;
+        self.edit_selected_playlist(|library, id| library.set_smart_query(id, query))
+    }
+
+    fn enqueue_playlist(&self) -> Result<()> {
+        let songs = self
+            .music_library
+            .lock()
+            .playlist_songs(self.selected_playlist()?)?;
+        self.enqueue_songs(songs)
+    }
+
+    /// Replaces the queue with the selected playlist and starts playing it
+    fn play_playlist(&mut self) -> Result<()> {
+        let songs = self
+            .music_library
+            .lock()
+            .playlist_songs(self.selected_playlist()?)?;
+        self.player.lock().play_songs(songs)
+    }
+
+    /// Works out the stats screen from the play history and the library
+    fn refresh_stats(&mut self) {
+        let history = self.read_history();
+        let library = self.music_library.lock();
+        self.listening_stats =
+            library.listening_stats(&history, self.stats_period.start(), u64::MAX);
+        self.library_composition = library.composition();
+    }
+
+    fn read_history(&self) -> Vec<library::Play> {
+        if self.global_settings.history_file.is_empty() {
+            return Vec::new();
+        }
+        read_history(&self.global_settings.history_file).unwrap_or_else(|e| {
+            println!("Reading play history failed: {}", e);
+            Vec::new()
+        })
+    }
+
+    fn export_wrapped(&self) -> Result<()> {
+        let path = match self.wrapped_path.as_str() {
+            "" => format!("wrapped-{}.json", self.wrapped_year),
+            path => path.to_string(),
+        };
+        let history = self.read_history();
+        self.music_library
+            .lock()
+            .export_wrapped(&history, self.wrapped_year, Path::new(&path))?;
+        println!("Exported {} summary to {}", self.wrapped_year, path);
         Ok(())
     }
 
-    fn kill_sink(&mut self) -> Result<()> {
-        if self.sink.lock().as_ref().is_some() {
-            self.sink = Arc::new(Mutex::new(None));
-            println!("sink killed");
+    fn refresh_search(&mut self) {
+        let query = SearchQuery::parse(&self.search_query);
+        let library = self.music_library.lock();
+        self.search_results = library.search(&query);
+        if let Some((field, descending)) = self.song_sort {
+            library.sort_songs(&mut self.search_results, field, descending);
         }
+        self.selected_songs
+            .retain(|id| library.songs.contains_key(id));
+    }
+
+    fn sort_songs_by(&mut self, field: SongField) {
+        self.song_sort = match self.song_sort {
+            Some((sorted, false)) if sorted == field => Some((field, true)),
+            Some((sorted, true)) if sorted == field => None,
+            _ => Some((field, false)),
+        };
+        self.refresh_search();
+    }
+
+    /// Selected songs in the order the song table shows them
+    fn selection(&self) -> Vec<Uuid> {
+        self.search_results
+            .iter()
+            .filter(|id| self.selected_songs.contains(id))
+            .copied()
+            .collect()
+    }
+
+    fn enqueue_songs(&self, songs: Vec<Song>) -> Result<()> {
+        self.player.lock().enqueue(songs);
         Ok(())
     }
 
-    // fn stop_current_playback(&mut self) -> Result<()> {}
+    fn enqueue_selection(&self) -> Result<()> {
+        let songs: Vec<Song> = {
+            let library = self.music_library.lock();
+            self.selection()
+                .iter()
+                .filter_map(|id| library.songs.get(id).cloned())
+                .collect()
+        };
+        self.enqueue_songs(songs)
+    }
 
-    fn update_time(&mut self) {
-        let time_remaining = self
-            .playback_queue
-            .lock()
-            .get(self.playback_index)
-            .unwrap_or(&(Song::default(), false))
-            .0
-            .duration
-            // .as_secs()
-            - self
-                .sink
-                .lock()
-                .as_ref()
-                .unwrap_or(&Sink::new_idle().0)
-                .get_pos();
-        // .as_secs();
-        // println!("song duration remaining: {:?}", time_remaining);
-        if self.sink.lock().is_some() {
-            if !self.sink.lock().as_ref().unwrap().is_paused() && time_remaining <= Duration::ZERO {
-                self.next_in_queue();
-            }
+    fn add_selection_to_playlist(&self) -> Result<()> {
+        let song_ids = self.selection();
+        self.edit_selected_playlist(|library, id| {
+            song_ids
+                .into_iter()
+                .try_for_each(|song_id| library.add_to_playlist(id, song_id))
+        })
+    }
+
+    fn execute(&self, command: PlayerCommand) {
+        if let Err(e) = self.player.lock().execute(command) {
+            println!("Playback failed: {}", e);
         }
     }
 
-    fn next_in_queue(&mut self) -> Result<()> {
-        const PREVENT_SKIP_BEYOND_QUEUE_LENGTH: usize = 1;
-        if self.playback_queue.lock().len() == 0 {
-            return Ok(());
+    fn run_shortcut(&mut self, action: Action) -> Command<Message> {
+        match action {
+            Action::TogglePlayback => self.execute(PlayerCommand::TogglePlayback),
+            Action::NextSong => self.execute(PlayerCommand::NextSong),
+            Action::PreviousSong => self.execute(PlayerCommand::PreviousSong),
+            Action::SeekForward => self.execute(PlayerCommand::SeekBy(SEEK_STEP, true)),
+            Action::SeekBackward => self.execute(PlayerCommand::SeekBy(SEEK_STEP, false)),
+            Action::VolumeUp => self.execute(PlayerCommand::ChangeVolume(VOLUME_STEP)),
+            Action::VolumeDown => self.execute(PlayerCommand::ChangeVolume(-VOLUME_STEP)),
+            Action::FocusSearch => {
+                if !matches!(self.ui_state, UIState::Main) {
+                    self.navigate(UIState::Main);
+                }
+                self.show_shortcut_help = false;
+                return text_input::focus(search_input_id());
+            }
+            Action::NavigateBack => self.navigate_back(),
+            Action::NavigateForward => self.navigate_forward(),
+            Action::ToggleShortcutHelp => self.show_shortcut_help = !self.show_shortcut_help,
+            Action::CloseShortcutHelp => self.show_shortcut_help = false,
         }
-        if self.playback_index < self.playback_queue.lock().len() - PREVENT_SKIP_BEYOND_QUEUE_LENGTH
+        Command::none()
+    }
+
+    fn update_media_controls(&self) {
+        let mut media_controls = self.media_controls.lock();
+        if let Some(Err(e)) = media_controls
+            .as_mut()
+            .map(|media_controls| media_controls.update(&mut self.player.lock()))
         {
-            self.playback_index += 1;
+            println!("Media controls stopped working: {}", e);
+            *media_controls = None;
         }
-        self.play_song_from_queue()?;
-        Ok(())
     }
 
-    fn prev_in_queue(&mut self) -> Result<()> {
-        if self.playback_index > 0 {
-            self.playback_index -= 1;
+    fn navigate(&mut self, ui_state: UIState) {
+        let previous = std::mem::replace(&mut self.ui_state, ui_state);
+        self.back_history.push(previous);
+        self.forward_history.clear();
+    }
+
+    fn navigate_back(&mut self) {
+        if let Some(ui_state) = self.back_history.pop() {
+            let current = std::mem::replace(&mut self.ui_state, ui_state);
+            self.forward_history.push(current);
         }
-        self.play_song_from_queue()?;
-        Ok(())
+    }
+
+    fn navigate_forward(&mut self) {
+        if let Some(ui_state) = self.forward_history.pop() {
+            let current = std::mem::replace(&mut self.ui_state, ui_state);
+            self.back_history.push(current);
+        }
+    }
+
+    /// Cover of the song's album, read from disk the first time it is asked for
+    fn cover_art(&self, song: &Song) -> Option<image::Handle> {
+        let key = song.album.as_ref().map_or(song.key(), |album| album.id);
+        self.cover_art
+            .lock()
+            .entry(key)
+            .or_insert_with(|| cover_art(song).map(image::Handle::from_memory))
+            .clone()
     }
 
     fn load_library(&self) -> Result<(), String> {
@@ -232,43 +603,96 @@ impl Jukebox {
             .map_err(|e| e.to_string())
     }
 
-    fn scan_and_save(&mut self) -> Result<(), String> {
-        //scan
-        self.music_library = Arc::new(Mutex::new(Library::new()));
-        let _ = self
-            .music_library
-            .lock()
-            .import_dir(&self.global_settings.folder_to_scan)
-            .map_err(|e| return e.to_string());
-        //save
-        let save_path = &self.global_settings.library_file;
-        self.music_library
-            .lock()
-            .save_to_file(&save_path)
-            .map_err(|e| return format!("SaveLibrary Error: {}", e))
+    /// Reads the files off the UI thread, then queues them
+    fn open_files(&self, request: OpenRequest) -> Command<Message> {
+        let player = Arc::clone(&self.player);
+        let added_to_library = request.add_to_library;
+        Command::perform(
+            async move { task::spawn_blocking(move || open_files(&player, &request)).await },
+            move |_| Message::FilesOpened(added_to_library),
+        )
     }
 
-    fn read_or_create_config() -> GlobalSettings {
-        let settings = fs::read_to_string("Settings.toml");
-        match settings {
-            Ok(settings) => toml::from_str(&settings).unwrap_or(GlobalSettings::default()),
-            Err(err) => {
-                println!("No Settings File: {}", err);
-                GlobalSettings::default()
-            }
+    fn scan_and_save(&self) -> Result<(), String> {
+        let folder = &self.global_settings.folder_to_scan;
+        // reading the tags takes a while, so it leaves the library and playback alone...
+        let scanned = Library::scan(folder).map_err(|e| format!("Scan failed: {}", e))?;
+        // ...and is merged under the lock, keeping whatever changed in the meantime
+        let mut library = self.music_library.lock();
+        library
+            .apply_scan(scanned, folder)
+            .map_err(|e| format!("Scan failed: {}", e))?;
+        library
+            .save_to_file(&self.global_settings.library_file)
+            .map_err(|e| format!("SaveLibrary Error: {}", e))
+    }
+
+    fn save_config(&self) -> Result<()> {
+        fs::write("Settings.toml", toml::to_string(&self.global_settings)?)?;
+        Ok(())
+    }
+
+    fn color_theme(&self, name: &str) -> Result<ColorTheme> {
+        ColorTheme::all_with(&self.user_themes)
+            .into_iter()
+            .find(|theme| theme.name == name)
+            .ok_or_else(|| anyhow!("unknown theme {}", name))
+    }
+
+    fn apply_theme(&mut self, name: String) -> Result<()> {
+        self.theme = self.color_theme(&name)?.to_iced();
+        self.global_settings.theme = name;
+        Ok(())
+    }
+
+    fn change_theme(&mut self, name: String) -> Result<()> {
+        self.apply_theme(name)?;
+        self.save_config()
+    }
+
+    fn reload_user_themes(&mut self) {
+        let (themes, errors) = load_user_themes(Path::new(USER_THEMES_DIR));
+        for error in &errors {
+            println!("Loading theme failed: {}", error);
+        }
+        self.user_themes = themes;
+        self.theme_errors = errors;
+        // the current theme may be the one that was edited
+        if let Err(e) = self.apply_theme(self.global_settings.theme.clone()) {
+            println!("{}", e);
         }
     }
+
+    /// Polled, so themes can be edited while the application shows them
+    fn reload_user_themes_if_changed(&mut self) {
+        let theme_files = theme_files_state(Path::new(USER_THEMES_DIR));
+        if theme_files != self.theme_files {
+            self.theme_files = theme_files;
+            self.reload_user_themes();
+        }
+    }
+
+    fn export_theme(&self) -> Result<()> {
+        let theme = self.color_theme(&self.global_settings.theme)?;
+        let path = theme.export(Path::new(USER_THEMES_DIR))?;
+        println!("Exported theme to {}", path.display());
+        Ok(())
+    }
 }
 
 // UI/Iced
 impl Application for Jukebox {
     type Executor = executor::Default;
-    type Flags = ();
+    type Flags = Launch;
     type Message = Message;
     type Theme = Theme;
 
-    fn new(_flags: ()) -> (Self, Command<Message>) {
-        let app = Self::default();
+    fn new(launch: Launch) -> (Self, Command<Message>) {
+        let app = Self {
+            launch_files: launch.open,
+            ..Self::default()
+        };
+        server::start(&app.player, &app.global_settings, launch.control);
         (
             app.clone(),
             Command::perform(async move { app.load_library() }, Message::LoadComplete),
@@ -276,7 +700,7 @@ impl Application for Jukebox {
     }
 
     fn theme(&self) -> Theme {
-        Theme::Dark
+        self.theme.clone()
     }
 
     fn title(&self) -> String {
@@ -284,22 +708,101 @@ impl Application for Jukebox {
     }
 
     fn subscription(&self) -> Subscription<Self::Message> {
-        // TODO get key input (handle media keys)
-        const TICK_DURATION: f32 = 0.01;
+        let time = iced::time::every(TICK_INTERVAL).map(|_| Message::TickUpdate);
+
+        let theme_files =
+            iced::time::every(THEME_FILES_CHECK_INTERVAL).map(|_| Message::CheckThemeFiles);
+
+        let media_controls =
+            iced::time::every(MEDIA_CONTROLS_CHECK_INTERVAL).map(|_| Message::CheckMediaControls);
+
+        // only keys that no widget (e.g. a focused text input) used
+        let keys =
+            keyboard::on_key_press(|key, modifiers| Some(Message::KeyPressed(key, modifiers)));
 
-        let time =
-            iced::time::every(Duration::from_secs_f32(TICK_DURATION)).map(|_| Message::TickUpdate);
+        let dropped_files = iced::event::listen_with(|event, _| match event {
+            iced::Event::Window(_, window::Event::FileDropped(path)) => {
+                Some(Message::FileDropped(path))
+            }
+            iced::Event::Window(_, window::Event::CloseRequested) => Some(Message::CloseRequested),
+            _ => None,
+        });
 
-        Subscription::batch([time])
+        Subscription::batch([time, theme_files, media_controls, keys, dropped_files])
     }
 
     fn update(&mut self, event: Message) -> Command<Message> {
+        // navigation, playback, theme reloading, shortcuts and media controls work the same from
+        // every screen
+        match event {
+            Message::ChangeUI(_) | Message::NavigateBack | Message::NavigateForward => {
+                match event {
+                    Message::ChangeUI(ui_state) => self.navigate(ui_state),
+                    Message::NavigateBack => self.navigate_back(),
+                    _ => self.navigate_forward(),
+                }
+                if matches!(self.ui_state, UIState::Stats) {
+                    self.refresh_stats();
+                }
+                return Command::none();
+            }
+            Message::CheckThemeFiles => {
+                self.reload_user_themes_if_changed();
+                return Command::none();
+            }
+            Message::KeyPressed(key, modifiers) => {
+                if matches!(self.ui_state, UIState::Loading) {
+                    return Command::none();
+                }
+                return match self.keymap.action(&key, modifiers) {
+                    Some(action) => self.run_shortcut(action),
+                    None => Command::none(),
+                };
+            }
+            Message::ShowShortcutHelp(show) => {
+                self.show_shortcut_help = show;
+                return Command::none();
+            }
+            Message::CheckMediaControls => {
+                self.update_media_controls();
+                return Command::none();
+            }
+            Message::FileDropped(path) => {
+                let request = OpenRequest {
+                    paths: vec![path],
+                    play: false,
+                    add_to_library: self.global_settings.add_opened_files,
+                };
+                return self.open_files(request);
+            }
+            Message::FilesOpened(added_to_library) => {
+                if added_to_library {
+                    self.refresh_search();
+                }
+                return Command::none();
+            }
+            Message::Player(command) => {
+                self.execute(command);
+                return Command::none();
+            }
+            Message::CloseRequested => {
+                let mut player = self.player.lock();
+                player.save_resume_position();
+                if let Err(e) = player.save_library() {
+                    println!("Saving the library failed: {}", e);
+                }
+                return window::close(window::Id::MAIN);
+            }
+            _ => (),
+        }
+
         match self.ui_state {
             UIState::Loading => match event {
                 Message::LoadComplete(result) => {
                     match result {
                         Ok(()) => {
                             println!("Library loaded successfully.");
+                            self.refresh_search();
                             self.ui_state = UIState::Main
                         }
                         Err(e) => {
@@ -307,53 +810,48 @@ impl Application for Jukebox {
                             self.ui_state = UIState::Main
                         }
                     }
-                    Command::none()
+                    let launch_files = std::mem::take(&mut self.launch_files);
+                    self.open_files(launch_files)
                 }
                 _ => Command::none(),
             },
             UIState::Main => match event {
                 Message::TickUpdate => {
-                    self.update_time();
-                    Command::none()
-                }
-                Message::TogglePlayback => {
-                    if self.sink.lock().is_none() {
-                        let _ = self.play_song_from_queue();
-                    } else {
-                        self.toggle_sink_playback();
-                    }
+                    self.player.lock().update_time();
                     Command::none()
                 }
                 Message::AddTestSongToQueue => {
-                    self.add_song_to_queue_end(Song::new(PathBuf::from_str("./test.ogg").unwrap()))
-                        .expect("adding song to queue failed");
+                    self.player
+                        .lock()
+                        .enqueue(vec![Song::new(PathBuf::from_str("./test.ogg").unwrap())]);
                     Command::none()
                 }
                 Message::Scan => {
-                    let mut jb = self.clone();
+                    let jb = self.clone();
                     println!("scanning...");
                     Command::perform(async move { jb.scan_and_save() }, Message::ScanComplete)
                 }
                 Message::ScanComplete(result) => {
                     match result {
-                        Ok(()) => self.load_library().unwrap(),
-                        Err(e) => {
-                            format!("Scan failed: {}", e);
+                        // the scan already replaced the shared library
+                        Ok(()) => {
+                            self.refresh_search();
+                            self.cover_art.lock().clear();
                         }
+                        Err(e) => println!("{}", e),
                     }
                     Command::none()
                 }
                 Message::PickSong(id) => {
-                    self.add_song_to_queue_end(
-                        self.music_library.lock().songs.get(&id).unwrap().clone(),
-                    )
-                    .expect("adding song to queue failed");
+                    let song = self.music_library.lock().songs.get(&id).unwrap().clone();
+                    self.player.lock().enqueue(vec![song]);
                     Command::none()
                 }
                 Message::LoadComplete(result) => {
                     match result {
                         Ok(()) => {
                             println!("Library loaded successfully.");
+                            self.refresh_search();
                         }
                         Err(e) => {
                             println!("Load failed: {}", e);
@@ -361,27 +859,222 @@ impl Application for Jukebox {
                     }
                     Command::none()
                 }
-                Message::PreviousSong => {
-                    let _ = self.prev_in_queue();
+                Message::BookmarkNameChanged(name) => {
+                    self.bookmark_name = name;
+                    Command::none()
+                }
+                Message::AddBookmark => {
+                    match self.player.lock().add_bookmark(&self.bookmark_name) {
+                        Ok(()) => self.bookmark_name.clear(),
+                        Err(e) => println!("Adding bookmark failed: {}", e),
+                    }
+                    Command::none()
+                }
+                Message::RemoveBookmark(index) => {
+                    if let Err(e) = self.player.lock().remove_bookmark(index) {
+                        println!("Removing bookmark failed: {}", e);
+                    }
+                    Command::none()
+                }
+                Message::SetRememberPosition(remember) => {
+                    if let Err(e) = self.player.lock().set_remember_position(remember) {
+                        println!("Changing remember position failed: {}", e);
+                    }
+                    Command::none()
+                }
+                Message::PlaylistPathChanged(path) => {
+                    self.playlist_path = path;
+                    Command::none()
+                }
+                Message::SearchChanged(query) => {
+                    self.search_query = query;
+                    self.refresh_search();
+                    self.song_table_offset = 0.0;
+                    scrollable::snap_to(song_table_id(), RelativeOffset::START)
+                }
+                Message::SongTableScrolled(viewport) => {
+                    self.song_table_offset = viewport.absolute_offset().y;
+                    self.song_table_height = viewport.bounds().height;
+                    Command::none()
+                }
+                Message::SortSongsBy(field) => {
+                    self.sort_songs_by(field);
+                    Command::none()
+                }
+                Message::ToggleSongSelected(id) => {
+                    if !self.selected_songs.remove(&id) {
+                        self.selected_songs.insert(id);
+                    }
+                    Command::none()
+                }
+                Message::SelectAllSongs => {
+                    self.selected_songs
+                        .extend(self.search_results.iter().copied());
+                    Command::none()
+                }
+                Message::ClearSongSelection => {
+                    self.selected_songs.clear();
+                    Command::none()
+                }
+                Message::EnqueueSelection => {
+                    if let Err(e) = self.enqueue_selection() {
+                        println!("Enqueueing selection failed: {}", e);
+                    }
+                    Command::none()
+                }
+                Message::RateSong(rating) => {
+                    if let Err(e) = self.player.lock().rate_current_song(rating) {
+                        println!("Rating song failed: {}", e);
+                    }
+                    Command::none()
+                }
+                Message::ImportPlaylist => {
+                    if let Err(e) = self.import_playlist() {
+                        println!("Importing playlist failed: {}", e);
+                    }
                     Command::none()
                 }
-                Message::NextSong => {
-                    let _ = self.next_in_queue();
+                Message::ExportQueue => {
+                    if let Err(e) = self.export_queue() {
+                        println!("Exporting queue failed: {}", e);
+                    }
                     Command::none()
                 }
-                Message::ChangeUI(ui_state) => {
-                    self.ui_state = ui_state;
+                Message::AddSelectionToPlaylist => {
+                    if let Err(e) = self.add_selection_to_playlist() {
+                        println!("Adding to playlist failed: {}", e);
+                    }
                     Command::none()
                 }
                 _ => Command::none(),
             },
+            UIState::Playlists => {
+                let result = match event {
+                    Message::TickUpdate => {
+                        self.player.lock().update_time();
+                        Ok(())
+                    }
+                    Message::PlaylistPathChanged(path) => {
+                        self.playlist_path = path;
+                        Ok(())
+                    }
+                    Message::PlaylistNameChanged(name) => {
+                        self.playlist_name = name;
+                        Ok(())
+                    }
+                    Message::SelectPlaylist(id) => {
+                        self.selected_playlist = Some(id);
+                        if let Some(smart_playlist) = self.music_library.lock().smart_playlist(id) {
+                            self.smart_query = smart_playlist.query.to_string();
+                        }
+                        Ok(())
+                    }
+                    Message::SmartQueryChanged(query) => {
+                        self.smart_query = query;
+                        Ok(())
+                    }
+                    Message::CreateSmartPlaylist => self.create_smart_playlist(),
+                    Message::UpdateSmartQuery => self.update_smart_query(),
+                    Message::ImportPlaylist => self.import_playlist(),
+                    Message::ExportPlaylist => self.export_playlist(),
+                    Message::CreatePlaylist => self.create_playlist(Vec::new()),
+                    Message::SaveQueueAsPlaylist => self.save_queue_as_playlist(),
+                    Message::RenamePlaylist => self.rename_playlist(),
+                    Message::DeletePlaylist => self.delete_playlist(),
+                    Message::DuplicatePlaylist => self.duplicate_playlist(),
+                    Message::RemoveFromPlaylist(index) => {
+                        self.edit_selected_playlist(|library, id| {
+                            library.remove_from_playlist(id, index)
+                        })
+                    }
+                    Message::MoveInPlaylist(from, to) => {
+                        self.edit_selected_playlist(|library, id| {
+                            library.move_in_playlist(id, from, to)
+                        })
+                    }
+                    Message::EnqueuePlaylist => self.enqueue_playlist(),
+                    Message::PlayPlaylist => self.play_playlist(),
+                    _ => Ok(()),
+                };
+                if let Err(e) = result {
+                    println!("Playlist action failed: {}", e);
+                }
+                Command::none()
+            }
             UIState::Settings => match event {
+                Message::TickUpdate => {
+                    self.player.lock().update_time();
+                    Command::none()
+                }
                 Message::SaveSettings(new_settings) => {
-                    self.global_settings = new_settings;
+                    self.global_settings = *new_settings;
                     Command::none()
                 }
-                Message::ChangeUI(ui_state) => {
-                    self.ui_state = ui_state;
+                Message::ThemeChanged(name) => {
+                    if let Err(e) = self.change_theme(name) {
+                        println!("Changing theme failed: {}", e);
+                    }
+                    Command::none()
+                }
+                Message::ExportTheme => {
+                    if let Err(e) = self.export_theme() {
+                        println!("Exporting theme failed: {}", e);
+                    }
+                    Command::none()
+                }
+                _ => Command::none(),
+            },
+            UIState::Artists
+            | UIState::Artist(_)
+            | UIState::Album(_)
+            | UIState::Genres
+            | UIState::Genre(_) => {
+                let result = match event {
+                    Message::TickUpdate => {
+                        self.player.lock().update_time();
+                        Ok(())
+                    }
+                    Message::PickSong(id) => {
+                        let song = self.music_library.lock().songs.get(&id).cloned();
+                        self.enqueue_songs(song.into_iter().collect())
+                    }
+                    Message::EnqueueAlbum(id) => {
+                        let songs = self.music_library.lock().album_songs(id);
+                        self.enqueue_songs(songs)
+                    }
+                    Message::EnqueueGenre(genre) => {
+                        let songs = self.music_library.lock().genre_songs(&genre);
+                        self.enqueue_songs(songs)
+                    }
+                    _ => Ok(()),
+                };
+                if let Err(e) = result {
+                    println!("Enqueueing failed: {}", e);
+                }
+                Command::none()
+            }
+            UIState::Stats => match event {
+                Message::TickUpdate => {
+                    self.player.lock().update_time();
+                    Command::none()
+                }
+                Message::StatsPeriodChanged(period) => {
+                    self.stats_period = period;
+                    self.refresh_stats();
+                    Command::none()
+                }
+                Message::WrappedYearChanged(year) => {
+                    self.wrapped_year = year;
+                    Command::none()
+                }
+                Message::WrappedPathChanged(path) => {
+                    self.wrapped_path = path;
+                    Command::none()
+                }
+                Message::ExportWrapped => {
+                    if let Err(e) = self.export_wrapped() {
+                        println!("Exporting summary failed: {}", e);
+                    }
                     Command::none()
                 }
                 _ => Command::none(),
@@ -389,16 +1082,63 @@ impl Application for Jukebox {
         }
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
+        if self.show_shortcut_help {
+            return shortcut_help_ui(self);
+        }
         match self.ui_state {
             UIState::Loading => loading_ui(),
-            UIState::Main => main_ui(self.clone()),
-            UIState::Settings => settings_ui(self.global_settings.clone()),
+            UIState::Main => main_ui(self),
+            UIState::Playlists => playlists_ui(self),
+            UIState::Settings => settings_ui(self),
+            UIState::Artists => artists_ui(self),
+            UIState::Artist(id) => artist_ui(self, id),
+            UIState::Album(id) => album_ui(self, id),
+            UIState::Genres => genres_ui(self),
+            UIState::Genre(ref genre) => genre_ui(self, genre),
+            UIState::Stats => stats_ui(self),
         }
     }
 }
 
 #[tokio::main]
 async fn main() -> iced::Result {
-    Jukebox::run(Settings::default())
+    let mut cli = Cli::parse();
+    let settings = GlobalSettings::read_or_create();
+    if let Some(command) = cli.command.take() {
+        if let Err(e) = command.run(settings).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let launch = match cli.launch(&settings).await {
+        Ok(Some(launch)) => launch,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let claimed = launch.control.is_some();
+    let result = if cli.daemon {
+        if let Err(e) = daemon::run(settings.clone(), launch).await {
+            println!("Daemon failed: {}", e);
+        }
+        Ok(())
+    } else {
+        Jukebox::run(Settings {
+            window: window::Settings {
+                // the library is saved first, see `Message::CloseRequested`
+                exit_on_close_request: false,
+                ..window::Settings::default()
+            },
+            ..Settings::with_flags(launch)
+        })
+    };
+    if claimed {
+        server::release(&settings.control_socket);
+    }
+    result
 }
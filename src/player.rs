@@ -0,0 +1,1086 @@
+use anyhow::{anyhow, bail, Result};
+use parking_lot::Mutex;
+use rodio::Sink;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, fs,
+    io::BufReader,
+    ops::Range,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::audio::AudioOutput;
+use crate::library::{append_play, Bookmark, Library, Play, Song, SongField};
+use crate::scrobble::{should_scrobble, Scrobble, Scrobbler};
+use crate::ui::format_duration;
+use crate::GlobalSettings;
+
+/// How often playback is checked for finished songs, loop points and the sleep timer
+pub const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How far the seek shortcuts and media controls jump
+pub const SEEK_STEP: Duration = Duration::from_secs(5);
+
+/// How long the sleep timer spends fading out before it pauses playback
+const SLEEP_FADE_DURATION: Duration = Duration::from_secs(30);
+/// How often the position of a resumable song is written to the library while it plays
+const RESUME_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// How long changes to the library's songs wait before it is saved, so that changes made in
+/// quick succession (e.g. skipping through the queue) are saved together
+const LIBRARY_SAVE_DELAY: Duration = Duration::from_secs(10);
+
+/// The song playing now, from when it started until playback moves on
+struct Listen {
+    song: Song,
+    play: Play,
+    last_update: Instant, // when `play.played` was last brought up to date
+    scrobbled: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PlaybackSettings {
+    pub volume: f32, // lets leave this at 1.0 for now
+    pub speed: f32,  // lets leave this at 1.0 for now
+}
+
+impl Default for PlaybackSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            speed: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopAfter {
+    Track,
+    Album,
+}
+
+impl fmt::Display for StopAfter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopAfter::Track => write!(f, "this track"),
+            StopAfter::Album => write!(f, "this album"),
+        }
+    }
+}
+
+/// Points A and B of a practice loop within the current song
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoopPoints {
+    pub start: Option<Duration>,
+    pub end: Option<Duration>,
+}
+
+impl LoopPoints {
+    pub fn active(&self) -> Option<(Duration, Duration)> {
+        self.start.zip(self.end)
+    }
+
+    /// Moves A, dropping a B point that would no longer come after it
+    fn set_start(&mut self, position: Duration) {
+        if self.end.is_some_and(|end| end <= position) {
+            self.end = None;
+        }
+        self.start = Some(position);
+    }
+
+    /// Moves B, unless it would not come after A. A loop without an explicit A point starts
+    /// at the beginning of the song.
+    fn set_end(&mut self, position: Duration) {
+        let start = *self.start.get_or_insert(Duration::ZERO);
+        if position > start {
+            self.end = Some(position);
+        }
+    }
+
+    /// Where to jump back to when playback at `position` has reached B
+    fn wrap(&self, position: Duration) -> Option<Duration> {
+        self.active()
+            .filter(|(_, end)| position >= *end)
+            .map(|(start, _)| start)
+    }
+}
+
+/// What a client (the window, the desktop's media controls, ...) can ask the player to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerCommand {
+    TogglePlayback,
+    SetPaused(bool),
+    Stop,
+    PlayQueueIndex(usize),
+    PreviousSong,
+    NextSong,
+    SeekBy(Duration, bool), // forward when true
+    SeekTo(Duration),
+    SetVolume(f32),
+    ChangeVolume(f32),
+    SetSleepTimer(Option<u64>), // minutes, `None` cancels
+    SetStopAfter(Option<StopAfter>),
+    SetLoopStart,
+    SetLoopEnd,
+    ClearLoop,
+    RemoveFromQueue(Range<usize>), // positions
+    MoveInQueue(usize, usize),     // from, to
+    ClearQueue,
+}
+
+/// Files and folders to open, e.g. given on the command line
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OpenRequest {
+    pub paths: Vec<PathBuf>, // absolute, the running jukebox may have another working directory
+    pub play: bool,          // replace the queue instead of adding to it
+    pub add_to_library: bool, // keep songs from outside the library in it
+}
+
+/// What is playing right now, for clients that show it
+#[derive(Debug, Clone, Default)]
+pub struct NowPlaying {
+    pub song: Option<Song>, // `None` when stopped
+    pub playing: bool,
+    pub position: Duration,
+    pub volume: f32,
+}
+
+/// The library, the queue and the audio output, without any user interface. The window and
+/// the daemon both drive one of these and call `update_time` every `TICK_INTERVAL`.
+pub struct Player {
+    pub settings: GlobalSettings,
+    pub playback_settings: PlaybackSettings,
+    output: Option<AudioOutput>, // opened with the first song played
+    sink: Option<Sink>,
+    library: Arc<Mutex<Library>>, // shared, so loading and scanning do not block playback
+    pub queue: VecDeque<(Song, bool)>, // true for the current song
+    queue_ids: VecDeque<u32>,     // one per queue entry, kept when the entries move
+    last_queue_id: u32,
+    queue_version: u32, // bumped on every change to the songs in the queue or their order
+    pub playback_index: usize,
+    sleep_timer: Option<Instant>, // when playback should be paused
+    pub stop_after: Option<StopAfter>,
+    pub loop_points: LoopPoints,
+    last_resume_save: Instant,
+    unsaved_since: Option<Instant>, // when the library first changed after it was last saved
+    listen: Option<Listen>,
+    scrobbler: Option<Scrobbler>, // `None` without any scrobbling service set up
+}
+
+impl Player {
+    pub fn new(settings: GlobalSettings) -> Self {
+        let scrobbler = Scrobbler::start(&settings);
+        Self {
+            settings,
+            playback_settings: PlaybackSettings::default(), // TODO fetch
+            output: None,
+            sink: None,
+            library: Arc::new(Mutex::new(Library::new())),
+            queue: VecDeque::new(),
+            queue_ids: VecDeque::new(),
+            last_queue_id: 0,
+            queue_version: 0,
+            playback_index: 0,
+            sleep_timer: None,
+            stop_after: None,
+            loop_points: LoopPoints::default(),
+            last_resume_save: Instant::now(),
+            unsaved_since: None,
+            listen: None,
+            scrobbler,
+        }
+    }
+
+    pub fn library(&self) -> Arc<Mutex<Library>> {
+        Arc::clone(&self.library)
+    }
+
+    pub fn execute(&mut self, command: PlayerCommand) -> Result<()> {
+        match command {
+            PlayerCommand::TogglePlayback => self.toggle_playback(),
+            PlayerCommand::SetPaused(paused) => self.set_paused(paused),
+            PlayerCommand::Stop => {
+                self.save_resume_position();
+                return self.kill_sink();
+            }
+            PlayerCommand::PlayQueueIndex(index) => return self.play_queue_index(index),
+            PlayerCommand::PreviousSong => {
+                self.save_resume_position();
+                return self.prev_in_queue();
+            }
+            PlayerCommand::NextSong => {
+                self.save_resume_position();
+                return self.next_in_queue();
+            }
+            PlayerCommand::SeekBy(step, forward) => return self.seek_by(step, forward),
+            PlayerCommand::SeekTo(position) => return self.seek_to(position),
+            PlayerCommand::SetVolume(volume) => self.set_volume(volume),
+            PlayerCommand::ChangeVolume(change) => {
+                self.set_volume(self.playback_settings.volume + change)
+            }
+            PlayerCommand::SetSleepTimer(minutes) => self.set_sleep_timer(minutes),
+            PlayerCommand::SetStopAfter(stop_after) => self.stop_after = stop_after,
+            PlayerCommand::SetLoopStart => self.set_loop_start(),
+            PlayerCommand::SetLoopEnd => self.set_loop_end(),
+            PlayerCommand::ClearLoop => self.loop_points = LoopPoints::default(),
+            PlayerCommand::RemoveFromQueue(range) => return self.remove_from_queue(range),
+            PlayerCommand::MoveInQueue(from, to) => return self.move_in_queue(from, to),
+            PlayerCommand::ClearQueue => {
+                self.save_resume_position();
+                self.queue.clear();
+                self.queue_ids.clear();
+                self.queue_changed();
+                self.playback_index = 0;
+                return self.kill_sink();
+            }
+        }
+        Ok(())
+    }
+
+    fn toggle_playback(&mut self) {
+        if self.sink.is_none() {
+            let _ = self.play_song_from_queue();
+        } else {
+            self.toggle_sink_playback();
+        }
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        if self.sink.is_none() {
+            if !paused {
+                let _ = self.play_song_from_queue();
+            }
+        } else if self.is_playing() == paused {
+            self.toggle_sink_playback();
+        }
+    }
+
+    fn toggle_sink_playback(&mut self) {
+        self.update_listen();
+        if self.sink.as_ref().unwrap().is_paused() {
+            self.sink.as_ref().unwrap().play();
+        } else {
+            self.sink.as_ref().unwrap().pause();
+            self.save_resume_position();
+        }
+    }
+
+    pub fn enqueue(&mut self, songs: Vec<Song>) {
+        for song in songs {
+            let id = self.new_queue_id();
+            self.queue.push_back((song, false));
+            self.queue_ids.push_back(id);
+        }
+        self.queue_changed();
+    }
+
+    fn new_queue_id(&mut self) -> u32 {
+        self.last_queue_id = self.last_queue_id.wrapping_add(1).max(1);
+        self.last_queue_id
+    }
+
+    fn queue_changed(&mut self) {
+        self.queue_version = self.queue_version.wrapping_add(1);
+    }
+
+    /// Goes up whenever songs are added to, removed from or moved in the queue, or a queued
+    /// song's details change, so clients can tell when to fetch the queue again
+    pub fn queue_version(&self) -> u32 {
+        self.queue_version
+    }
+
+    /// Id of the queue entry at `index`. Unlike the index it stays the same while the entry
+    /// is in the queue, however songs are added, moved or removed around it.
+    pub fn queue_id(&self, index: usize) -> Option<u32> {
+        self.queue_ids.get(index).copied()
+    }
+
+    pub fn queue_index_of_id(&self, id: u32) -> Option<usize> {
+        self.queue_ids.iter().position(|entry_id| *entry_id == id)
+    }
+
+    /// Queues the songs in `request.paths`, files or folders, or plays them. Songs that are
+    /// not in the library are read from their files before `player` is locked, so opening a
+    /// large folder does not hold up playback. Returns how many songs were opened and the
+    /// paths without any.
+    pub fn open(player: &Mutex<Player>, request: &OpenRequest) -> Result<(usize, Vec<PathBuf>)> {
+        let (library, library_file) = {
+            let player = player.lock();
+            (player.library(), player.settings.library_file.clone())
+        };
+        let mut songs = Vec::new();
+        let mut empty = Vec::new();
+        for path in &request.paths {
+            let found = Library::read_songs(path)?;
+            if found.is_empty() {
+                empty.push(path.clone());
+            }
+            songs.extend(found);
+        }
+
+        // library paths may be relative to the working directory, opened ones are not
+        let library_paths: Vec<(Uuid, PathBuf, Duration)> = library
+            .lock()
+            .songs
+            .values()
+            .map(|song| (song.id, song.file_path.clone(), song.start_offset()))
+            .collect();
+        let known: HashMap<(PathBuf, Duration), Uuid> = library_paths
+            .into_iter()
+            .filter_map(|(id, path, offset)| Some(((fs::canonicalize(path).ok()?, offset), id)))
+            .collect();
+        {
+            let mut library = library.lock();
+            for song in songs.iter_mut() {
+                let known = known
+                    .get(&(song.file_path.clone(), song.start_offset()))
+                    .and_then(|id| library.songs.get(id));
+                if let Some(known) = known {
+                    *song = known.clone();
+                }
+            }
+            if request.add_to_library && songs.iter().any(|song| song.id.is_nil()) {
+                library.add_songs(&mut songs)?;
+                library.save_to_file(&library_file)?;
+            }
+        }
+
+        let count = songs.len();
+        let mut player = player.lock();
+        if request.play && count > 0 {
+            player.play_songs(songs)?;
+        } else {
+            player.enqueue(songs);
+        }
+        Ok((count, empty))
+    }
+
+    /// Replaces the queue with `songs` and starts playing the first one
+    pub fn play_songs(&mut self, songs: Vec<Song>) -> Result<()> {
+        self.save_resume_position();
+        self.queue.clear();
+        self.queue_ids.clear();
+        self.enqueue(songs);
+        self.playback_index = 0;
+        self.play_song_from_queue()
+    }
+
+    fn play_queue_index(&mut self, index: usize) -> Result<()> {
+        if index >= self.queue.len() {
+            bail!("there is no song at position {} in the queue", index);
+        }
+        self.save_resume_position();
+        self.playback_index = index;
+        self.play_song_from_queue()
+    }
+
+    /// Removing the current song moves playback on to the first one after the removed songs
+    fn remove_from_queue(&mut self, range: Range<usize>) -> Result<()> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        if range.end > self.queue.len() {
+            bail!(
+                "there is no song at position {} in the queue",
+                range.end - 1
+            );
+        }
+        let removes_current = range.contains(&self.playback_index);
+        if removes_current {
+            self.save_resume_position();
+        }
+        self.queue.drain(range.clone());
+        self.queue_ids.drain(range.clone());
+        self.queue_changed();
+
+        if range.end <= self.playback_index {
+            self.playback_index -= range.len();
+        } else if removes_current {
+            self.playback_index = range.start;
+            if self.playback_index < self.queue.len() {
+                if self.sink.is_some() {
+                    return self.play_song_from_queue();
+                }
+            } else {
+                self.playback_index = self.queue.len().saturating_sub(1);
+                return self.kill_sink();
+            }
+        }
+        Ok(())
+    }
+
+    fn move_in_queue(&mut self, from: usize, to: usize) -> Result<()> {
+        if from >= self.queue.len() || to >= self.queue.len() {
+            bail!("the queue has no position {}", from.max(to));
+        }
+        let entry = self.queue.remove(from).unwrap();
+        self.queue.insert(to, entry);
+        let id = self.queue_ids.remove(from).unwrap();
+        self.queue_ids.insert(to, id);
+        self.queue_changed();
+
+        if from == self.playback_index {
+            self.playback_index = to;
+        } else if from < self.playback_index && to >= self.playback_index {
+            self.playback_index -= 1;
+        } else if from > self.playback_index && to <= self.playback_index {
+            self.playback_index += 1;
+        }
+        Ok(())
+    }
+
+    pub fn queue_songs(&self) -> Vec<Song> {
+        self.queue.iter().map(|(song, _)| song.clone()).collect()
+    }
+
+    fn play_song_from_queue(&mut self) -> Result<()> {
+        self.replace_sink()?;
+        self.loop_points = LoopPoints::default();
+
+        for (_song, current) in self.queue.iter_mut() {
+            *current = false;
+        }
+
+        let song = match self.queue.get_mut(self.playback_index) {
+            Some((song, is_current)) => {
+                *is_current = true;
+                song.clone()
+            }
+            None => return Ok(()),
+        };
+
+        self.sink
+            .as_ref()
+            .unwrap()
+            .append(rodio::Decoder::new(BufReader::new(std::fs::File::open(
+                &song.file_path,
+            )?))?);
+        println!(
+            "added song: {} by {}",
+            song.title,
+            song.artists.first().unwrap().name
+        );
+
+        let resume = self.resume_position(&song);
+        if let Some(position) = resume {
+            println!("resuming at {}", format_duration(position));
+        }
+        // always seek, cue sheet tracks start somewhere inside their file
+        self.seek_to(resume.unwrap_or(Duration::ZERO))?;
+        self.last_resume_save = Instant::now();
+        let play = Play::start(song.id);
+        if let Some(scrobbler) = &self.scrobbler {
+            scrobbler.now_playing(Scrobble::new(&song, play.started_at));
+        }
+        self.listen = Some(Listen {
+            song,
+            play,
+            last_update: Instant::now(),
+            scrobbled: false,
+        });
+
+        Ok(())
+    }
+
+    fn replace_sink(&mut self) -> Result<()> {
+        self.kill_sink()?;
+        let output = match &mut self.output {
+            Some(output) => output,
+            None => self.output.insert(AudioOutput::open()?),
+        };
+        match output.new_sink(self.playback_settings) {
+            Ok(sink) => self.sink = Some(sink),
+            Err(e) => {
+                // the device may have gone away, open it again next time
+                self.output = None;
+                return Err(e);
+            }
+        }
+        println!("sink created");
+        Ok(())
+    }
+
+    fn kill_sink(&mut self) -> Result<()> {
+        self.end_listen(false);
+        if self.sink.take().is_some() {
+            println!("sink killed");
+        }
+        Ok(())
+    }
+
+    // fn stop_current_playback(&mut self) -> Result<()> {}
+
+    pub fn update_time(&mut self) {
+        let time_remaining = self
+            .queue
+            .get(self.playback_index)
+            .map_or(Duration::ZERO, |(song, _)| song.duration)
+            .saturating_sub(self.current_position());
+        if let Some(start) = self.loop_points.wrap(self.current_position()) {
+            let _ = self.seek_to(start);
+            return;
+        }
+        let song_finished = self
+            .sink
+            .as_ref()
+            .is_some_and(|sink| !sink.is_paused() && time_remaining <= Duration::ZERO);
+        if song_finished {
+            let _ = self.finish_current_song();
+        } else if self.last_resume_save.elapsed() >= RESUME_SAVE_INTERVAL && self.is_playing() {
+            self.save_resume_position();
+        }
+        self.update_listen();
+        self.update_sleep_timer();
+        if self
+            .unsaved_since
+            .is_some_and(|since| since.elapsed() >= LIBRARY_SAVE_DELAY)
+        {
+            if let Err(e) = self.save_library() {
+                println!("Saving the library failed: {}", e);
+            }
+        }
+    }
+
+    /// Writes the changes `update_song` made to the library file, if there are any. Happens
+    /// on its own every `LIBRARY_SAVE_DELAY`, and needs to be called before quitting.
+    pub fn save_library(&mut self) -> Result<()> {
+        if self.unsaved_since.take().is_some() {
+            self.library
+                .lock()
+                .save_to_file(&self.settings.library_file)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the time played since the last update to the current listen, and scrobbles it
+    /// once it played long enough
+    fn update_listen(&mut self) {
+        let playing = self.is_playing();
+        let Some(listen) = &mut self.listen else {
+            return;
+        };
+        if playing {
+            listen.play.played += listen.last_update.elapsed();
+        }
+        listen.last_update = Instant::now();
+
+        if !listen.scrobbled && should_scrobble(listen.song.duration, listen.play.played) {
+            listen.scrobbled = true;
+            if let Some(scrobbler) = &self.scrobbler {
+                scrobbler.scrobble(Scrobble::new(&listen.song, listen.play.started_at));
+            }
+        }
+    }
+
+    /// Records the current listen, as skipped unless the song played to the end. Songs from
+    /// outside the library are only scrobbled.
+    fn end_listen(&mut self, finished: bool) {
+        self.update_listen();
+        let Some(Listen { mut play, .. }) = self.listen.take() else {
+            return;
+        };
+        if play.song_id.is_nil() {
+            return;
+        }
+        play.skipped = !finished;
+        if let Err(e) = self.record(play) {
+            println!("Recording play failed: {}", e);
+        }
+    }
+
+    /// Logs a play in the history file and counts it in the song's statistics
+    fn record(&mut self, play: Play) -> Result<()> {
+        if !self.settings.history_file.is_empty() {
+            append_play(&self.settings.history_file, &play)?;
+        }
+        let fields = [SongField::Plays, SongField::Skips, SongField::DaysAgoPlayed];
+        self.update_song(play.song_id, &fields, |song| {
+            song.record(&play);
+            // a finished song starts from the beginning next time
+            if !play.skipped {
+                song.resume_position = None;
+            }
+        })
+    }
+
+    /// Whether a song should pick up where it was left off, e.g. audiobooks and podcasts
+    fn remembers_position(&self, song: &Song) -> bool {
+        song.remember_position.unwrap_or_else(|| {
+            let min_duration = Duration::from_secs(self.settings.resume_min_duration_mins * 60);
+            song.duration >= min_duration
+                || self
+                    .settings
+                    .resume_genres
+                    .iter()
+                    .any(|genre| genre.eq_ignore_ascii_case(&song.genre))
+        })
+    }
+
+    /// Where `song` picks up when it starts playing, `None` for the beginning
+    fn resume_position(&self, song: &Song) -> Option<Duration> {
+        song.resume_position
+            .filter(|_| self.remembers_position(song))
+    }
+
+    /// Stores how far into the current song playback is, if that song remembers its position
+    pub fn save_resume_position(&mut self) {
+        self.last_resume_save = Instant::now();
+        let Some((song, _)) = self.queue.get(self.playback_index).cloned() else {
+            return;
+        };
+        if song.id.is_nil() || self.sink.is_none() || !self.remembers_position(&song) {
+            return;
+        }
+
+        let position = self.current_position();
+        if let Err(e) = self.update_song(song.id, &[], |song| song.resume_position = Some(position))
+        {
+            println!("Saving resume position failed: {}", e);
+        }
+    }
+
+    pub fn set_remember_position(&mut self, remember: Option<bool>) -> Result<()> {
+        let id = self
+            .current_song_id()
+            .ok_or_else(|| anyhow!("no library song is playing"))?;
+        self.update_song(id, &[], |song| song.remember_position = remember)
+    }
+
+    /// Moves on to the next song, unless a "stop after" request says playback should end here
+    fn finish_current_song(&mut self) -> Result<()> {
+        self.end_listen(true);
+
+        let stop = match self.stop_after {
+            Some(StopAfter::Track) => true,
+            Some(StopAfter::Album) => !self.next_song_is_same_album(),
+            None => false,
+        };
+        if !stop {
+            return self.next_in_queue();
+        }
+
+        self.stop_after = None;
+        if self.playback_index + 1 < self.queue.len() {
+            self.playback_index += 1;
+        }
+        // the next song starts from the beginning once playback is toggled back on
+        self.kill_sink()
+    }
+
+    fn next_song_is_same_album(&self) -> bool {
+        match (
+            self.queue.get(self.playback_index),
+            self.queue.get(self.playback_index + 1),
+        ) {
+            (Some((current, _)), Some((next, _))) => current.same_album_as(next),
+            _ => false,
+        }
+    }
+
+    fn set_sleep_timer(&mut self, minutes: Option<u64>) {
+        self.sleep_timer =
+            minutes.map(|minutes| Instant::now() + Duration::from_secs(minutes * 60));
+        // undo any fade that was already in progress
+        self.set_sink_volume(self.playback_settings.volume);
+    }
+
+    pub fn sleep_timer_remaining(&self) -> Option<Duration> {
+        self.sleep_timer
+            .map(|ends_at| ends_at.saturating_duration_since(Instant::now()))
+    }
+
+    fn update_sleep_timer(&mut self) {
+        let Some(remaining) = self.sleep_timer_remaining() else {
+            return;
+        };
+
+        if remaining.is_zero() {
+            self.sleep_timer = None;
+            if let Some(sink) = self.sink.as_ref() {
+                sink.pause();
+            }
+            self.save_resume_position();
+            self.set_sink_volume(self.playback_settings.volume);
+            println!("sleep timer finished, playback paused");
+        } else if remaining < SLEEP_FADE_DURATION {
+            let fade = remaining.as_secs_f32() / SLEEP_FADE_DURATION.as_secs_f32();
+            self.set_sink_volume(self.playback_settings.volume * fade);
+        }
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+        self.playback_settings.volume = volume.clamp(0.0, 1.0);
+        self.set_sink_volume(self.playback_settings.volume);
+    }
+
+    fn set_sink_volume(&self, volume: f32) {
+        if let Some(sink) = self.sink.as_ref() {
+            sink.set_volume(volume);
+        }
+    }
+
+    /// Stopped rather than paused, the current song starts over when playback resumes
+    pub fn is_stopped(&self) -> bool {
+        self.sink.is_none()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.sink.as_ref().is_some_and(|sink| !sink.is_paused())
+    }
+
+    /// Where the current song starts within its file (cue sheet tracks share one file)
+    fn current_start_offset(&self) -> Duration {
+        self.queue
+            .get(self.playback_index)
+            .map_or(Duration::ZERO, |(song, _)| song.start_offset())
+    }
+
+    /// Position within the current song, not within the underlying file
+    pub fn current_position(&self) -> Duration {
+        let file_position = self
+            .sink
+            .as_ref()
+            .map_or(Duration::ZERO, |sink| sink.get_pos());
+        file_position.saturating_sub(self.current_start_offset())
+    }
+
+    fn seek_to(&self, position: Duration) -> Result<()> {
+        let file_position = self.current_start_offset() + position;
+        if let Some(sink) = self.sink.as_ref() {
+            // `SeekError` is not `Sync`, so it cannot be wrapped by anyhow directly
+            sink.try_seek(file_position).map_err(|e| anyhow!("{}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Seeks relative to the current position, staying within the song
+    fn seek_by(&self, step: Duration, forward: bool) -> Result<()> {
+        let duration = self
+            .queue
+            .get(self.playback_index)
+            .map_or(Duration::ZERO, |(song, _)| song.duration);
+        let position = self.current_position();
+        let target = if forward {
+            (position + step).min(duration)
+        } else {
+            position.saturating_sub(step)
+        };
+        self.seek_to(target)
+    }
+
+    fn set_loop_start(&mut self) {
+        let position = self.current_position();
+        self.loop_points.set_start(position);
+    }
+
+    fn set_loop_end(&mut self) {
+        let position = self.current_position();
+        self.loop_points.set_end(position);
+    }
+
+    fn current_song_id(&self) -> Option<Uuid> {
+        self.queue
+            .get(self.playback_index)
+            .map(|(song, _)| song.id)
+            .filter(|id| !id.is_nil())
+    }
+
+    pub fn now_playing(&self) -> NowPlaying {
+        NowPlaying {
+            song: self
+                .queue
+                .get(self.playback_index)
+                .filter(|_| self.sink.is_some())
+                .map(|(song, _)| song.clone()),
+            playing: self.is_playing(),
+            position: self.current_position(),
+            volume: self.playback_settings.volume,
+        }
+    }
+
+    /// Applies `change` to a song in the library and to its copies in the queue, then refreshes
+    /// the smart playlists that look at the changed `fields`. The library is saved a little later.
+    fn update_song(
+        &mut self,
+        id: Uuid,
+        fields: &[SongField],
+        change: impl Fn(&mut Song),
+    ) -> Result<()> {
+        let mut library = self.library.lock();
+        let song = library
+            .songs
+            .get_mut(&id)
+            .ok_or_else(|| anyhow!("song {} is not in the library", id))?;
+        change(song);
+        let mut queued = false;
+        for (song, _) in self.queue.iter_mut() {
+            if song.id == id {
+                change(song);
+                queued = true;
+            }
+        }
+        library.refresh_smart_playlists_using(fields);
+        drop(library);
+        if queued {
+            self.queue_changed();
+        }
+        self.unsaved_since.get_or_insert_with(Instant::now);
+        Ok(())
+    }
+
+    /// Counts a play of a library song that was played somewhere else, e.g. on a phone, and
+    /// scrobbles it
+    pub fn count_play(&mut self, id: Uuid) -> Result<()> {
+        let song = self
+            .library
+            .lock()
+            .songs
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| anyhow!("song {} is not in the library", id))?;
+        let play = Play {
+            played: song.duration,
+            ..Play::start(id)
+        };
+        if let Some(scrobbler) = &self.scrobbler {
+            scrobbler.scrobble(Scrobble::new(&song, play.started_at));
+        }
+        self.record(play)
+    }
+
+    pub fn rate_current_song(&mut self, rating: Option<u8>) -> Result<()> {
+        let id = self
+            .current_song_id()
+            .ok_or_else(|| anyhow!("only songs in the library can be rated"))?;
+        self.update_song(id, &[SongField::Rating], |song| {
+            song.rating = rating.map(|stars| stars.clamp(1, 5))
+        })
+    }
+
+    /// Bookmarks the current position, named after it unless `name` is given
+    pub fn add_bookmark(&mut self, name: &str) -> Result<()> {
+        let id = self
+            .current_song_id()
+            .ok_or_else(|| anyhow!("only songs in the library can be bookmarked"))?;
+        let position = self.current_position();
+        let name = match name.trim() {
+            "" => format!("Bookmark at {}", format_duration(position)),
+            name => name.to_string(),
+        };
+        self.update_song(id, &[], |song| {
+            song.add_bookmark(Bookmark {
+                name: name.clone(),
+                position,
+            })
+        })
+    }
+
+    pub fn remove_bookmark(&mut self, index: usize) -> Result<()> {
+        let id = self
+            .current_song_id()
+            .ok_or_else(|| anyhow!("no library song is playing"))?;
+        self.update_song(id, &[], |song| {
+            if index < song.bookmarks.len() {
+                song.bookmarks.remove(index);
+            }
+        })
+    }
+
+    fn next_in_queue(&mut self) -> Result<()> {
+        const PREVENT_SKIP_BEYOND_QUEUE_LENGTH: usize = 1;
+        if self.queue.is_empty() {
+            return Ok(());
+        }
+        if self.playback_index < self.queue.len() - PREVENT_SKIP_BEYOND_QUEUE_LENGTH {
+            self.playback_index += 1;
+        }
+        self.play_song_from_queue()?;
+        Ok(())
+    }
+
+    fn prev_in_queue(&mut self) -> Result<()> {
+        if self.playback_index > 0 {
+            self.playback_index -= 1;
+        }
+        self.play_song_from_queue()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::Artist;
+    use std::env;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    /// A stopped player with one library song in its queue, saving the library to a file of
+    /// its own
+    fn test_player(duration: Duration, genre: &str) -> (Player, Uuid) {
+        let settings = GlobalSettings {
+            library_file: env::temp_dir()
+                .join(format!("jukebox-library-{}.toml", Uuid::new_v4()))
+                .to_string_lossy()
+                .to_string(),
+            history_file: String::new(),
+            ..GlobalSettings::default()
+        };
+        let mut player = Player::new(settings);
+        let song = Song {
+            id: Uuid::new_v4(),
+            title: String::from("Chapter 1"),
+            artists: vec![Artist::new(String::from("Narrator"))],
+            file_path: PathBuf::from("/books/chapter1.mp3"),
+            duration,
+            genre: genre.to_string(),
+            ..Song::default()
+        };
+        let id = song.id;
+        player.library.lock().songs.insert(id, song.clone());
+        player.enqueue(vec![song]);
+        (player, id)
+    }
+
+    #[test]
+    fn keeps_loop_points_in_order() {
+        let mut points = LoopPoints::default();
+        points.set_end(secs(30));
+        // B alone loops from the start of the song
+        assert_eq!(points.active(), Some((secs(0), secs(30))));
+
+        points.set_start(secs(10));
+        assert_eq!(points.active(), Some((secs(10), secs(30))));
+        // B has to come after A
+        points.set_end(secs(10));
+        points.set_end(secs(5));
+        assert_eq!(points.active(), Some((secs(10), secs(30))));
+        points.set_end(secs(40));
+        assert_eq!(points.active(), Some((secs(10), secs(40))));
+
+        // moving A past B leaves only A
+        points.set_start(secs(40));
+        assert_eq!((points.start, points.end), (Some(secs(40)), None));
+        assert_eq!(points.active(), None);
+    }
+
+    #[test]
+    fn wraps_around_at_b() {
+        let mut points = LoopPoints::default();
+        points.set_start(secs(10));
+        assert_eq!(points.wrap(secs(100)), None);
+
+        points.set_end(secs(20));
+        assert_eq!(points.wrap(secs(10)), None);
+        assert_eq!(points.wrap(Duration::from_millis(19_999)), None);
+        assert_eq!(points.wrap(secs(20)), Some(secs(10)));
+        assert_eq!(points.wrap(secs(25)), Some(secs(10)));
+
+        let (mut player, _) = test_player(secs(60), "");
+        player.execute(PlayerCommand::SetLoopEnd).unwrap();
+        // stopped, so at 0:00, which is not after A
+        assert_eq!(player.loop_points.active(), None);
+        player.loop_points = points;
+        player.execute(PlayerCommand::ClearLoop).unwrap();
+        assert_eq!(player.loop_points.active(), None);
+    }
+
+    #[test]
+    fn saves_bookmarks_with_the_library() {
+        let (mut player, id) = test_player(secs(3_600), "Audiobook");
+        player.add_bookmark("  ").unwrap();
+        player.add_bookmark("Chapter 2").unwrap();
+        let names = |song: &Song| -> Vec<String> {
+            song.bookmarks.iter().map(|b| b.name.clone()).collect()
+        };
+        // the queue's copy of the song gets them too
+        assert_eq!(names(&player.queue[0].0), ["Bookmark at 0:00", "Chapter 2"]);
+
+        player.save_library().unwrap();
+        let library_file = player.settings.library_file.clone();
+        let saved = Library::read_from_file(&library_file).unwrap();
+        assert_eq!(names(&saved.songs[&id]), ["Bookmark at 0:00", "Chapter 2"]);
+        assert_eq!(saved.songs[&id].bookmarks[1].position, Duration::ZERO);
+
+        player.remove_bookmark(0).unwrap();
+        player.remove_bookmark(5).unwrap();
+        player.save_library().unwrap();
+        let saved = Library::read_from_file(&library_file).unwrap();
+        assert_eq!(names(&saved.songs[&id]), ["Chapter 2"]);
+        fs::remove_file(&library_file).unwrap();
+
+        // songs from outside the library have nowhere to keep them
+        player.queue[0].0.id = Uuid::nil();
+        assert!(player.add_bookmark("").is_err());
+    }
+
+    #[test]
+    fn remembers_positions_of_long_songs_and_audiobooks() {
+        let (player, _) = test_player(secs(60), "");
+        let song = |minutes: u64, genre: &str, remember: Option<bool>| Song {
+            duration: secs(minutes * 60),
+            genre: genre.to_string(),
+            remember_position: remember,
+            ..Song::default()
+        };
+        assert_eq!(player.settings.resume_min_duration_mins, 20);
+        assert!(!player.remembers_position(&song(19, "Rock", None)));
+        assert!(player.remembers_position(&song(20, "Rock", None)));
+        assert!(player.remembers_position(&song(3, "audiobook", None)));
+        assert!(player.remembers_position(&song(3, "Podcast", None)));
+        // set per song, whatever its length or genre
+        assert!(player.remembers_position(&song(3, "Rock", Some(true))));
+        assert!(!player.remembers_position(&song(90, "Podcast", Some(false))));
+    }
+
+    #[test]
+    fn resumes_where_songs_were_left() {
+        let (player, _) = test_player(secs(60), "");
+        let song = |genre: &str, resume_position: Option<Duration>| Song {
+            duration: secs(600),
+            genre: genre.to_string(),
+            resume_position,
+            ..Song::default()
+        };
+        assert_eq!(
+            player.resume_position(&song("Podcast", Some(secs(90)))),
+            Some(secs(90))
+        );
+        assert_eq!(player.resume_position(&song("Podcast", None)), None);
+        // a position saved before the song stopped remembering it is left alone
+        assert_eq!(player.resume_position(&song("Rock", Some(secs(90)))), None);
+    }
+
+    #[test]
+    fn forgets_the_position_of_finished_songs() {
+        let (mut player, id) = test_player(secs(3_600), "Podcast");
+        let set_position = |player: &mut Player| {
+            player
+                .update_song(id, &[], |song| song.resume_position = Some(secs(600)))
+                .unwrap()
+        };
+        let position = |player: &Player| player.library.lock().songs[&id].resume_position;
+
+        set_position(&mut player);
+        // stopped, so there is no position to save
+        player.save_resume_position();
+        assert_eq!(position(&player), Some(secs(600)));
+
+        let skipped = Play {
+            skipped: true,
+            ..Play::start(id)
+        };
+        player.record(skipped).unwrap();
+        assert_eq!(position(&player), Some(secs(600)));
+        assert_eq!(player.queue[0].0.resume_position, Some(secs(600)));
+
+        player.record(Play::start(id)).unwrap();
+        assert_eq!(position(&player), None);
+        assert_eq!(player.queue[0].0.resume_position, None);
+    }
+}
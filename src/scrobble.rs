@@ -0,0 +1,506 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    io::ErrorKind,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Duration,
+};
+
+use crate::library::Song;
+use crate::GlobalSettings;
+use lastfm::LastFm;
+use listenbrainz::ListenBrainz;
+
+mod http;
+mod lastfm;
+mod listenbrainz;
+
+/// Songs this short are never scrobbled
+const MIN_DURATION: Duration = Duration::from_secs(30);
+/// A song is scrobbled once it played for half its length or this long, whichever comes first
+const MAX_PLAYED_BEFORE_SCROBBLE: Duration = Duration::from_secs(4 * 60);
+/// How long to wait before submitting the queued scrobbles again after a failure
+const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Most scrobbles sent in one request, Last.fm takes no more than this
+const BATCH_SIZE: usize = 50;
+
+/// What a scrobbling service is told about a song
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scrobble {
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub duration: u64,   // seconds
+    pub started_at: u64, // unix seconds
+}
+
+impl Scrobble {
+    pub fn new(song: &Song, started_at: u64) -> Self {
+        Scrobble {
+            artist: song
+                .artists
+                .iter()
+                .map(|artist| artist.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            title: song.title.clone(),
+            album: song.album.as_ref().map(|album| album.title.clone()),
+            album_artist: song.album.as_ref().map(|album| album.artist.name.clone()),
+            track_number: song.track_number,
+            duration: song.duration.as_secs(),
+            started_at,
+        }
+    }
+}
+
+/// Whether a listen has gone on long enough to be scrobbled
+pub fn should_scrobble(duration: Duration, played: Duration) -> bool {
+    duration > MIN_DURATION && (played >= duration / 2 || played >= MAX_PLAYED_BEFORE_SCROBBLE)
+}
+
+/// A submission the service turned down for good, e.g. because of missing tags. Any other
+/// failure keeps the scrobble queued.
+#[derive(Debug)]
+pub struct Rejected(pub String);
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rejected: {}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum Service {
+    LastFm,
+    ListenBrainz,
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Service::LastFm => "Last.fm",
+            Service::ListenBrainz => "ListenBrainz",
+        })
+    }
+}
+
+/// A scrobble one service has not accepted yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Pending {
+    service: Service,
+    scrobble: Scrobble,
+}
+
+enum Submission {
+    NowPlaying(Scrobble),
+    Scrobble(Scrobble),
+}
+
+/// Hands submissions to a background thread, so that slow or unreachable services never hold
+/// up playback
+pub struct Scrobbler {
+    submissions: Sender<Submission>,
+}
+
+impl Scrobbler {
+    /// Starts submitting to the services that have credentials in the settings, `None` when
+    /// none of them do
+    pub fn start(settings: &GlobalSettings) -> Option<Self> {
+        let mut worker = Worker {
+            lastfm: LastFm::new(settings),
+            listenbrainz: ListenBrainz::new(settings),
+            queue_file: settings.scrobble_queue_file.clone(),
+            queue: Vec::new(),
+        };
+        if worker.lastfm.is_none() && worker.listenbrainz.is_none() {
+            return None;
+        }
+        worker.queue = worker.read_queue();
+
+        let (submissions, receiver) = mpsc::channel();
+        let spawned = thread::Builder::new()
+            .name(String::from("scrobbler"))
+            .spawn(move || worker.run(receiver));
+        match spawned {
+            Ok(_) => Some(Scrobbler { submissions }),
+            Err(e) => {
+                println!("Scrobbling unavailable: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn now_playing(&self, scrobble: Scrobble) {
+        let _ = self.submissions.send(Submission::NowPlaying(scrobble));
+    }
+
+    pub fn scrobble(&self, scrobble: Scrobble) {
+        let _ = self.submissions.send(Submission::Scrobble(scrobble));
+    }
+}
+
+struct Worker {
+    lastfm: Option<LastFm>,
+    listenbrainz: Option<ListenBrainz>,
+    queue_file: String,  // empty keeps the queue in memory only
+    queue: Vec<Pending>, // oldest first
+}
+
+impl Worker {
+    fn run(mut self, submissions: Receiver<Submission>) {
+        self.submit_queue();
+        loop {
+            let submission = if self.queue.is_empty() {
+                submissions
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                submissions.recv_timeout(RETRY_INTERVAL)
+            };
+            match submission {
+                Ok(Submission::NowPlaying(scrobble)) => self.now_playing(&scrobble),
+                Ok(Submission::Scrobble(scrobble)) => {
+                    for service in self.services() {
+                        self.queue.push(Pending {
+                            service,
+                            scrobble: scrobble.clone(),
+                        });
+                    }
+                    self.submit_queue();
+                }
+                Err(RecvTimeoutError::Timeout) => self.submit_queue(),
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    fn services(&self) -> Vec<Service> {
+        let mut services = Vec::new();
+        if self.lastfm.is_some() {
+            services.push(Service::LastFm);
+        }
+        if self.listenbrainz.is_some() {
+            services.push(Service::ListenBrainz);
+        }
+        services
+    }
+
+    /// Only worth sending while it is true, so failures are not retried
+    fn now_playing(&mut self, scrobble: &Scrobble) {
+        if let Some(lastfm) = &mut self.lastfm {
+            if let Err(e) = lastfm.now_playing(scrobble) {
+                println!("Last.fm now playing failed: {}", e);
+            }
+        }
+        if let Some(listenbrainz) = &self.listenbrainz {
+            if let Err(e) = listenbrainz.now_playing(scrobble) {
+                println!("ListenBrainz now playing failed: {}", e);
+            }
+        }
+    }
+
+    /// Sends the queued scrobbles in batches, keeping the ones that failed for the next try
+    fn submit_queue(&mut self) {
+        for service in self.services() {
+            let (mut waiting, rest): (Vec<_>, Vec<_>) = std::mem::take(&mut self.queue)
+                .into_iter()
+                .partition(|pending| pending.service == service);
+            self.queue = rest;
+
+            let mut one_at_a_time: usize = 0; // scrobbles left of a batch that was turned down
+            while !waiting.is_empty() {
+                let count = match one_at_a_time {
+                    0 => waiting.len().min(BATCH_SIZE),
+                    _ => 1,
+                };
+                let batch: Vec<Scrobble> = waiting[..count]
+                    .iter()
+                    .map(|pending| pending.scrobble.clone())
+                    .collect();
+                match self.submit(service, &batch) {
+                    Ok(()) => {
+                        waiting.drain(..count);
+                        one_at_a_time = one_at_a_time.saturating_sub(1);
+                    }
+                    // one of them was turned down, find out which by sending them one at a time
+                    Err(e) if e.is::<Rejected>() && count > 1 => one_at_a_time = count,
+                    Err(e) if e.is::<Rejected>() => {
+                        println!("{} scrobble of {} dropped: {}", service, batch[0].title, e);
+                        waiting.remove(0);
+                        one_at_a_time = one_at_a_time.saturating_sub(1);
+                    }
+                    Err(e) => {
+                        println!(
+                            "{} scrobbling failed, {} scrobbles queued: {}",
+                            service,
+                            waiting.len(),
+                            e
+                        );
+                        break;
+                    }
+                }
+            }
+            self.queue.extend(waiting);
+        }
+        self.write_queue();
+    }
+
+    fn submit(&mut self, service: Service, scrobbles: &[Scrobble]) -> Result<()> {
+        match service {
+            Service::LastFm => match &mut self.lastfm {
+                Some(lastfm) => lastfm.scrobble(scrobbles),
+                None => Ok(()),
+            },
+            Service::ListenBrainz => match &self.listenbrainz {
+                Some(listenbrainz) => listenbrainz.scrobble(scrobbles),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// The scrobbles queued on disk, leaving out those for services that are no longer set up,
+    /// which would otherwise stay queued forever
+    fn read_queue(&self) -> Vec<Pending> {
+        if self.queue_file.is_empty() {
+            return Vec::new();
+        }
+        let queue = match fs::read_to_string(&self.queue_file) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(anyhow::Error::from),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        };
+        let mut queue: Vec<Pending> = queue.unwrap_or_else(|e| {
+            println!("Reading {} failed: {}", self.queue_file, e);
+            Vec::new()
+        });
+        let services = self.services();
+        let queued = queue.len();
+        queue.retain(|pending| services.contains(&pending.service));
+        if queue.len() < queued {
+            println!(
+                "Dropped {} queued scrobbles for services that are no longer set up",
+                queued - queue.len()
+            );
+        }
+        queue
+    }
+
+    fn write_queue(&self) {
+        if self.queue_file.is_empty() {
+            return;
+        }
+        let written = if self.queue.is_empty() {
+            match fs::remove_file(&self.queue_file) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        } else {
+            serde_json::to_string_pretty(&self.queue)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Ok(fs::write(&self.queue_file, json)?))
+        };
+        if let Err(e) = written {
+            println!("Saving {} failed: {}", self.queue_file, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    /// Answers one request per response in turn, handing over each request body it got
+    pub(super) fn mock_server(responses: Vec<(u16, &'static str)>) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (bodies, received) = mpsc::channel();
+        thread::spawn(move || {
+            for (status, response) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(": ") {
+                        Some(("Content-Length", value)) => length = value.parse().unwrap(),
+                        None if line.trim_end().is_empty() => break,
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.send(String::from_utf8(body).unwrap()).unwrap();
+                write!(
+                    &stream,
+                    "HTTP/1.1 {} Whatever\r\nContent-Length: {}\r\n\r\n{}",
+                    status,
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+        });
+        (url, received)
+    }
+
+    pub(super) fn scrobble(title: &str) -> Scrobble {
+        Scrobble {
+            artist: String::from("Low"),
+            title: title.to_string(),
+            album: Some(String::from("Things We Lost in the Fire")),
+            album_artist: Some(String::from("Low")),
+            track_number: Some(1),
+            duration: 200,
+            started_at: 1_000_000,
+        }
+    }
+
+    #[test]
+    fn scrobbles_long_enough_listens() {
+        let seconds = Duration::from_secs;
+        // too short to count, however long it played
+        assert!(!should_scrobble(seconds(30), seconds(30)));
+        assert!(should_scrobble(seconds(31), seconds(16)));
+        // half of the song
+        assert!(!should_scrobble(seconds(200), seconds(99)));
+        assert!(should_scrobble(seconds(200), seconds(100)));
+        // or four minutes of a long one
+        assert!(!should_scrobble(seconds(3600), seconds(239)));
+        assert!(should_scrobble(seconds(3600), seconds(240)));
+    }
+
+    #[test]
+    fn keeps_failed_scrobbles_and_drops_rejected_ones() {
+        let (url, bodies) = mock_server(vec![
+            (503, "unavailable"),
+            (400, r#"{"error": "one of them is bad"}"#),
+            (200, r#"{"status": "ok"}"#),
+            (400, r#"{"error": "no artist"}"#),
+        ]);
+        let queue_file =
+            env::temp_dir().join(format!("jukebox-scrobbles-{}.json", uuid::Uuid::new_v4()));
+        let settings = GlobalSettings {
+            listenbrainz_token: String::from("token"),
+            listenbrainz_endpoint: url,
+            ..GlobalSettings::default()
+        };
+        let mut worker = Worker {
+            lastfm: None,
+            listenbrainz: ListenBrainz::new(&settings),
+            queue_file: queue_file.to_string_lossy().to_string(),
+            queue: ["first", "second"]
+                .map(|title| Pending {
+                    service: Service::ListenBrainz,
+                    scrobble: scrobble(title),
+                })
+                .to_vec(),
+        };
+        let titles = |queue: &[Pending]| -> Vec<String> {
+            queue
+                .iter()
+                .map(|pending| pending.scrobble.title.clone())
+                .collect()
+        };
+
+        // a failure keeps both queued, on disk too
+        worker.submit_queue();
+        assert!(bodies.recv().unwrap().contains(r#""listen_type":"import""#));
+        assert_eq!(titles(&worker.queue), ["first", "second"]);
+        assert_eq!(titles(&worker.read_queue()), ["first", "second"]);
+
+        // a rejected batch is sent again one at a time, dropping only the one turned down
+        worker.submit_queue();
+        assert!(bodies.recv().unwrap().contains(r#""listen_type":"import""#));
+        let first = bodies.recv().unwrap();
+        assert!(first.contains(r#""listen_type":"single""#) && first.contains("first"));
+        assert!(bodies.recv().unwrap().contains("second"));
+        assert!(worker.queue.is_empty());
+        assert!(!queue_file.exists());
+    }
+
+    #[test]
+    fn goes_back_to_batches_after_finding_a_rejected_scrobble() {
+        // the first batch is turned down for its first scrobble
+        let mut responses = vec![(400, r#"{"error": "no artist"}"#); 2];
+        responses.extend([(200, r#"{"status": "ok"}"#); BATCH_SIZE]);
+        let (url, bodies) = mock_server(responses);
+        let settings = GlobalSettings {
+            listenbrainz_token: String::from("token"),
+            listenbrainz_endpoint: url,
+            ..GlobalSettings::default()
+        };
+        let mut worker = Worker {
+            lastfm: None,
+            listenbrainz: ListenBrainz::new(&settings),
+            queue_file: String::new(),
+            queue: (0..BATCH_SIZE + 2)
+                .map(|number| Pending {
+                    service: Service::ListenBrainz,
+                    scrobble: scrobble(&format!("song {}", number)),
+                })
+                .collect(),
+        };
+
+        worker.submit_queue();
+        assert!(worker.queue.is_empty());
+        let bodies: Vec<String> = bodies.try_iter().collect();
+        assert_eq!(bodies.len(), BATCH_SIZE + 2);
+        let batches = |bodies: &[String]| {
+            bodies
+                .iter()
+                .filter(|body| body.contains(r#""listen_type":"import""#))
+                .count()
+        };
+        // the rejected batch, one at a time, then the last two together
+        assert_eq!(batches(&bodies[..1]), 1);
+        assert_eq!(batches(&bodies[1..BATCH_SIZE + 1]), 0);
+        assert_eq!(batches(&bodies[BATCH_SIZE + 1..]), 1);
+        assert!(
+            bodies[BATCH_SIZE + 1].contains("song 50")
+                && bodies[BATCH_SIZE + 1].contains("song 51")
+        );
+    }
+
+    #[test]
+    fn drops_scrobbles_queued_for_services_no_longer_set_up() {
+        let queue_file =
+            env::temp_dir().join(format!("jukebox-scrobbles-{}.json", uuid::Uuid::new_v4()));
+        let queue =
+            [Service::LastFm, Service::ListenBrainz, Service::LastFm].map(|service| Pending {
+                service,
+                scrobble: scrobble("first"),
+            });
+        fs::write(&queue_file, serde_json::to_string(&queue).unwrap()).unwrap();
+        let (url, bodies) = mock_server(vec![(200, r#"{"status": "ok"}"#)]);
+        let settings = GlobalSettings {
+            listenbrainz_token: String::from("token"),
+            listenbrainz_endpoint: url,
+            ..GlobalSettings::default()
+        };
+        let mut worker = Worker {
+            lastfm: None,
+            listenbrainz: ListenBrainz::new(&settings),
+            queue_file: queue_file.to_string_lossy().to_string(),
+            queue: Vec::new(),
+        };
+
+        worker.queue = worker.read_queue();
+        assert_eq!(worker.queue.len(), 1);
+        assert_eq!(worker.queue[0].service, Service::ListenBrainz);
+        worker.submit_queue();
+        assert!(bodies.recv().unwrap().contains("first"));
+        assert!(!queue_file.exists());
+    }
+}
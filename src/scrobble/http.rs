@@ -0,0 +1,365 @@
+use anyhow::{anyhow, bail, Result};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+use url::Url;
+
+/// How long connecting, sending or waiting for an answer may take
+const TIMEOUT: Duration = Duration::from_secs(20);
+/// Largest response read, the services answer scrobbles with a few hundred bytes of JSON
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Sends a POST request and waits for the whole response. One connection per request, which
+/// is plenty for a scrobble every few minutes.
+///
+/// Written by hand on rustls, which the build already has, because none of the blocking HTTP
+/// client crates (ureq, reqwest's blocking client) are among the dependencies that can be
+/// built offline, and the scrobblers need no more than this.
+pub fn post(
+    url: &str,
+    headers: &[(&str, &str)],
+    content_type: &str,
+    body: &str,
+) -> Result<Response> {
+    let url = Url::parse(url).map_err(|e| anyhow!("invalid url {}: {}", url, e))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("{} has no host", url))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| anyhow!("{} has no port", url))?;
+
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: jukebox/{}\r\nConnection: close\r\n\
+         Content-Type: {}\r\nContent-Length: {}\r\n",
+        &url[url::Position::BeforePath..url::Position::AfterQuery],
+        match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        },
+        env!("CARGO_PKG_VERSION"),
+        content_type,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+
+    let stream = connect(host, port)?;
+    let response = match url.scheme() {
+        "http" => exchange(stream, &request)?,
+        "https" => {
+            let server_name = rustls::ServerName::try_from(host)
+                .map_err(|e| anyhow!("invalid host {}: {}", host, e))?;
+            let connection = rustls::ClientConnection::new(tls_config(), server_name)?;
+            exchange(rustls::StreamOwned::new(connection, stream), &request)?
+        }
+        scheme => bail!("unsupported scheme {}", scheme),
+    };
+    parse_response(&response)
+}
+
+fn connect(host: &str, port: u16) -> Result<TcpStream> {
+    let mut last_error = anyhow!("{} did not resolve to any address", host);
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = anyhow!("cannot connect to {}: {}", address, e),
+        }
+    }
+    Err(last_error)
+}
+
+fn exchange(mut stream: impl Read + Write, request: &str) -> Result<Vec<u8>> {
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+    let mut response = Vec::new();
+    let read = (&mut stream)
+        .take(MAX_RESPONSE_SIZE + 1)
+        .read_to_end(&mut response);
+    match read {
+        Ok(_) if response.len() as u64 > MAX_RESPONSE_SIZE => {
+            bail!("response larger than {} bytes", MAX_RESPONSE_SIZE)
+        }
+        Ok(_) => Ok(response),
+        // plenty of servers close TLS connections without saying goodbye first
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof && !response.is_empty() => Ok(response),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Client settings with the Mozilla root certificates, built once
+fn tls_config() -> Arc<rustls::ClientConfig> {
+    static CONFIG: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+    let config = CONFIG.get_or_init(|| {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        Arc::new(
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        )
+    });
+    Arc::clone(config)
+}
+
+fn parse_response(response: &[u8]) -> Result<Response> {
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("incomplete response"))?;
+    let head = String::from_utf8_lossy(&response[..header_end]);
+    let body = &response[header_end + 4..];
+
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| anyhow!("invalid status line"))?;
+    let chunked = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("transfer-encoding")
+                && value.trim().eq_ignore_ascii_case("chunked")
+        })
+    });
+    let body = if chunked {
+        dechunk(body)?
+    } else {
+        body.to_vec()
+    };
+    Ok(Response {
+        status,
+        body: String::from_utf8_lossy(&body).to_string(),
+    })
+}
+
+/// Joins the chunks of a `Transfer-Encoding: chunked` body
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>> {
+    let mut joined = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or_else(|| anyhow!("incomplete chunk"))?;
+        let size = String::from_utf8_lossy(&body[..line_end]);
+        // chunk extensions follow a semicolon
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size =
+            usize::from_str_radix(size, 16).map_err(|_| anyhow!("invalid chunk size {}", size))?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(joined);
+        }
+        let chunk = body
+            .get(..size)
+            .ok_or_else(|| anyhow!("incomplete chunk"))?;
+        joined.extend_from_slice(chunk);
+        body = body.get(size + 2..).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::BufRead, io::BufReader, net::TcpListener, thread};
+
+    #[test]
+    fn parses_plain_responses() {
+        let response = parse_response(
+            b"HTTP/1.1 201 Created\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello",
+        )
+        .unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.body, "hello");
+        assert!(response.is_success());
+
+        let response = parse_response(b"HTTP/1.0 404 Not Found\r\n\r\n").unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(response.body, "");
+        assert!(!response.is_success());
+
+        // the body is whatever follows the first blank line, blank lines included
+        let response = parse_response(b"HTTP/1.1 200 OK\r\n\r\na\r\n\r\nb").unwrap();
+        assert_eq!(response.body, "a\r\n\r\nb");
+        // a chunked-looking body without the header is taken as is
+        let response = parse_response(b"HTTP/1.1 200 OK\r\n\r\n2\r\nhi\r\n0\r\n\r\n").unwrap();
+        assert_eq!(response.body, "2\r\nhi\r\n0\r\n\r\n");
+    }
+
+    #[test]
+    fn parses_chunked_responses() {
+        let response = parse_response(
+            b"HTTP/1.1 200 OK\r\ntransfer-encoding : Chunked\r\n\r\n5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.body, "hello, world");
+
+        let response = parse_response(
+            b"HTTP/1.1 200 OK\r\nTRANSFER-ENCODING: chunked\r\n\r\nA;name=value\r\n0123456789\r\n0\r\n",
+        )
+        .unwrap();
+        assert_eq!(response.body, "0123456789");
+    }
+
+    #[test]
+    fn rejects_broken_responses() {
+        for response in [
+            &b""[..],
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n",
+            b"HTTP/1.1 200 OK\n\nhello",
+            b"\r\n\r\n",
+            b"HTTP/1.1\r\n\r\n",
+            b"HTTP/1.1 OK 200\r\n\r\n",
+            b"HTTP/1.1 99999 Too Big\r\n\r\n",
+        ] {
+            assert!(
+                parse_response(response).is_err(),
+                "{:?} parsed",
+                String::from_utf8_lossy(response)
+            );
+        }
+    }
+
+    /// A connection that answers with `response`, whatever is sent to it
+    struct Canned(std::io::Cursor<Vec<u8>>);
+
+    impl Read for Canned {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Canned {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn limits_the_response_size() {
+        let canned = |size: u64| Canned(std::io::Cursor::new(vec![b'x'; size as usize]));
+        let response = exchange(canned(MAX_RESPONSE_SIZE), "POST / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(response.len() as u64, MAX_RESPONSE_SIZE);
+        let error = exchange(canned(MAX_RESPONSE_SIZE + 1), "").unwrap_err();
+        assert_eq!(error.to_string(), "response larger than 1048576 bytes");
+    }
+
+    #[test]
+    fn dechunks_bodies() {
+        assert_eq!(dechunk(b"0\r\n\r\n").unwrap(), b"");
+        assert_eq!(
+            dechunk(b"3\r\nabc\r\n1\r\nd\r\n0\r\n\r\n").unwrap(),
+            b"abcd"
+        );
+        // sizes are hexadecimal and chunks may hold line breaks of their own
+        let lines = "\r\n".repeat(13);
+        let body = format!("1a\r\n{}\r\n0\r\n", lines);
+        assert_eq!(dechunk(body.as_bytes()).unwrap(), lines.as_bytes());
+        // trailers after the last chunk are ignored
+        assert_eq!(
+            dechunk(b"2\r\nab\r\n0\r\nExpires: never\r\n\r\n").unwrap(),
+            b"ab"
+        );
+
+        assert_eq!(
+            dechunk(b"2\r\nab").unwrap_err().to_string(),
+            "incomplete chunk"
+        );
+        assert_eq!(
+            dechunk(b"1f\r\nab\r\n").unwrap_err().to_string(),
+            "incomplete chunk"
+        );
+        assert_eq!(
+            dechunk(b"zz\r\nab\r\n").unwrap_err().to_string(),
+            "invalid chunk size zz"
+        );
+        for body in [&b""[..], b"3", b"-1\r\n", b"\r\n"] {
+            assert!(
+                dechunk(body).is_err(),
+                "{:?} dechunked",
+                String::from_utf8_lossy(body)
+            );
+        }
+    }
+
+    #[test]
+    fn posts_to_plain_http_servers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut head = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                head.push(line.trim_end().to_string());
+            }
+            let mut body = vec![0; 7];
+            reader.read_exact(&mut body).unwrap();
+            (&stream)
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n",
+                )
+                .unwrap();
+            (head, String::from_utf8(body).unwrap())
+        });
+
+        let url = format!("http://127.0.0.1:{}/1/submit?x=1", port);
+        let response = post(
+            &url,
+            &[("Authorization", "Token abc")],
+            "text/plain",
+            "a=1&b=2",
+        )
+        .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, "ok");
+
+        let (head, body) = server.join().unwrap();
+        assert_eq!(head[0], "POST /1/submit?x=1 HTTP/1.1");
+        assert!(head.contains(&format!("Host: 127.0.0.1:{}", port)));
+        assert!(head.contains(&String::from("Content-Type: text/plain")));
+        assert!(head.contains(&String::from("Content-Length: 7")));
+        assert!(head.contains(&String::from("Authorization: Token abc")));
+        assert_eq!(body, "a=1&b=2");
+
+        assert!(post("ftp://127.0.0.1/", &[], "text/plain", "").is_err());
+        assert!(post("not a url", &[], "text/plain", "").is_err());
+    }
+}
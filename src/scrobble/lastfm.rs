@@ -0,0 +1,290 @@
+use anyhow::{anyhow, bail, Result};
+use md5::{Digest, Md5};
+use serde_json::Value;
+use std::fmt;
+use url::form_urlencoded;
+
+use super::{http, Rejected, Scrobble};
+use crate::GlobalSettings;
+
+/// Last.fm error codes that only concern the scrobbles sent, not the account or the service
+const REJECTED_ERRORS: [i64; 2] = [
+    6, // invalid parameters
+    7, // invalid resource
+];
+/// Last.fm error code for a session key that is no longer valid
+const INVALID_SESSION: i64 = 9;
+
+/// An error response from Last.fm
+#[derive(Debug)]
+struct ApiError {
+    code: i64,
+    message: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error {}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Last.fm's scrobbling API (version 2.0), or any server that speaks it, e.g. Libre.fm
+pub struct LastFm {
+    endpoint: String,
+    api_key: String,
+    api_secret: String,
+    username: String,
+    password: String,
+    session_key: Option<String>, // asked for with the username and password when missing
+}
+
+impl LastFm {
+    /// `None` without an API account, or without a session key or a password to get one
+    pub fn new(settings: &GlobalSettings) -> Option<Self> {
+        let can_log_in =
+            !settings.lastfm_username.is_empty() && !settings.lastfm_password.is_empty();
+        if settings.lastfm_api_key.is_empty()
+            || settings.lastfm_api_secret.is_empty()
+            || (settings.lastfm_session_key.is_empty() && !can_log_in)
+        {
+            return None;
+        }
+        Some(LastFm {
+            endpoint: settings.lastfm_endpoint.clone(),
+            api_key: settings.lastfm_api_key.clone(),
+            api_secret: settings.lastfm_api_secret.clone(),
+            username: settings.lastfm_username.clone(),
+            password: settings.lastfm_password.clone(),
+            session_key: Some(settings.lastfm_session_key.clone()).filter(|key| !key.is_empty()),
+        })
+    }
+
+    pub fn now_playing(&mut self, scrobble: &Scrobble) -> Result<()> {
+        let mut params = vec![
+            (String::from("artist"), scrobble.artist.clone()),
+            (String::from("track"), scrobble.title.clone()),
+            (String::from("duration"), scrobble.duration.to_string()),
+        ];
+        if let Some(album) = &scrobble.album {
+            params.push((String::from("album"), album.clone()));
+        }
+        self.call_with_session("track.updateNowPlaying", params)?;
+        Ok(())
+    }
+
+    /// At most 50 at a time
+    pub fn scrobble(&mut self, scrobbles: &[Scrobble]) -> Result<()> {
+        let mut params = Vec::new();
+        for (index, scrobble) in scrobbles.iter().enumerate() {
+            let mut param =
+                |name: &str, value: String| params.push((format!("{}[{}]", name, index), value));
+            param("artist", scrobble.artist.clone());
+            param("track", scrobble.title.clone());
+            param("timestamp", scrobble.started_at.to_string());
+            param("duration", scrobble.duration.to_string());
+            if let Some(album) = &scrobble.album {
+                param("album", album.clone());
+            }
+            if let Some(album_artist) = &scrobble.album_artist {
+                param("albumArtist", album_artist.clone());
+            }
+            if let Some(track_number) = scrobble.track_number {
+                param("trackNumber", track_number.to_string());
+            }
+        }
+
+        let response = self.call_with_session("track.scrobble", params)?;
+        let ignored = response["scrobbles"]["@attr"]["ignored"]
+            .as_i64()
+            .or_else(|| {
+                response["scrobbles"]["@attr"]["ignored"]
+                    .as_str()?
+                    .parse()
+                    .ok()
+            })
+            .unwrap_or(0);
+        if ignored > 0 {
+            println!(
+                "Last.fm ignored {} of {} scrobbles",
+                ignored,
+                scrobbles.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Calls a method that needs a session, logging in first if there is none yet
+    fn call_with_session(
+        &mut self,
+        method: &str,
+        mut params: Vec<(String, String)>,
+    ) -> Result<Value> {
+        let session_key = match &self.session_key {
+            Some(key) => key.clone(),
+            None => self.log_in()?,
+        };
+        params.push((String::from("sk"), session_key));
+        let result = self.call(method, params);
+        let session_expired = result.as_ref().is_err_and(|e| {
+            e.downcast_ref::<ApiError>()
+                .is_some_and(|e| e.code == INVALID_SESSION)
+        });
+        if session_expired && !self.password.is_empty() {
+            // log in again next time
+            self.session_key = None;
+        }
+        result
+    }
+
+    fn log_in(&mut self) -> Result<String> {
+        let params = vec![
+            (String::from("username"), self.username.clone()),
+            (String::from("password"), self.password.clone()),
+        ];
+        let response = self.call("auth.getMobileSession", params)?;
+        let key = response["session"]["key"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Last.fm login returned no session key"))?
+            .to_string();
+        println!("Logged in to Last.fm as {}", self.username);
+        self.session_key = Some(key.clone());
+        Ok(key)
+    }
+
+    /// Signs and sends a method call, turning Last.fm's error responses into errors
+    fn call(&self, method: &str, mut params: Vec<(String, String)>) -> Result<Value> {
+        params.push((String::from("method"), method.to_string()));
+        params.push((String::from("api_key"), self.api_key.clone()));
+        params.sort();
+        let signature = signature(&params, &self.api_secret);
+
+        let body = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(&params)
+            .append_pair("api_sig", &signature)
+            .append_pair("format", "json")
+            .finish();
+        let response = http::post(
+            &self.endpoint,
+            &[],
+            "application/x-www-form-urlencoded",
+            &body,
+        )?;
+
+        let json: Value = match serde_json::from_str(&response.body) {
+            Ok(json) => json,
+            Err(_) if !response.is_success() => bail!("HTTP status {}", response.status),
+            Err(e) => bail!("invalid response: {}", e),
+        };
+        if let Some(code) = json["error"].as_i64() {
+            let error = ApiError {
+                code,
+                message: json["message"]
+                    .as_str()
+                    .unwrap_or("unknown error")
+                    .to_string(),
+            };
+            if REJECTED_ERRORS.contains(&code) {
+                return Err(Rejected(error.to_string()).into());
+            }
+            return Err(error.into());
+        }
+        if !response.is_success() {
+            bail!("HTTP status {}", response.status);
+        }
+        Ok(json)
+    }
+}
+
+/// Signs sorted parameters, covering every one except the response format
+fn signature(params: &[(String, String)], api_secret: &str) -> String {
+    let signed: String = params
+        .iter()
+        .map(|(name, value)| format!("{}{}", name, value))
+        .collect();
+    format!("{:x}", Md5::digest(format!("{}{}", signed, api_secret)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{mock_server, scrobble};
+    use super::*;
+
+    fn last_fm(endpoint: String, session_key: &str) -> LastFm {
+        let settings = GlobalSettings {
+            lastfm_api_key: String::from("key"),
+            lastfm_api_secret: String::from("secret"),
+            lastfm_session_key: session_key.to_string(),
+            lastfm_username: String::from("rj"),
+            lastfm_password: String::from("hunter2"),
+            lastfm_endpoint: endpoint,
+            ..GlobalSettings::default()
+        };
+        LastFm::new(&settings).unwrap()
+    }
+
+    fn params(body: &str) -> Vec<(String, String)> {
+        form_urlencoded::parse(body.as_bytes())
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn signs_sorted_parameters() {
+        let params = [
+            ("api_key", "key"),
+            ("method", "auth.getMobileSession"),
+            ("password", "hunter2"),
+            ("username", "rj"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        assert_eq!(
+            signature(&params, "secret"),
+            "d37f8c0aca96c652fb937b1ed75f6951"
+        );
+    }
+
+    #[test]
+    fn logs_in_and_forgets_expired_sessions() {
+        let (url, bodies) = mock_server(vec![
+            (200, r#"{"session": {"name": "rj", "key": "fresh"}}"#),
+            (
+                200,
+                r#"{"scrobbles": {"@attr": {"accepted": 1, "ignored": 0}}}"#,
+            ),
+            (403, r#"{"error": 9, "message": "Invalid session key"}"#),
+            (400, r#"{"error": 6, "message": "Invalid parameters"}"#),
+        ]);
+        let mut lastfm = last_fm(url, "");
+
+        lastfm.scrobble(&[scrobble("first")]).unwrap();
+        let login = params(&bodies.recv().unwrap());
+        assert!(login.contains(&(
+            String::from("method"),
+            String::from("auth.getMobileSession")
+        )));
+        // every parameter but the signature and the format is signed, in order
+        let (signed, rest): (Vec<_>, Vec<_>) = login
+            .into_iter()
+            .partition(|(name, _)| name != "api_sig" && name != "format");
+        assert!(signed.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(
+            rest[0],
+            (String::from("api_sig"), signature(&signed, "secret"))
+        );
+        let sent = params(&bodies.recv().unwrap());
+        assert!(sent.contains(&(String::from("sk"), String::from("fresh"))));
+        assert!(sent.contains(&(String::from("track[0]"), String::from("first"))));
+
+        let error = lastfm.scrobble(&[scrobble("second")]).unwrap_err();
+        assert!(!error.is::<Rejected>());
+        assert_eq!(lastfm.session_key, None);
+        bodies.recv().unwrap();
+
+        // missing tags are turned down for good
+        lastfm.session_key = Some(String::from("fresh"));
+        let error = lastfm.now_playing(&scrobble("")).unwrap_err();
+        assert!(error.is::<Rejected>(), "{}", error);
+    }
+}
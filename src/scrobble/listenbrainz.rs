@@ -0,0 +1,90 @@
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use super::{http, Rejected, Scrobble};
+use crate::GlobalSettings;
+
+/// ListenBrainz's listen submission API, or a self-hosted instance of it
+pub struct ListenBrainz {
+    endpoint: String,
+    token: String,
+}
+
+impl ListenBrainz {
+    /// `None` without a user token
+    pub fn new(settings: &GlobalSettings) -> Option<Self> {
+        if settings.listenbrainz_token.is_empty() {
+            return None;
+        }
+        Some(ListenBrainz {
+            endpoint: settings.listenbrainz_endpoint.clone(),
+            token: settings.listenbrainz_token.clone(),
+        })
+    }
+
+    pub fn now_playing(&self, scrobble: &Scrobble) -> Result<()> {
+        self.submit("playing_now", vec![track_metadata(scrobble)])
+    }
+
+    pub fn scrobble(&self, scrobbles: &[Scrobble]) -> Result<()> {
+        let listens = scrobbles
+            .iter()
+            .map(|scrobble| {
+                let mut listen = track_metadata(scrobble);
+                listen["listened_at"] = json!(scrobble.started_at);
+                listen
+            })
+            .collect();
+        let listen_type = if scrobbles.len() == 1 {
+            "single"
+        } else {
+            "import"
+        };
+        self.submit(listen_type, listens)
+    }
+
+    fn submit(&self, listen_type: &str, payload: Vec<Value>) -> Result<()> {
+        let url = format!("{}/1/submit-listens", self.endpoint.trim_end_matches('/'));
+        let body = json!({ "listen_type": listen_type, "payload": payload }).to_string();
+        let authorization = format!("Token {}", self.token);
+        let response = http::post(
+            &url,
+            &[("Authorization", &authorization)],
+            "application/json",
+            &body,
+        )?;
+        if response.is_success() {
+            return Ok(());
+        }
+
+        let message = serde_json::from_str::<Value>(&response.body)
+            .ok()
+            .and_then(|json| json["error"].as_str().map(str::to_string))
+            .unwrap_or_else(|| format!("HTTP status {}", response.status));
+        // anything but a bad request may work later, e.g. once the token is fixed
+        if response.status == 400 {
+            return Err(Rejected(message).into());
+        }
+        bail!(message)
+    }
+}
+
+fn track_metadata(scrobble: &Scrobble) -> Value {
+    let mut metadata = json!({
+        "artist_name": scrobble.artist,
+        "track_name": scrobble.title,
+        "additional_info": {
+            "duration_ms": scrobble.duration * 1000,
+            "media_player": "jukebox",
+            "submission_client": "jukebox",
+            "submission_client_version": env!("CARGO_PKG_VERSION"),
+        },
+    });
+    if let Some(album) = &scrobble.album {
+        metadata["release_name"] = json!(album);
+    }
+    if let Some(track_number) = scrobble.track_number {
+        metadata["additional_info"]["tracknumber"] = json!(track_number);
+    }
+    json!({ "track_metadata": metadata })
+}
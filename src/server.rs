@@ -0,0 +1,60 @@
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+use crate::player::Player;
+use crate::GlobalSettings;
+pub use control::{
+    claim, default_socket_path, is_running, release, send_request, wait_until_running,
+    ControlRequest, ControlSocket,
+};
+
+mod control;
+mod http;
+mod mpd;
+mod subsonic;
+
+/// Starts the servers enabled in the settings in the background, and answers on the control
+/// socket if this instance claimed it. They share `player` with whatever else drives it (the
+/// window or the daemon) and live as long as the program does.
+pub fn start(
+    player: &Arc<Mutex<Player>>,
+    settings: &GlobalSettings,
+    control: Option<ControlSocket>,
+) {
+    if let Some(socket) = control {
+        let player = Arc::clone(player);
+        tokio::spawn(async move {
+            if let Err(e) = control::serve(player, socket).await {
+                println!("Control socket stopped: {}", e);
+            }
+        });
+    }
+    if !settings.mpd_address.is_empty() {
+        let (player, address) = (Arc::clone(player), settings.mpd_address.clone());
+        tokio::spawn(async move {
+            if let Err(e) = mpd::serve(player, address).await {
+                println!("MPD server stopped: {}", e);
+            }
+        });
+    }
+    if !settings.http_address.is_empty() {
+        let (player, address) = (Arc::clone(player), settings.http_address.clone());
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(player, address).await {
+                println!("HTTP API stopped: {}", e);
+            }
+        });
+    }
+    if !settings.subsonic_address.is_empty() {
+        if settings.subsonic_password.is_empty() {
+            println!("Subsonic API not started, it needs a subsonic_password");
+            return;
+        }
+        let (player, address) = (Arc::clone(player), settings.subsonic_address.clone());
+        tokio::spawn(async move {
+            if let Err(e) = subsonic::serve(player, address).await {
+                println!("Subsonic API stopped: {}", e);
+            }
+        });
+    }
+}
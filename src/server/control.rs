@@ -0,0 +1,320 @@
+use anyhow::{anyhow, bail, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io,
+    os::unix::{io::AsRawFd, net::UnixListener as StdUnixListener},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    task, time,
+};
+
+use crate::library::{Library, SearchQuery, Song};
+use crate::player::{OpenRequest, Player, PlayerCommand};
+use crate::ui::format_duration;
+
+type SharedPlayer = Arc<Mutex<Player>>;
+
+/// How long a launch waits for the jukebox that claimed the socket first to start listening
+const CLAIM_TIMEOUT: Duration = Duration::from_secs(5);
+const CLAIM_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What the command line asks of a running jukebox. Requests and replies are sent as one
+/// line of JSON each.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ControlRequest {
+    Scan,
+    Play(String),    // search query
+    Enqueue(String), // search query
+    Pause,
+    Next,
+    Status,
+    Open(OpenRequest), // from a second launch, e.g. a double-clicked file
+}
+
+/// A message for the user either way
+pub type ControlReply = Result<String, String>;
+
+/// The claimed control socket. The lock next to it stays held for as long as this lives,
+/// which is what makes the jukebox holding it the only one.
+pub struct ControlSocket {
+    listener: StdUnixListener,
+    _lock: File,
+}
+
+/// Where the control socket goes unless the settings say otherwise: the user's runtime
+/// directory, or the config directory on systems without one. Never relative to the working
+/// directory, which differs between a terminal, a file manager and a desktop file.
+pub fn default_socket_path() -> String {
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            let config = env::var_os("XDG_CONFIG_HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| Some(Path::new(&env::var_os("HOME")?).join(".config")))?;
+            Some(config.join("jukebox"))
+        })
+        .unwrap_or_default();
+    dir.join("jukebox.sock").to_string_lossy().to_string()
+}
+
+/// Binds the Unix socket at `path`, which also makes this the only jukebox running from
+/// these settings. `None` when another jukebox already has it.
+///
+/// Whoever holds the lock file next to the socket owns it, so a socket left behind by a
+/// jukebox that did not shut down cleanly can be replaced without racing another launch.
+pub fn claim(path: &str) -> Result<Option<ControlSocket>> {
+    if let Some(dir) = Path::new(path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+    {
+        fs::create_dir_all(dir)?;
+    }
+    let lock_path = format!("{}.lock", path);
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| anyhow!("cannot open {}: {}", lock_path, e))?;
+    // SAFETY: flock only takes the file descriptor, which `lock` keeps open
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() == io::ErrorKind::WouldBlock {
+            return Ok(None);
+        }
+        bail!("cannot lock {}: {}", lock_path, e);
+    }
+
+    // nobody else can be listening here while we hold the lock
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            bail!("cannot replace {}: {}", path, e);
+        }
+    }
+    let listener =
+        StdUnixListener::bind(path).map_err(|e| anyhow!("cannot listen on {}: {}", path, e))?;
+    listener.set_nonblocking(true)?;
+    Ok(Some(ControlSocket {
+        listener,
+        _lock: lock,
+    }))
+}
+
+/// Removes the socket again on the way out, a stale one is also replaced by `claim`
+pub fn release(path: &str) {
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            println!("Removing {} failed: {}", path, e);
+        }
+    }
+}
+
+/// Answers the command line of the same user on the socket taken by `claim`
+pub async fn serve(player: SharedPlayer, socket: ControlSocket) -> Result<()> {
+    let listener = UnixListener::from_std(socket.listener)?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let player = Arc::clone(&player);
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(player, stream).await {
+                println!("Control client failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Whether a jukebox is listening at `path`
+pub async fn is_running(path: &str) -> bool {
+    UnixStream::connect(path).await.is_ok()
+}
+
+/// Waits for the jukebox that claimed the socket at `path` to listen on it, which it may not
+/// do yet when both were launched at the same moment
+pub async fn wait_until_running(path: &str) -> Result<()> {
+    let started = Instant::now();
+    while !is_running(path).await {
+        if started.elapsed() > CLAIM_TIMEOUT {
+            bail!("the jukebox holding {} does not answer", path);
+        }
+        time::sleep(CLAIM_RETRY_INTERVAL).await;
+    }
+    Ok(())
+}
+
+/// Sends `request` to the jukebox listening at `path`
+pub async fn send_request(path: &str, request: &ControlRequest) -> Result<String> {
+    let mut stream = UnixStream::connect(path)
+        .await
+        .map_err(|e| anyhow!("no jukebox is running ({}: {})", path, e))?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes()).await?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply).await?;
+    serde_json::from_str::<ControlReply>(&reply)?.map_err(|e| anyhow!(e))
+}
+
+async fn handle_client(player: SharedPlayer, stream: UnixStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let reply: ControlReply = match serde_json::from_str(&line) {
+            Ok(request) => answer(&player, request).await.map_err(|e| e.to_string()),
+            Err(e) => Err(format!("unknown request: {}", e)),
+        };
+        let mut reply = serde_json::to_string(&reply)?;
+        reply.push('\n');
+        writer.write_all(reply.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+async fn answer(player: &SharedPlayer, request: ControlRequest) -> Result<String> {
+    match request {
+        ControlRequest::Scan => {
+            let (library, settings) = {
+                let player = player.lock();
+                (player.library(), player.settings.clone())
+            };
+            let count = task::spawn_blocking(move || {
+                // scanning takes a while, so it leaves the library and playback alone...
+                let scanned = Library::scan(&settings.folder_to_scan)?;
+                // ...and is merged under the lock, keeping whatever changed in the meantime
+                let mut library = library.lock();
+                library.apply_scan(scanned, &settings.folder_to_scan)?;
+                library.save_to_file(&settings.library_file)?;
+                Ok::<_, anyhow::Error>(library.songs.len())
+            })
+            .await??;
+            Ok(format!("Scanned {} songs", count))
+        }
+        ControlRequest::Play(query) => {
+            let mut player = player.lock();
+            let songs = find_songs(&player, &query)?;
+            let count = songs.len();
+            player.play_songs(songs)?;
+            Ok(format!("Playing {} songs", count))
+        }
+        ControlRequest::Enqueue(query) => {
+            let mut player = player.lock();
+            let songs = find_songs(&player, &query)?;
+            let count = songs.len();
+            player.enqueue(songs);
+            Ok(format!("Queued {} songs", count))
+        }
+        ControlRequest::Pause => {
+            let mut player = player.lock();
+            player.execute(PlayerCommand::TogglePlayback)?;
+            Ok(String::from(match player.is_playing() {
+                true => "Playing",
+                false => "Paused",
+            }))
+        }
+        ControlRequest::Next => {
+            player.lock().execute(PlayerCommand::NextSong)?;
+            Ok(status(&player.lock()))
+        }
+        ControlRequest::Status => Ok(status(&player.lock())),
+        ControlRequest::Open(request) => {
+            let (player, opened) = (Arc::clone(player), request.clone());
+            let (count, missing) =
+                task::spawn_blocking(move || Player::open(&player, &opened)).await??;
+            let mut reply = match request.play {
+                true => format!("Playing {} songs", count),
+                false => format!("Queued {} songs", count),
+            };
+            for path in missing {
+                reply.push_str(&format!("\nNo songs in {}", path.display()));
+            }
+            Ok(reply)
+        }
+    }
+}
+
+/// The library songs matching `query`, in search order
+fn find_songs(player: &Player, query: &str) -> Result<Vec<Song>> {
+    let library = player.library();
+    let library = library.lock();
+    let songs: Vec<Song> = library
+        .search(&SearchQuery::parse(query))
+        .iter()
+        .filter_map(|id| library.songs.get(id).cloned())
+        .collect();
+    if songs.is_empty() {
+        bail!("nothing matches {}", query);
+    }
+    Ok(songs)
+}
+
+fn status(player: &Player) -> String {
+    let now_playing = player.now_playing();
+    let Some(song) = now_playing.song else {
+        return format!("Stopped, {} songs queued", player.queue.len());
+    };
+    let artists: Vec<&str> = song.artists.iter().map(|a| a.name.as_str()).collect();
+    format!(
+        "{}: {} - {}\n{} / {}, song {} of {}, volume {}%",
+        match now_playing.playing {
+            true => "Playing",
+            false => "Paused",
+        },
+        artists.join(", "),
+        song.title,
+        format_duration(now_playing.position),
+        format_duration(song.duration),
+        player.playback_index + 1,
+        player.queue.len(),
+        (now_playing.volume * 100.0).round(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn only_one_jukebox_claims_the_socket() {
+        let dir = env::temp_dir().join(format!("jukebox-control-{}", Uuid::new_v4()));
+        let path = dir.join("jukebox.sock").to_string_lossy().to_string();
+
+        // left behind by a jukebox that did not shut down cleanly
+        fs::create_dir_all(&dir).unwrap();
+        drop(StdUnixListener::bind(&path).unwrap());
+        assert!(Path::new(&path).exists());
+
+        let first = claim(&path).unwrap().expect("stale socket not replaced");
+        assert!(claim(&path).unwrap().is_none());
+        // the lock decides, not whether the socket file is there
+        fs::remove_file(&path).unwrap();
+        assert!(claim(&path).unwrap().is_none());
+
+        drop(first);
+        assert!(claim(&path).unwrap().is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn creates_the_socket_directory() {
+        let dir = env::temp_dir().join(format!("jukebox-control-{}", Uuid::new_v4()));
+        let path = dir
+            .join("nested/jukebox.sock")
+            .to_string_lossy()
+            .to_string();
+        let socket = claim(&path).unwrap().unwrap();
+        assert!(std::os::unix::net::UnixStream::connect(&path).is_ok());
+        drop(socket);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
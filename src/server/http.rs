@@ -0,0 +1,769 @@
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get, post},
+    Json, Router,
+};
+use parking_lot::{Mutex, MutexGuard};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{convert::Infallible, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, sync::mpsc, time};
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+use crate::library::{AlbumSummary, Artist, SearchQuery, Song};
+use crate::player::{Player, PlayerCommand};
+
+/// How often the player is checked for changes to send to `/api/events` listeners
+const EVENT_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+type SharedPlayer = Arc<Mutex<Player>>;
+
+/// Serves the JSON API on `address`, e.g. `curl localhost:6680/api/now-playing`:
+///
+/// - `GET /api/songs?q=&offset=&limit=`, `/api/songs/:id`, `/api/albums`, `/api/albums/:id`,
+///   `/api/artists`, `/api/artists/:id` and `/api/search?q=` browse the library
+/// - `GET`/`POST`/`DELETE /api/queue` lists, appends `{"song_ids": [...]}` to and clears the
+///   queue, `DELETE /api/queue/:index`, `POST /api/queue/:index/play` and
+///   `POST /api/queue/move {"from", "to"}` change it
+/// - `GET /api/now-playing`, `POST /api/player/{play,pause,toggle,stop,next,previous}`,
+///   `POST /api/player/seek {"position"}` (seconds) and `POST /api/player/volume {"volume"}`
+///   (0 to 1) control playback
+/// - `GET /api/events` streams `now-playing`, `position` and `queue` server-sent events
+pub async fn serve(player: SharedPlayer, address: String) -> Result<()> {
+    let listener = TcpListener::bind(&address)
+        .await
+        .map_err(|e| anyhow!("cannot listen on {}: {}", address, e))?;
+    println!("HTTP API listening on {}", address);
+    axum::serve(listener, router(player)).await?;
+    Ok(())
+}
+
+fn router(player: SharedPlayer) -> Router {
+    Router::new()
+        .route("/api/songs", get(songs))
+        .route("/api/songs/:id", get(song))
+        .route("/api/albums", get(albums))
+        .route("/api/albums/:id", get(album))
+        .route("/api/artists", get(artists))
+        .route("/api/artists/:id", get(artist))
+        .route("/api/search", get(search))
+        .route("/api/queue", get(queue).post(enqueue).delete(clear_queue))
+        .route("/api/queue/move", post(move_in_queue))
+        .route("/api/queue/:index", delete(remove_from_queue))
+        .route("/api/queue/:index/play", post(play_queue_index))
+        .route("/api/now-playing", get(now_playing))
+        .route("/api/player/seek", post(seek))
+        .route("/api/player/volume", post(set_volume))
+        .route("/api/player/:action", post(control))
+        .route("/api/events", get(events))
+        .with_state(player)
+}
+
+/// Errors are sent as `{"error": "..."}`
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn not_found(what: &str) -> ApiError {
+        ApiError(StatusCode::NOT_FOUND, format!("no such {}", what))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> ApiError {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Serialize)]
+struct SongInfo {
+    id: Uuid,
+    title: String,
+    artists: Vec<String>,
+    album: Option<String>,
+    album_id: Option<Uuid>,
+    album_artist: Option<String>,
+    year: u16,
+    genre: String,
+    track: Option<u32>,
+    disc: Option<u32>,
+    duration: f64, // seconds
+    rating: Option<u8>,
+    play_count: u32,
+}
+
+impl From<&Song> for SongInfo {
+    fn from(song: &Song) -> SongInfo {
+        SongInfo {
+            id: song.id,
+            title: song.title.clone(),
+            artists: song
+                .artists
+                .iter()
+                .map(|artist| artist.name.clone())
+                .collect(),
+            album: song.album.as_ref().map(|album| album.title.clone()),
+            album_id: song.album.as_ref().map(|album| album.id),
+            album_artist: song.album.as_ref().map(|album| album.artist.name.clone()),
+            year: song.year,
+            genre: song.genre.clone(),
+            track: song.track_number,
+            disc: song.disc_number,
+            duration: song.duration.as_secs_f64(),
+            rating: song.rating,
+            play_count: song.play_count,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AlbumInfo {
+    id: Uuid,
+    title: String,
+    artist: String,
+    artist_id: Uuid,
+    year: u16,
+    song_count: usize,
+    duration: f64, // seconds
+}
+
+impl From<&AlbumSummary> for AlbumInfo {
+    fn from(summary: &AlbumSummary) -> AlbumInfo {
+        AlbumInfo {
+            id: summary.album.id,
+            title: summary.album.title.clone(),
+            artist: summary.album.artist.name.clone(),
+            artist_id: summary.album.artist.id,
+            year: summary.year,
+            song_count: summary.song_count,
+            duration: summary.duration.as_secs_f64(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ArtistInfo {
+    id: Uuid,
+    name: String,
+    album_count: usize,
+}
+
+impl ArtistInfo {
+    fn new((artist, album_count): &(Artist, usize)) -> ArtistInfo {
+        ArtistInfo {
+            id: artist.id,
+            name: artist.name.clone(),
+            album_count: *album_count,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct QueueInfo {
+    playback_index: usize,
+    songs: Vec<SongInfo>,
+}
+
+impl QueueInfo {
+    fn new(player: &Player) -> QueueInfo {
+        QueueInfo {
+            playback_index: player.playback_index,
+            songs: player
+                .queue
+                .iter()
+                .map(|(song, _)| SongInfo::from(song))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct NowPlayingInfo {
+    state: &'static str, // "playing", "paused" or "stopped"
+    song: Option<SongInfo>,
+    position: f64, // seconds
+    volume: f32,
+}
+
+impl NowPlayingInfo {
+    fn new(player: &Player) -> NowPlayingInfo {
+        let now_playing = player.now_playing();
+        let state = match (&now_playing.song, now_playing.playing) {
+            (None, _) => "stopped",
+            (Some(_), true) => "playing",
+            (Some(_), false) => "paused",
+        };
+        NowPlayingInfo {
+            state,
+            song: now_playing.song.as_ref().map(SongInfo::from),
+            position: now_playing.position.as_secs_f64(),
+            volume: now_playing.volume,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SongsQuery {
+    q: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+/// Library songs, best matches first when searching
+async fn songs(
+    State(player): State<SharedPlayer>,
+    Query(query): Query<SongsQuery>,
+) -> ApiResult<Vec<SongInfo>> {
+    let search = SearchQuery::parse(query.q.as_deref().unwrap_or_default());
+    let library = player.lock().library();
+    let library = library.lock();
+    let songs = library
+        .search(&search)
+        .iter()
+        .skip(query.offset.unwrap_or(0))
+        .take(query.limit.unwrap_or(usize::MAX))
+        .filter_map(|id| library.songs.get(id).map(SongInfo::from))
+        .collect();
+    Ok(Json(songs))
+}
+
+async fn song(State(player): State<SharedPlayer>, Path(id): Path<Uuid>) -> ApiResult<SongInfo> {
+    let library = player.lock().library();
+    let library = library.lock();
+    let song = library
+        .songs
+        .get(&id)
+        .ok_or_else(|| ApiError::not_found("song"))?;
+    Ok(Json(SongInfo::from(song)))
+}
+
+async fn albums(State(player): State<SharedPlayer>) -> ApiResult<Vec<AlbumInfo>> {
+    let library = player.lock().library();
+    let albums = library.lock().all_albums();
+    Ok(Json(albums.iter().map(AlbumInfo::from).collect()))
+}
+
+async fn album(
+    State(player): State<SharedPlayer>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<serde_json::Value> {
+    let library = player.lock().library();
+    let library = library.lock();
+    let summary = library
+        .all_albums()
+        .into_iter()
+        .find(|summary| summary.album.id == id)
+        .ok_or_else(|| ApiError::not_found("album"))?;
+    let songs: Vec<SongInfo> = library.album_songs(id).iter().map(SongInfo::from).collect();
+    Ok(Json(
+        json!({ "album": AlbumInfo::from(&summary), "songs": songs }),
+    ))
+}
+
+async fn artists(State(player): State<SharedPlayer>) -> ApiResult<Vec<ArtistInfo>> {
+    let library = player.lock().library();
+    let artists = library.lock().artists_with_album_counts();
+    Ok(Json(artists.iter().map(ArtistInfo::new).collect()))
+}
+
+async fn artist(
+    State(player): State<SharedPlayer>,
+    Path(id): Path<Uuid>,
+) -> ApiResult<serde_json::Value> {
+    let library = player.lock().library();
+    let library = library.lock();
+    let artist = library
+        .artists_with_album_counts()
+        .into_iter()
+        .find(|(artist, _)| artist.id == id)
+        .ok_or_else(|| ApiError::not_found("artist"))?;
+    let albums: Vec<AlbumInfo> = library
+        .artist_albums(id)
+        .iter()
+        .map(AlbumInfo::from)
+        .collect();
+    let singles: Vec<SongInfo> = library
+        .artist_singles(id)
+        .iter()
+        .map(SongInfo::from)
+        .collect();
+    Ok(Json(json!({
+        "artist": ArtistInfo::new(&artist),
+        "albums": albums,
+        "songs": singles,
+    })))
+}
+
+/// Songs as the search box finds them, plus albums and artists whose name contains the query
+async fn search(
+    State(player): State<SharedPlayer>,
+    Query(query): Query<SongsQuery>,
+) -> ApiResult<serde_json::Value> {
+    let text = query.q.unwrap_or_default();
+    let needle = text.to_lowercase();
+    let library = player.lock().library();
+    let library = library.lock();
+
+    let limit = query.limit.unwrap_or(usize::MAX);
+    let songs: Vec<SongInfo> = library
+        .search(&SearchQuery::parse(&text))
+        .iter()
+        .take(limit)
+        .filter_map(|id| library.songs.get(id).map(SongInfo::from))
+        .collect();
+    let albums: Vec<AlbumInfo> = library
+        .all_albums()
+        .iter()
+        .filter(|summary| summary.album.title.to_lowercase().contains(&needle))
+        .take(limit)
+        .map(AlbumInfo::from)
+        .collect();
+    let artists: Vec<ArtistInfo> = library
+        .artists_with_album_counts()
+        .iter()
+        .filter(|(artist, _)| artist.name.to_lowercase().contains(&needle))
+        .take(limit)
+        .map(ArtistInfo::new)
+        .collect();
+    Ok(Json(
+        json!({ "songs": songs, "albums": albums, "artists": artists }),
+    ))
+}
+
+async fn queue(State(player): State<SharedPlayer>) -> ApiResult<QueueInfo> {
+    Ok(Json(QueueInfo::new(&player.lock())))
+}
+
+#[derive(Deserialize)]
+struct EnqueueRequest {
+    song_ids: Vec<Uuid>,
+}
+
+async fn enqueue(
+    State(player): State<SharedPlayer>,
+    Json(request): Json<EnqueueRequest>,
+) -> ApiResult<QueueInfo> {
+    let mut player = player.lock();
+    let songs = {
+        let library = player.library();
+        let library = library.lock();
+        request
+            .song_ids
+            .iter()
+            .map(|id| library.songs.get(id).cloned())
+            .collect::<Option<Vec<Song>>>()
+            .ok_or_else(|| ApiError::not_found("song"))?
+    };
+    player.enqueue(songs);
+    Ok(Json(QueueInfo::new(&player)))
+}
+
+async fn clear_queue(State(player): State<SharedPlayer>) -> ApiResult<QueueInfo> {
+    queue_command(&player, None, PlayerCommand::ClearQueue)
+}
+
+async fn remove_from_queue(
+    State(player): State<SharedPlayer>,
+    Path(index): Path<usize>,
+) -> ApiResult<QueueInfo> {
+    queue_command(
+        &player,
+        Some(index),
+        PlayerCommand::RemoveFromQueue(index..index + 1),
+    )
+}
+
+#[derive(Deserialize)]
+struct MoveRequest {
+    from: usize,
+    to: usize,
+}
+
+async fn move_in_queue(
+    State(player): State<SharedPlayer>,
+    Json(request): Json<MoveRequest>,
+) -> ApiResult<QueueInfo> {
+    queue_command(
+        &player,
+        Some(request.from.max(request.to)),
+        PlayerCommand::MoveInQueue(request.from, request.to),
+    )
+}
+
+async fn play_queue_index(
+    State(player): State<SharedPlayer>,
+    Path(index): Path<usize>,
+) -> ApiResult<NowPlayingInfo> {
+    player_command(&player, Some(index), PlayerCommand::PlayQueueIndex(index))
+}
+
+async fn now_playing(State(player): State<SharedPlayer>) -> ApiResult<NowPlayingInfo> {
+    Ok(Json(NowPlayingInfo::new(&player.lock())))
+}
+
+async fn control(
+    State(player): State<SharedPlayer>,
+    Path(action): Path<String>,
+) -> ApiResult<NowPlayingInfo> {
+    let command = match action.as_str() {
+        "play" => PlayerCommand::SetPaused(false),
+        "pause" => PlayerCommand::SetPaused(true),
+        "toggle" => PlayerCommand::TogglePlayback,
+        "stop" => PlayerCommand::Stop,
+        "next" => PlayerCommand::NextSong,
+        "previous" => PlayerCommand::PreviousSong,
+        _ => return Err(ApiError::not_found("player action")),
+    };
+    player_command(&player, None, command)
+}
+
+#[derive(Deserialize)]
+struct SeekRequest {
+    position: f64, // seconds
+}
+
+async fn seek(
+    State(player): State<SharedPlayer>,
+    Json(request): Json<SeekRequest>,
+) -> ApiResult<NowPlayingInfo> {
+    let position = Duration::try_from_secs_f64(request.position).map_err(|_| {
+        ApiError(
+            StatusCode::BAD_REQUEST,
+            format!("invalid position {}", request.position),
+        )
+    })?;
+    player_command(&player, None, PlayerCommand::SeekTo(position))
+}
+
+#[derive(Deserialize)]
+struct VolumeRequest {
+    volume: f32, // 0 to 1
+}
+
+async fn set_volume(
+    State(player): State<SharedPlayer>,
+    Json(request): Json<VolumeRequest>,
+) -> ApiResult<NowPlayingInfo> {
+    player_command(&player, None, PlayerCommand::SetVolume(request.volume))
+}
+
+/// Runs the command, first making sure the queue has a position `index` if the command needs
+/// one. Both happen under one lock, so a queue changed by someone else in the meantime gives
+/// 404 rather than a failed command.
+fn execute(
+    player: &SharedPlayer,
+    index: Option<usize>,
+    command: PlayerCommand,
+) -> Result<MutexGuard<'_, Player>, ApiError> {
+    let mut player = player.lock();
+    if index.is_some_and(|index| index >= player.queue.len()) {
+        return Err(ApiError::not_found("queue position"));
+    }
+    player.execute(command)?;
+    Ok(player)
+}
+
+/// Runs the command and answers with the queue it left behind
+fn queue_command(
+    player: &SharedPlayer,
+    index: Option<usize>,
+    command: PlayerCommand,
+) -> ApiResult<QueueInfo> {
+    let player = execute(player, index, command)?;
+    Ok(Json(QueueInfo::new(&player)))
+}
+
+/// Runs the command and answers with what is playing afterwards
+fn player_command(
+    player: &SharedPlayer,
+    index: Option<usize>,
+    command: PlayerCommand,
+) -> ApiResult<NowPlayingInfo> {
+    let player = execute(player, index, command)?;
+    Ok(Json(NowPlayingInfo::new(&player)))
+}
+
+type EventSender = mpsc::Sender<Result<Event, Infallible>>;
+
+/// Sends the current state right away, then whatever changes
+async fn events(
+    State(player): State<SharedPlayer>,
+) -> Sse<ReceiverStream<Result<Event, Infallible>>> {
+    let (sender, receiver) = mpsc::channel(16);
+    tokio::spawn(watch(player, sender));
+    Sse::new(ReceiverStream::new(receiver)).keep_alive(KeepAlive::default())
+}
+
+/// `now-playing` when the song, state or volume changes, `position` every second of playback
+/// and after seeking, `queue` when the player's queue version or playback index moves. Events
+/// are only built once something changed. Stops once the listener is gone.
+async fn watch(player: SharedPlayer, sender: EventSender) {
+    let mut check = time::interval(EVENT_CHECK_INTERVAL);
+    let mut sent_now_playing = None;
+    let mut sent_position = None;
+    let mut sent_queue = None;
+
+    while !sender.is_closed() {
+        check.tick().await;
+        let mut events = Vec::new();
+        {
+            let player = player.lock();
+
+            let key = now_playing_key(&player);
+            if sent_now_playing != Some(key) {
+                events.push(event("now-playing", &NowPlayingInfo::new(&player)));
+                sent_now_playing = Some(key);
+            }
+
+            let position = player.current_position();
+            if sent_position != Some(position.as_secs()) {
+                events.push(event(
+                    "position",
+                    &json!({ "position": position.as_secs_f64() }),
+                ));
+                sent_position = Some(position.as_secs());
+            }
+
+            let key = (player.queue_version(), player.playback_index);
+            if sent_queue != Some(key) {
+                events.push(event("queue", &QueueInfo::new(&player)));
+                sent_queue = Some(key);
+            }
+        }
+
+        for event in events {
+            if sender.send(Ok(event)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Changes with whatever `now-playing` events show: the state, the song and the volume
+fn now_playing_key(player: &Player) -> (bool, bool, Option<Uuid>, u32) {
+    let song = match player.is_stopped() {
+        true => None,
+        false => player.queue.get(player.playback_index),
+    };
+    (
+        player.is_stopped(),
+        player.is_playing(),
+        song.map(|(song, _)| song.key()),
+        player.playback_settings.volume.to_bits(),
+    )
+}
+
+fn event(name: &str, data: &impl Serialize) -> Event {
+    Event::default()
+        .event(name)
+        .data(serde_json::to_string(data).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{Album, Artist};
+    use crate::GlobalSettings;
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header::CONTENT_TYPE, Request},
+    };
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    /// A stopped player with an album of three songs in its library
+    fn test_player() -> SharedPlayer {
+        let settings = GlobalSettings {
+            history_file: String::new(),
+            ..GlobalSettings::default()
+        };
+        let player = Player::new(settings);
+        {
+            let library = player.library();
+            let mut library = library.lock();
+            let artist = Artist::new(String::from("Low"));
+            for track in 1..=3 {
+                let id = Uuid::new_v4();
+                let song = Song {
+                    id,
+                    title: format!("Song {}", track),
+                    artists: vec![artist.clone()],
+                    album: Album::new(Some(String::from("Things We Lost")), artist.clone()),
+                    file_path: format!("/music/{:02}.flac", track).into(),
+                    track_number: Some(track),
+                    duration: Duration::from_secs(180),
+                    ..Song::default()
+                };
+                library.songs.insert(id, song);
+            }
+            library.rebuild_search_index();
+        }
+        Arc::new(Mutex::new(player))
+    }
+
+    fn song_ids(player: &SharedPlayer) -> Vec<Uuid> {
+        let library = player.lock().library();
+        let library = library.lock();
+        let mut songs: Vec<&Song> = library.songs.values().collect();
+        songs.sort_by_key(|song| song.track_number);
+        songs.iter().map(|song| song.id).collect()
+    }
+
+    async fn request(
+        player: &SharedPlayer,
+        method: &str,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = Request::builder().method(method).uri(uri);
+        let request = match body {
+            Some(body) => request
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+        let response = router(Arc::clone(player))
+            .oneshot(request.unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    fn titles(queue: &Value) -> Vec<&str> {
+        queue["songs"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|song| song["title"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reports_status() {
+        let player = test_player();
+        let (status, now_playing) = request(&player, "GET", "/api/now-playing", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(now_playing["state"], "stopped");
+        assert_eq!(now_playing["song"], Value::Null);
+
+        let (status, queue) = request(&player, "GET", "/api/queue", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(queue["songs"], json!([]));
+
+        let (status, _) = request(&player, "POST", "/api/player/dance", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let seek = json!({ "position": -1.0 });
+        let (status, error) = request(&player, "POST", "/api/player/seek", Some(seek)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"], "invalid position -1");
+
+        let volume = json!({ "volume": 0.25 });
+        let (status, now_playing) =
+            request(&player, "POST", "/api/player/volume", Some(volume)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(now_playing["volume"], 0.25);
+    }
+
+    #[tokio::test]
+    async fn changes_the_queue() {
+        let player = test_player();
+        let ids = song_ids(&player);
+
+        let songs = json!({ "song_ids": ids });
+        let (status, queue) = request(&player, "POST", "/api/queue", Some(songs)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(titles(&queue), ["Song 1", "Song 2", "Song 3"]);
+
+        let step = json!({ "from": 0, "to": 2 });
+        let (status, queue) = request(&player, "POST", "/api/queue/move", Some(step)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(titles(&queue), ["Song 2", "Song 3", "Song 1"]);
+
+        let (status, queue) = request(&player, "DELETE", "/api/queue/1", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(titles(&queue), ["Song 2", "Song 1"]);
+
+        let (status, queue) = request(&player, "DELETE", "/api/queue", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(titles(&queue), Vec::<&str>::new());
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_queue_positions() {
+        let player = test_player();
+        let ids = song_ids(&player);
+
+        let unknown = json!({ "song_ids": [ids[0], Uuid::new_v4()] });
+        let (status, error) = request(&player, "POST", "/api/queue", Some(unknown)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error["error"], "no such song");
+        assert!(player.lock().queue.is_empty());
+
+        let songs = json!({ "song_ids": ids });
+        request(&player, "POST", "/api/queue", Some(songs)).await;
+
+        for (method, uri, body) in [
+            ("DELETE", "/api/queue/3", None),
+            ("POST", "/api/queue/3/play", None),
+            (
+                "POST",
+                "/api/queue/move",
+                Some(json!({ "from": 3, "to": 0 })),
+            ),
+            (
+                "POST",
+                "/api/queue/move",
+                Some(json!({ "from": 0, "to": 7 })),
+            ),
+        ] {
+            let (status, error) = request(&player, method, uri, body).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{} {}", method, uri);
+            assert_eq!(error["error"], "no such queue position");
+        }
+        let (status, _) = request(&player, "DELETE", "/api/queue/first", None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(player.lock().queue.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn sends_queue_events_only_on_changes() {
+        let player = test_player();
+        let ids = song_ids(&player);
+        let (sender, mut receiver) = mpsc::channel(16);
+        tokio::spawn(watch(Arc::clone(&player), sender));
+
+        // the current state right away: now-playing, position and queue
+        for _ in 0..3 {
+            assert!(receiver.recv().await.unwrap().is_ok());
+        }
+        time::sleep(EVENT_CHECK_INTERVAL * 3).await;
+        assert!(receiver.try_recv().is_err());
+
+        let songs = {
+            let library = player.lock().library();
+            let library = library.lock();
+            ids.iter().map(|id| library.songs[id].clone()).collect()
+        };
+        player.lock().enqueue(songs);
+        let queue = time::timeout(EVENT_CHECK_INTERVAL * 4, receiver.recv()).await;
+        assert!(queue.unwrap().is_some());
+        time::sleep(EVENT_CHECK_INTERVAL * 3).await;
+        assert!(receiver.try_recv().is_err());
+    }
+}
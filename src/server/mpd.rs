@@ -0,0 +1,1426 @@
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedReadHalf, TcpListener, TcpStream},
+    time,
+};
+use uuid::Uuid;
+
+use crate::library::{SearchQuery, Song};
+use crate::player::{Player, PlayerCommand};
+use filter::{Filter, Operator, Tag, TAG_TYPES};
+
+mod filter;
+
+/// Announced to clients when they connect, the protocol version the answers follow
+const PROTOCOL_VERSION: &str = "0.23.0";
+
+/// How often the player is checked for changes while a client waits in `idle`
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Longest command line accepted, in bytes. Clients sending more are disconnected.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+/// Most bytes of commands a command list may gather, MPD's default limit
+const MAX_COMMAND_LIST_SIZE: usize = 2 * 1024 * 1024;
+
+const ACK_ERROR_ARG: u32 = 2;
+const ACK_ERROR_UNKNOWN: u32 = 5;
+const ACK_ERROR_NO_EXIST: u32 = 50;
+const ACK_ERROR_SYSTEM: u32 = 52;
+
+/// What `commands` reports, everything `execute` answers
+const COMMANDS: [&str; 59] = [
+    "add",
+    "addid",
+    "binarylimit",
+    "clear",
+    "clearerror",
+    "close",
+    "command_list_begin",
+    "command_list_end",
+    "command_list_ok_begin",
+    "commands",
+    "consume",
+    "count",
+    "currentsong",
+    "decoders",
+    "delete",
+    "deleteid",
+    "find",
+    "findadd",
+    "getvol",
+    "idle",
+    "list",
+    "listall",
+    "listallinfo",
+    "listmounts",
+    "listplaylists",
+    "lsinfo",
+    "move",
+    "moveid",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "password",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlist",
+    "playlistid",
+    "playlistinfo",
+    "plchanges",
+    "plchangesposid",
+    "previous",
+    "random",
+    "repeat",
+    "replay_gain_status",
+    "search",
+    "searchadd",
+    "seek",
+    "seekcur",
+    "seekid",
+    "setvol",
+    "single",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "urlhandlers",
+    "volume",
+];
+
+/// Lets MPD clients (mpc, ncmpcpp, phone remotes, ...) control the queue and browse the
+/// library, e.g. `mpc -p 6600 status`. Every connection drives the same `Player`.
+pub async fn serve(player: Arc<Mutex<Player>>, address: String) -> Result<()> {
+    let listener = TcpListener::bind(&address)
+        .await
+        .map_err(|e| anyhow!("cannot listen on {}: {}", address, e))?;
+    println!("MPD server listening on {}", address);
+    accept_clients(player, listener).await
+}
+
+async fn accept_clients(player: Arc<Mutex<Player>>, listener: TcpListener) -> Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let client = Client::new(Arc::clone(&player));
+        tokio::spawn(async move {
+            if let Err(e) = client.run(stream).await {
+                println!("MPD client {} disconnected: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// An error as clients expect it, `ACK [code@command_index] {command} message`
+#[derive(Debug)]
+struct Ack {
+    code: u32,
+    message: String,
+}
+
+impl Ack {
+    fn arg(message: impl Into<String>) -> Ack {
+        Ack {
+            code: ACK_ERROR_ARG,
+            message: message.into(),
+        }
+    }
+
+    fn no_exist(message: impl Into<String>) -> Ack {
+        Ack {
+            code: ACK_ERROR_NO_EXIST,
+            message: message.into(),
+        }
+    }
+
+    fn response(&self, index: usize, command: &str) -> String {
+        format!(
+            "ACK [{}@{}] {{{}}} {}\n",
+            self.code, index, command, self.message
+        )
+    }
+}
+
+impl From<anyhow::Error> for Ack {
+    fn from(e: anyhow::Error) -> Ack {
+        Ack {
+            code: ACK_ERROR_SYSTEM,
+            message: e.to_string(),
+        }
+    }
+}
+
+/// The state `idle` watches, one field per subsystem
+#[derive(Debug, Clone, PartialEq)]
+struct Snapshot {
+    player: (&'static str, usize, Option<Uuid>), // state, queue position and song
+    mixer: u32,
+    playlist: u32,
+    database: usize,
+}
+
+impl Snapshot {
+    fn take(player: &Player) -> Snapshot {
+        Snapshot {
+            player: (
+                state(player),
+                player.playback_index,
+                player
+                    .queue
+                    .get(player.playback_index)
+                    .map(|(song, _)| song.key()),
+            ),
+            mixer: volume(player),
+            playlist: player.queue_version(),
+            database: player.library().lock().songs.len(),
+        }
+    }
+
+    /// Names of the subsystems that differ between the two
+    fn changes(&self, now: &Snapshot) -> Vec<&'static str> {
+        [
+            ("player", self.player != now.player),
+            ("mixer", self.mixer != now.mixer),
+            ("playlist", self.playlist != now.playlist),
+            ("database", self.database != now.database),
+        ]
+        .into_iter()
+        .filter_map(|(subsystem, changed)| changed.then_some(subsystem))
+        .collect()
+    }
+}
+
+struct Client {
+    player: Arc<Mutex<Player>>,
+    seen: Snapshot, // as the client was last told about by `idle`
+}
+
+impl Client {
+    fn new(player: Arc<Mutex<Player>>) -> Client {
+        let seen = Snapshot::take(&player.lock());
+        Client { player, seen }
+    }
+
+    async fn run(mut self, stream: TcpStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = LineReader::new(reader);
+        writer
+            .write_all(format!("OK MPD {}\n", PROTOCOL_VERSION).as_bytes())
+            .await?;
+
+        let mut command_list: Option<(Vec<String>, bool)> = None; // commands, `list_OK` after each
+        let mut command_list_size = 0;
+        while let Some(line) = lines.next_line().await? {
+            let response = if let Some((commands, list_ok)) = &mut command_list {
+                if line.trim() != "command_list_end" {
+                    command_list_size += line.len();
+                    if command_list_size > MAX_COMMAND_LIST_SIZE {
+                        return Err(anyhow!("command list too long"));
+                    }
+                    commands.push(line);
+                    continue;
+                }
+                let response = self.run_commands(commands, *list_ok);
+                command_list = None;
+                response
+            } else {
+                match line.trim() {
+                    "command_list_begin" => {
+                        command_list = Some((Vec::new(), false));
+                        command_list_size = 0;
+                        continue;
+                    }
+                    "command_list_ok_begin" => {
+                        command_list = Some((Vec::new(), true));
+                        command_list_size = 0;
+                        continue;
+                    }
+                    "close" => break,
+                    // only means something while idling
+                    "noidle" => continue,
+                    command if command == "idle" || command.starts_with("idle ") => {
+                        let subsystems = match split_line(command) {
+                            Ok(words) => words[1..].to_vec(),
+                            Err(ack) => {
+                                writer.write_all(ack.response(0, "idle").as_bytes()).await?;
+                                continue;
+                            }
+                        };
+                        match self.idle(&subsystems, &mut lines).await? {
+                            Some(response) => response,
+                            None => break,
+                        }
+                    }
+                    _ => self.run_commands(&[line], false),
+                }
+            };
+            writer.write_all(response.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    /// Answers each command in turn, stopping at the first one that fails
+    fn run_commands(&mut self, lines: &[String], list_ok: bool) -> String {
+        let mut response = String::new();
+        for (index, line) in lines.iter().enumerate() {
+            let words = match split_line(line) {
+                Ok(words) => words,
+                Err(ack) => return response + &ack.response(index, ""),
+            };
+            let Some((command, args)) = words.split_first() else {
+                let ack = Ack {
+                    code: ACK_ERROR_UNKNOWN,
+                    message: "No command given".to_string(),
+                };
+                return response + &ack.response(index, "");
+            };
+
+            match execute(&mut self.player.lock(), command, args) {
+                Ok(output) => {
+                    response += &output;
+                    if list_ok {
+                        response += "list_OK\n";
+                    }
+                }
+                Err(ack) => return response + &ack.response(index, command),
+            }
+        }
+        response + "OK\n"
+    }
+
+    /// Waits until one of `subsystems` (any if empty) changes or the client sends `noidle`.
+    /// `None` when the client went away or sent something else meanwhile.
+    async fn idle(
+        &mut self,
+        subsystems: &[String],
+        lines: &mut LineReader,
+    ) -> Result<Option<String>> {
+        let mut check = time::interval(IDLE_CHECK_INTERVAL);
+        loop {
+            let now = Snapshot::take(&self.player.lock());
+            let changed: Vec<&str> = self
+                .seen
+                .changes(&now)
+                .into_iter()
+                .filter(|name| subsystems.is_empty() || subsystems.iter().any(|s| s == name))
+                .collect();
+            if !changed.is_empty() {
+                self.seen = now;
+                let response: String = changed
+                    .iter()
+                    .map(|name| format!("changed: {}\n", name))
+                    .collect();
+                return Ok(Some(response + "OK\n"));
+            }
+
+            tokio::select! {
+                _ = check.tick() => {}
+                line = lines.next_line() => {
+                    return Ok(match line? {
+                        Some(line) if line.trim() == "noidle" => Some("OK\n".to_string()),
+                        _ => None,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Reads a client's command lines, refusing ones longer than `MAX_LINE_LENGTH`
+struct LineReader {
+    reader: BufReader<OwnedReadHalf>,
+    line: Vec<u8>, // read so far, kept when `next_line` is cancelled by `idle`
+}
+
+impl LineReader {
+    fn new(reader: OwnedReadHalf) -> LineReader {
+        LineReader {
+            reader: BufReader::new(reader),
+            line: Vec::new(),
+        }
+    }
+
+    /// The next line without its line ending, `None` once the client closed the connection
+    async fn next_line(&mut self) -> Result<Option<String>> {
+        loop {
+            let limit = (MAX_LINE_LENGTH + 1).saturating_sub(self.line.len()) as u64;
+            let read = (&mut self.reader)
+                .take(limit)
+                .read_until(b'\n', &mut self.line)
+                .await?;
+            if self.line.ends_with(b"\n") {
+                break;
+            }
+            if self.line.len() > MAX_LINE_LENGTH {
+                return Err(anyhow!("line too long"));
+            }
+            if read == 0 {
+                if self.line.is_empty() {
+                    return Ok(None);
+                }
+                // the last line does not need a line ending
+                break;
+            }
+        }
+
+        let mut line = String::from_utf8(std::mem::take(&mut self.line))?;
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(Some(line))
+    }
+}
+
+/// Splits a command line into words, where quoted words may contain spaces and `\"`
+fn split_line(line: &str) -> Result<Vec<String>, Ack> {
+    let mut words = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut word = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => word.extend(chars.next()),
+                    Some(c) => word.push(c),
+                    None => return Err(Ack::arg("Missing closing '\"'")),
+                }
+            }
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+            words.push(word);
+        }
+    }
+    Ok(words)
+}
+
+fn execute(player: &mut Player, command: &str, args: &[String]) -> Result<String, Ack> {
+    let music_dir = PathBuf::from(&player.settings.folder_to_scan);
+    let mut out = String::new();
+
+    match command {
+        "ping" | "clearerror" | "password" | "binarylimit" => {}
+        "status" => write_status(&mut out, player),
+        "currentsong" => {
+            if player.playback_index < player.queue.len() {
+                write_queue_entry(&mut out, player, player.playback_index, &music_dir);
+            }
+        }
+        "stats" => {
+            let library = player.library();
+            let library = library.lock();
+            write_stats(&mut out, library.songs.values());
+        }
+
+        "play" | "playid" => {
+            let index = match args.first() {
+                Some(arg) if command == "play" => Some(parse(arg)?),
+                Some(arg) => Some(index_of_id(player, arg)?),
+                None => None,
+            };
+            match index {
+                Some(index) if index >= player.queue.len() => {
+                    return Err(Ack::arg("Bad song index"))
+                }
+                Some(index) => player.execute(PlayerCommand::PlayQueueIndex(index))?,
+                None => player.execute(PlayerCommand::SetPaused(false))?,
+            }
+        }
+        "pause" => {
+            // pausing or resuming does not start playback when stopped
+            if !player.is_stopped() {
+                let command = match args.first().map(String::as_str) {
+                    Some("1") => PlayerCommand::SetPaused(true),
+                    Some("0") => PlayerCommand::SetPaused(false),
+                    Some(arg) => return Err(Ack::arg(format!("Boolean (0/1) expected: {}", arg))),
+                    None => PlayerCommand::TogglePlayback,
+                };
+                player.execute(command)?;
+            }
+        }
+        "stop" => player.execute(PlayerCommand::Stop)?,
+        "next" => player.execute(PlayerCommand::NextSong)?,
+        "previous" => player.execute(PlayerCommand::PreviousSong)?,
+        "seek" | "seekid" => {
+            let index = match command {
+                "seek" => parse(arg(args, 0)?)?,
+                _ => index_of_id(player, arg(args, 0)?)?,
+            };
+            if index >= player.queue.len() {
+                return Err(Ack::arg("Bad song index"));
+            }
+            let position = parse_seconds(arg(args, 1)?)?;
+            if index != player.playback_index || player.is_stopped() {
+                player.execute(PlayerCommand::PlayQueueIndex(index))?;
+            }
+            player.execute(PlayerCommand::SeekTo(position))?;
+        }
+        "seekcur" => {
+            let time = arg(args, 0)?;
+            let command = if let Some(step) = time.strip_prefix('+') {
+                PlayerCommand::SeekBy(parse_seconds(step)?, true)
+            } else if let Some(step) = time.strip_prefix('-') {
+                PlayerCommand::SeekBy(parse_seconds(step)?, false)
+            } else {
+                PlayerCommand::SeekTo(parse_seconds(time)?)
+            };
+            player.execute(command)?;
+        }
+        "setvol" => {
+            let volume: u32 = parse(arg(args, 0)?)?;
+            if volume > 100 {
+                return Err(Ack::arg("Invalid volume value"));
+            }
+            player.execute(PlayerCommand::SetVolume(volume as f32 / 100.0))?;
+        }
+        "volume" => {
+            let change: i32 = parse(arg(args, 0)?)?;
+            player.execute(PlayerCommand::ChangeVolume(change as f32 / 100.0))?;
+        }
+        "getvol" => {
+            let _ = writeln!(out, "volume: {}", volume(player));
+        }
+        "repeat" | "random" | "single" | "consume" => {
+            if arg(args, 0)? != "0" {
+                return Err(Ack::arg(format!("{} is not supported", command)));
+            }
+        }
+
+        "add" => {
+            let songs = songs_at(player, arg(args, 0)?, &music_dir)?;
+            player.enqueue(songs);
+        }
+        "addid" => {
+            let uri = arg(args, 0)?;
+            let song = library_songs(player, |song| {
+                song_uri(song, &music_dir) == uri.trim_matches('/')
+            })
+            .into_iter()
+            .next()
+            .ok_or_else(|| Ack::no_exist("No such song"))?;
+            let end = player.queue.len();
+            let position = match args.get(1) {
+                Some(arg) => parse(arg)?,
+                None => end,
+            };
+            if position > end {
+                return Err(Ack::arg("Bad song index"));
+            }
+            player.enqueue(vec![song]);
+            if position < end {
+                player.execute(PlayerCommand::MoveInQueue(end, position))?;
+            }
+            let _ = writeln!(out, "Id: {}", queue_id(player, position));
+        }
+        "delete" | "deleteid" => {
+            let range = match command {
+                "delete" => parse_range(arg(args, 0)?, player.queue.len())?,
+                _ => {
+                    let index = index_of_id(player, arg(args, 0)?)?;
+                    index..index + 1
+                }
+            };
+            player.execute(PlayerCommand::RemoveFromQueue(range))?;
+        }
+        "move" | "moveid" => {
+            let range = match command {
+                "move" => parse_range(arg(args, 0)?, player.queue.len())?,
+                _ => {
+                    let index = index_of_id(player, arg(args, 0)?)?;
+                    index..index + 1
+                }
+            };
+            let to: usize = parse(arg(args, 1)?)?;
+            if to + range.len() > player.queue.len() {
+                return Err(Ack::arg("Bad song index"));
+            }
+            move_block(player, range, to)?;
+        }
+        "clear" => player.execute(PlayerCommand::ClearQueue)?,
+        "playlist" => {
+            for (index, (song, _)) in player.queue.iter().enumerate() {
+                let _ = writeln!(out, "{}:file: {}", index, song_uri(song, &music_dir));
+            }
+        }
+        "playlistinfo" | "playlistid" | "plchanges" | "plchangesposid" => {
+            // `plchanges` asks for what changed since a version: nothing when that is the
+            // current one, otherwise the whole queue, which is always correct
+            let range = match (command, args.first()) {
+                ("plchanges" | "plchangesposid", Some(arg))
+                    if parse::<u32>(arg)? == player.queue_version() =>
+                {
+                    0..0
+                }
+                ("playlistinfo", Some(arg)) => parse_range(arg, player.queue.len())?,
+                ("playlistid", Some(arg)) => {
+                    let index = index_of_id(player, arg)?;
+                    index..index + 1
+                }
+                _ => 0..player.queue.len(),
+            };
+            for index in range {
+                if command == "plchangesposid" {
+                    let _ = write!(out, "cpos: {}\nId: {}\n", index, queue_id(player, index));
+                } else {
+                    write_queue_entry(&mut out, player, index, &music_dir);
+                }
+            }
+        }
+
+        "list" => {
+            let tag = Tag::parse(arg(args, 0)?)
+                .filter(|tag| *tag != Tag::Any)
+                .ok_or_else(|| Ack::arg(format!("Unknown tag type: {}", args[0])))?;
+            let (filter, options) = parse_filter(&args[1..], Operator::Equals, tag)?;
+            let songs = matching_songs(player, &filter, false, &music_dir);
+            write_list(&mut out, &songs, tag, &options.groups, &music_dir);
+        }
+        "find" | "search" | "findadd" | "searchadd" | "count" => {
+            let (operator, ignore_case) = match command {
+                "search" | "searchadd" => (Operator::Contains, true),
+                _ => (Operator::Equals, false),
+            };
+            let (filter, options) = parse_filter(args, operator, Tag::Any)?;
+            let mut songs = matching_songs(player, &filter, ignore_case, &music_dir);
+            if let Some((tag, descending)) = options.sort {
+                songs.sort_by(|a, b| {
+                    let order = compare_tag(tag, a, b, &music_dir);
+                    if descending {
+                        order.reverse()
+                    } else {
+                        order
+                    }
+                });
+            }
+            if let Some(window) = options.window {
+                songs = songs
+                    .get(parse_range(&window, songs.len())?)
+                    .unwrap()
+                    .to_vec();
+            }
+
+            match command {
+                "findadd" | "searchadd" => player.enqueue(songs),
+                "count" => write_count(&mut out, &songs, &options.groups, &music_dir),
+                _ => {
+                    for song in &songs {
+                        write_song(&mut out, song, &music_dir);
+                    }
+                }
+            }
+        }
+        "lsinfo" | "listall" | "listallinfo" => {
+            let uri = args.first().map_or("", |uri| uri.trim_matches('/'));
+            let library = player.library();
+            let library = library.lock();
+            let mut songs: Vec<(String, &Song)> = library
+                .songs
+                .values()
+                .map(|song| (song_uri(song, &music_dir), song))
+                .collect();
+            songs.sort_by(|(a, _), (b, _)| a.cmp(b));
+            write_directory(&mut out, &songs, uri, command, &music_dir)?;
+        }
+
+        "tagtypes" => {
+            // enabling and disabling tags is accepted, every tag is always sent
+            if args.is_empty() {
+                for tag in TAG_TYPES {
+                    let _ = writeln!(out, "tagtype: {}", tag.name());
+                }
+            }
+        }
+        "commands" => {
+            for command in COMMANDS {
+                let _ = writeln!(out, "command: {}", command);
+            }
+        }
+        "notcommands" | "urlhandlers" | "decoders" | "listplaylists" | "listmounts" => {}
+        "outputs" => {
+            out += "outputid: 0\noutputname: Default\nplugin: rodio\noutputenabled: 1\n";
+        }
+        "replay_gain_status" => out += "replay_gain_mode: off\n",
+        _ => {
+            return Err(Ack {
+                code: ACK_ERROR_UNKNOWN,
+                message: format!("unknown command \"{}\"", command),
+            })
+        }
+    }
+
+    Ok(out)
+}
+
+fn arg(args: &[String], index: usize) -> Result<&str, Ack> {
+    args.get(index)
+        .map(String::as_str)
+        .ok_or_else(|| Ack::arg("too few arguments"))
+}
+
+fn parse<T: FromStr>(arg: &str) -> Result<T, Ack> {
+    arg.parse()
+        .map_err(|_| Ack::arg(format!("Invalid argument: {}", arg)))
+}
+
+fn parse_seconds(arg: &str) -> Result<Duration, Ack> {
+    Duration::try_from_secs_f64(parse(arg)?).map_err(|_| Ack::arg(format!("Invalid time: {}", arg)))
+}
+
+/// `POS`, `START:END` or `START:` (to the end) within a list of `len` items
+fn parse_range(arg: &str, len: usize) -> Result<Range<usize>, Ack> {
+    let range = match arg.split_once(':') {
+        Some((start, "")) => parse(start)?..len,
+        Some((start, end)) => parse(start)?..parse(end)?,
+        None => {
+            let position = parse(arg)?;
+            position..position + 1
+        }
+    };
+    if range.start > range.end || range.end > len {
+        return Err(Ack::arg("Bad song index"));
+    }
+    Ok(range)
+}
+
+/// Song ids stay with their queue entry, see `Player::queue_id`
+fn index_of_id(player: &Player, arg: &str) -> Result<usize, Ack> {
+    player
+        .queue_index_of_id(parse(arg)?)
+        .ok_or_else(|| Ack::no_exist("No such song"))
+}
+
+fn queue_id(player: &Player, index: usize) -> u32 {
+    player.queue_id(index).unwrap_or_default()
+}
+
+/// Moves the songs in `range` so that the first of them ends up at `to`
+fn move_block(player: &mut Player, range: Range<usize>, to: usize) -> Result<(), Ack> {
+    let count = range.len();
+    if to < range.start {
+        for offset in 0..count {
+            player.execute(PlayerCommand::MoveInQueue(
+                range.start + offset,
+                to + offset,
+            ))?;
+        }
+    } else if to > range.start {
+        for _ in 0..count {
+            player.execute(PlayerCommand::MoveInQueue(range.start, to + count - 1))?;
+        }
+    }
+    Ok(())
+}
+
+fn state(player: &Player) -> &'static str {
+    if player.is_stopped() {
+        "stop"
+    } else if player.is_playing() {
+        "play"
+    } else {
+        "pause"
+    }
+}
+
+fn volume(player: &Player) -> u32 {
+    (player.playback_settings.volume * 100.0).round() as u32
+}
+
+/// Where clients find a song: its path below the music folder with `/` separators, with the
+/// track number below that for cue sheet tracks, e.g. `Album/album.flac/track0003`
+fn song_uri(song: &Song, music_dir: &Path) -> String {
+    let path = song
+        .file_path
+        .strip_prefix(music_dir)
+        .unwrap_or(&song.file_path);
+    let mut uri = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    if song.cue_range.is_some() {
+        let _ = write!(uri, "/track{:04}", song.track_number.unwrap_or(0));
+    }
+    uri
+}
+
+/// The library songs `keep` accepts, in library order. Only those are copied out of the
+/// library, the rest are looked at where they are.
+fn library_songs(player: &Player, keep: impl Fn(&Song) -> bool) -> Vec<Song> {
+    let library = player.library();
+    let library = library.lock();
+    library
+        .search(&SearchQuery::default())
+        .iter()
+        .filter_map(|id| library.songs.get(id))
+        .filter(|song| keep(song))
+        .cloned()
+        .collect()
+}
+
+/// The song at `uri`, or every song below it when it is a directory, in file name order
+fn songs_at(player: &Player, uri: &str, music_dir: &Path) -> Result<Vec<Song>, Ack> {
+    let uri = uri.trim_matches('/');
+    let directory = format!("{}/", uri);
+    let mut songs: Vec<(String, Song)> = library_songs(player, |song| {
+        let song_uri = song_uri(song, music_dir);
+        uri.is_empty() || song_uri == uri || song_uri.starts_with(&directory)
+    })
+    .into_iter()
+    .map(|song| (song_uri(&song, music_dir), song))
+    .collect();
+    if songs.is_empty() {
+        return Err(Ack::no_exist("No such directory"));
+    }
+    songs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(songs.into_iter().map(|(_, song)| song).collect())
+}
+
+/// The `sort`, `window` and `group` arguments that can follow a filter
+#[derive(Default)]
+struct FilterOptions {
+    sort: Option<(Tag, bool)>, // descending when true
+    window: Option<String>,
+    groups: Vec<Tag>,
+}
+
+/// `default_tag` is the tag a lone value filters on, which is how old clients ask for the
+/// albums of an artist (`list album "Low"`)
+fn parse_filter(
+    mut args: &[String],
+    operator: Operator,
+    default_tag: Tag,
+) -> Result<(Filter, FilterOptions), Ack> {
+    let mut options = FilterOptions::default();
+    while let [rest @ .., keyword, value] = args {
+        match keyword.to_lowercase().as_str() {
+            "sort" => {
+                let (name, descending) = match value.strip_prefix('-') {
+                    Some(name) => (name, true),
+                    None => (value.as_str(), false),
+                };
+                let tag =
+                    Tag::parse(name).ok_or_else(|| Ack::arg(format!("Unknown tag: {}", name)))?;
+                options.sort = Some((tag, descending));
+            }
+            "window" => options.window = Some(value.clone()),
+            "group" => {
+                let tag =
+                    Tag::parse(value).ok_or_else(|| Ack::arg(format!("Unknown tag: {}", value)))?;
+                options.groups.insert(0, tag);
+            }
+            _ => break,
+        }
+        args = rest;
+    }
+
+    let filter = match args {
+        [value] if default_tag == Tag::Album && !value.trim_start().starts_with('(') => {
+            Filter::Compare(Tag::Artist, Operator::Equals, value.clone())
+        }
+        args => Filter::parse(args, operator).map_err(Ack::arg)?,
+    };
+    Ok((filter, options))
+}
+
+fn matching_songs(
+    player: &Player,
+    filter: &Filter,
+    ignore_case: bool,
+    music_dir: &Path,
+) -> Vec<Song> {
+    library_songs(player, |song| filter.matches(song, music_dir, ignore_case))
+}
+
+/// Numbers compare as numbers, so track 10 comes after track 9
+fn compare_tag(tag: Tag, a: &Song, b: &Song, music_dir: &Path) -> Ordering {
+    let first = |song| tag.values(song, music_dir).into_iter().next();
+    let (a, b) = (first(a).unwrap_or_default(), first(b).unwrap_or_default());
+    match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.to_lowercase().cmp(&b.to_lowercase()),
+    }
+}
+
+fn write_song(out: &mut String, song: &Song, music_dir: &Path) {
+    let _ = writeln!(out, "file: {}", song_uri(song, music_dir));
+    for tag in TAG_TYPES {
+        for value in tag.values(song, music_dir) {
+            let _ = writeln!(out, "{}: {}", tag.name(), value);
+        }
+    }
+    let _ = writeln!(out, "Time: {}", song.duration.as_secs());
+    let _ = writeln!(out, "duration: {:.3}", song.duration.as_secs_f64());
+}
+
+fn write_queue_entry(out: &mut String, player: &Player, index: usize, music_dir: &Path) {
+    write_song(out, &player.queue[index].0, music_dir);
+    let _ = write!(out, "Pos: {}\nId: {}\n", index, queue_id(player, index));
+}
+
+fn write_status(out: &mut String, player: &Player) {
+    let state = state(player);
+    let _ = write!(
+        out,
+        "volume: {}\nrepeat: 0\nrandom: 0\nsingle: 0\nconsume: 0\nplaylist: {}\n\
+         playlistlength: {}\nstate: {}\n",
+        volume(player),
+        player.queue_version(),
+        player.queue.len(),
+        state
+    );
+
+    let index = player.playback_index;
+    if let Some((song, _)) = player.queue.get(index) {
+        let _ = write!(
+            out,
+            "song: {}\nsongid: {}\n",
+            index,
+            queue_id(player, index)
+        );
+        if state != "stop" {
+            let elapsed = player.current_position();
+            let _ = write!(
+                out,
+                "time: {}:{}\nelapsed: {:.3}\nduration: {:.3}\n",
+                elapsed.as_secs(),
+                song.duration.as_secs(),
+                elapsed.as_secs_f64(),
+                song.duration.as_secs_f64()
+            );
+        }
+    }
+    if index + 1 < player.queue.len() {
+        let _ = write!(
+            out,
+            "nextsong: {}\nnextsongid: {}\n",
+            index + 1,
+            queue_id(player, index + 1)
+        );
+    }
+}
+
+fn write_stats<'a>(out: &mut String, songs: impl Iterator<Item = &'a Song>) {
+    let mut artists = BTreeSet::new();
+    let mut albums = BTreeSet::new();
+    let mut count = 0;
+    let mut playtime = Duration::ZERO;
+    for song in songs {
+        artists.extend(song.artists.iter().map(|artist| artist.name.as_str()));
+        albums.extend(song.album.as_ref().map(|album| album.id));
+        count += 1;
+        playtime += song.duration;
+    }
+    let _ = write!(
+        out,
+        "artists: {}\nalbums: {}\nsongs: {}\nuptime: 0\nplaytime: 0\ndb_playtime: {}\ndb_update: 0\n",
+        artists.len(),
+        albums.len(),
+        count,
+        playtime.as_secs()
+    );
+}
+
+/// The first value of each group tag, `group` arguments sort and split the results by these
+fn group_key(song: &Song, groups: &[Tag], music_dir: &Path) -> Vec<String> {
+    groups
+        .iter()
+        .map(|tag| {
+            tag.values(song, music_dir)
+                .into_iter()
+                .next()
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn write_group_header(out: &mut String, groups: &[Tag], key: &[String]) {
+    for (tag, value) in groups.iter().zip(key) {
+        let _ = writeln!(out, "{}: {}", tag.name(), value);
+    }
+}
+
+/// The distinct values of `tag`, grouped
+fn write_list(out: &mut String, songs: &[Song], tag: Tag, groups: &[Tag], music_dir: &Path) {
+    let values: BTreeSet<(Vec<String>, String)> = songs
+        .iter()
+        .flat_map(|song| {
+            let key = group_key(song, groups, music_dir);
+            tag.values(song, music_dir)
+                .into_iter()
+                .map(move |value| (key.clone(), value))
+        })
+        .collect();
+
+    let mut last_key = None;
+    for (key, value) in &values {
+        if last_key != Some(key) {
+            write_group_header(out, groups, key);
+            last_key = Some(key);
+        }
+        let _ = writeln!(out, "{}: {}", tag.name(), value);
+    }
+}
+
+fn write_count(out: &mut String, songs: &[Song], groups: &[Tag], music_dir: &Path) {
+    let mut counts: BTreeMap<Vec<String>, (usize, Duration)> = BTreeMap::new();
+    for song in songs {
+        let (count, playtime) = counts
+            .entry(group_key(song, groups, music_dir))
+            .or_default();
+        *count += 1;
+        *playtime += song.duration;
+    }
+    if counts.is_empty() && groups.is_empty() {
+        counts.insert(Vec::new(), (0, Duration::ZERO));
+    }
+
+    for (key, (count, playtime)) in &counts {
+        write_group_header(out, groups, key);
+        let _ = write!(out, "songs: {}\nplaytime: {}\n", count, playtime.as_secs());
+    }
+}
+
+/// `lsinfo` lists what is directly inside `uri` (or the song at it), `listall` and
+/// `listallinfo` everything below it. `songs` are sorted by uri.
+fn write_directory(
+    out: &mut String,
+    songs: &[(String, &Song)],
+    uri: &str,
+    command: &str,
+    music_dir: &Path,
+) -> Result<(), Ack> {
+    if let Some((_, song)) = songs.iter().find(|(song_uri, _)| song_uri == uri) {
+        write_song(out, song, music_dir);
+        return Ok(());
+    }
+
+    let prefix = match uri {
+        "" => String::new(),
+        uri => format!("{}/", uri),
+    };
+    let inside: Vec<&(String, &Song)> = songs
+        .iter()
+        .filter(|(song_uri, _)| song_uri.starts_with(&prefix))
+        .collect();
+    if inside.is_empty() && !uri.is_empty() {
+        return Err(Ack::no_exist("No such directory"));
+    }
+
+    let mut listed_directories = BTreeSet::new();
+    let mut files = String::new();
+    for (song_uri, song) in inside {
+        let relative = &song_uri[prefix.len()..];
+        let parts: Vec<&str> = relative.split('/').collect();
+        let depth = match command {
+            "lsinfo" => 1,
+            _ => parts.len() - 1,
+        };
+        for end in 1..=depth.min(parts.len() - 1) {
+            let directory = format!("{}{}", prefix, parts[..end].join("/"));
+            if listed_directories.insert(directory.clone()) {
+                let _ = writeln!(out, "directory: {}", directory);
+            }
+        }
+
+        match command {
+            "lsinfo" if parts.len() > 1 => {}
+            "lsinfo" => write_song(&mut files, song, music_dir),
+            "listall" => {
+                let _ = writeln!(out, "file: {}", song_uri);
+            }
+            _ => write_song(out, song, music_dir),
+        }
+    }
+    // `lsinfo` lists the directories first
+    out.push_str(&files);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{Album, Artist};
+    use crate::GlobalSettings;
+    use tokio::io::AsyncBufRead;
+
+    const MUSIC_DIR: &str = "/music";
+
+    /// A stopped player with a library of two albums, three songs each
+    fn test_player() -> Player {
+        let settings = GlobalSettings {
+            folder_to_scan: String::from(MUSIC_DIR),
+            history_file: String::new(),
+            ..GlobalSettings::default()
+        };
+        let player = Player::new(settings);
+        {
+            let library = player.library();
+            let mut library = library.lock();
+            for (artist, album, year) in [
+                ("Low", "Things We Lost", 2001),
+                ("Talk Talk", "Laughing Stock", 1991),
+            ] {
+                let artist = Artist::new(artist.to_string());
+                for track in 1..=3 {
+                    let id = Uuid::new_v4();
+                    let song = Song {
+                        id,
+                        title: format!("{} {}", album, track),
+                        artists: vec![artist.clone()],
+                        album: Album::new(Some(album.to_string()), artist.clone()),
+                        file_path: Path::new(MUSIC_DIR)
+                            .join(&artist.name)
+                            .join(album)
+                            .join(format!("{:02}.flac", track)),
+                        year,
+                        track_number: Some(track),
+                        duration: Duration::from_secs(180),
+                        ..Song::default()
+                    };
+                    library.songs.insert(id, song);
+                }
+            }
+            library.rebuild_search_index();
+        }
+        player
+    }
+
+    fn run(player: &mut Player, line: &str) -> Result<String, Ack> {
+        let words = split_line(line)?;
+        execute(player, &words[0], &words[1..])
+    }
+
+    fn queue_titles(player: &Player) -> Vec<String> {
+        player
+            .queue
+            .iter()
+            .map(|(song, _)| song.title.clone())
+            .collect()
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn splits_lines_into_words() {
+        assert_eq!(split_line("  status ").unwrap(), strings(&["status"]));
+        assert_eq!(
+            split_line(r#"find "album" "Laughing \"Stock\"" title x"#).unwrap(),
+            strings(&["find", "album", r#"Laughing "Stock""#, "title", "x"])
+        );
+        assert_eq!(split_line(r#"add """#).unwrap(), strings(&["add", ""]));
+        assert!(split_line("").unwrap().is_empty());
+        assert!(split_line(r#"add "Low"#).is_err());
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("2", 5).unwrap(), 2..3);
+        assert_eq!(parse_range("1:3", 5).unwrap(), 1..3);
+        assert_eq!(parse_range("3:", 5).unwrap(), 3..5);
+        assert_eq!(parse_range("5:", 5).unwrap(), 5..5);
+        for bad in ["5", "3:1", "0:6", "x", "-1", ":2"] {
+            assert!(parse_range(bad, 5).is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn parses_filters() {
+        let compare =
+            |tag, operator, value: &str| Filter::Compare(tag, operator, value.to_string());
+        assert_eq!(
+            Filter::parse(
+                &strings(&["artist", "Low", "date", "2001"]),
+                Operator::Contains
+            )
+            .unwrap(),
+            Filter::And(vec![
+                compare(Tag::Artist, Operator::Contains, "Low"),
+                compare(Tag::Date, Operator::Contains, "2001"),
+            ])
+        );
+        assert_eq!(
+            Filter::parse(
+                &strings(&[r#"((artist == 'Low') AND (!(title contains "it's \"1\"")))"#]),
+                Operator::Equals
+            )
+            .unwrap(),
+            Filter::And(vec![
+                compare(Tag::Artist, Operator::Equals, "Low"),
+                Filter::Not(Box::new(compare(
+                    Tag::Title,
+                    Operator::Contains,
+                    r#"it's "1""#
+                ))),
+            ])
+        );
+        for bad in [
+            vec!["artist"],
+            vec!["colour", "red"],
+            vec!["(colour == 'red')"],
+            vec!["(artist =~ 'Low')"],
+            vec!["(artist == 'Low'"],
+            vec!["(artist == Low)"],
+            vec!["(artist == 'Low') trailing"],
+        ] {
+            assert!(
+                Filter::parse(&strings(&bad), Operator::Equals).is_err(),
+                "{:?} parsed",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn limits_filter_nesting() {
+        let nested = |depth: usize| {
+            format!(
+                "{}(artist == 'Low'){}",
+                "(!".repeat(depth),
+                ")".repeat(depth)
+            )
+        };
+        assert!(Filter::parse(&[nested(10)], Operator::Equals).is_ok());
+        assert!(Filter::parse(&[nested(100_000)], Operator::Equals).is_err());
+    }
+
+    #[test]
+    fn moves_blocks_of_songs() {
+        let mut player = test_player();
+        run(&mut player, "add Low").unwrap();
+        run(&mut player, "add \"Talk Talk\"").unwrap();
+        let titles = queue_titles(&player);
+
+        move_block(&mut player, 0..2, 3).unwrap();
+        let order = [2, 3, 4, 0, 1, 5];
+        assert_eq!(
+            queue_titles(&player),
+            order.map(|index| titles[index].clone())
+        );
+
+        move_block(&mut player, 3..6, 0).unwrap();
+        let order = [0, 1, 5, 2, 3, 4];
+        assert_eq!(
+            queue_titles(&player),
+            order.map(|index| titles[index].clone())
+        );
+    }
+
+    #[test]
+    fn executes_queue_commands() {
+        let mut player = test_player();
+        let status = run(&mut player, "status").unwrap();
+        assert!(status.contains("playlistlength: 0\n"));
+        assert!(status.contains("state: stop\n"));
+
+        run(&mut player, "add Low/Things We Lost").unwrap_err();
+        run(&mut player, "add \"Low/Things We Lost\"").unwrap();
+        assert_eq!(player.queue.len(), 3);
+        let added = run(&mut player, "addid \"Talk Talk/Laughing Stock/02.flac\" 1").unwrap();
+        assert_eq!(queue_titles(&player)[1], "Laughing Stock 2");
+        let id: u32 = added.trim().strip_prefix("Id: ").unwrap().parse().unwrap();
+
+        // ids stay with their songs while the queue changes around them
+        run(&mut player, "move 1 3").unwrap();
+        run(&mut player, "delete 0").unwrap();
+        let entry = run(&mut player, &format!("playlistid {}", id)).unwrap();
+        assert!(entry.contains("Title: Laughing Stock 2\n"), "{}", entry);
+        assert!(entry.contains("Pos: 2\n"), "{}", entry);
+        run(&mut player, &format!("deleteid {}", id)).unwrap();
+        assert_eq!(
+            run(&mut player, &format!("deleteid {}", id))
+                .unwrap_err()
+                .code,
+            ACK_ERROR_NO_EXIST
+        );
+        assert_eq!(
+            queue_titles(&player),
+            ["Things We Lost 2", "Things We Lost 3"]
+        );
+
+        let found = run(
+            &mut player,
+            "find \"(album == 'Laughing Stock')\" sort -track",
+        )
+        .unwrap();
+        let titles: Vec<&str> = found
+            .lines()
+            .filter_map(|line| line.strip_prefix("Title: "))
+            .collect();
+        assert_eq!(
+            titles,
+            ["Laughing Stock 3", "Laughing Stock 2", "Laughing Stock 1"]
+        );
+
+        assert_eq!(
+            run(&mut player, "setvol 101").unwrap_err().code,
+            ACK_ERROR_ARG
+        );
+        run(&mut player, "clear").unwrap();
+        assert!(player.queue.is_empty());
+    }
+
+    #[test]
+    fn versions_the_queue() {
+        let mut player = test_player();
+        let version = |player: &mut Player| -> u32 {
+            let status = run(player, "status").unwrap();
+            let line = status.lines().find(|line| line.starts_with("playlist: "));
+            line.unwrap()["playlist: ".len()..].parse().unwrap()
+        };
+        let empty = version(&mut player);
+        run(&mut player, "add \"\"").unwrap();
+        let full = version(&mut player);
+        assert!(full > empty);
+        run(&mut player, "move 0 5").unwrap();
+        run(&mut player, "move 5 0").unwrap();
+        // the same songs in the same order again, but not the same version
+        assert!(version(&mut player) > full);
+
+        let current = version(&mut player);
+        assert_eq!(
+            run(&mut player, &format!("plchanges {}", current)).unwrap(),
+            ""
+        );
+        let changes = run(&mut player, &format!("plchangesposid {}", empty)).unwrap();
+        assert_eq!(changes.matches("cpos: ").count(), 6);
+    }
+
+    #[test]
+    fn deletes_ranges_at_once() {
+        let mut player = test_player();
+        run(&mut player, "add \"\"").unwrap();
+        player.playback_index = 4;
+        let before = player.queue_version();
+
+        run(&mut player, "delete 1:3").unwrap();
+        assert_eq!(player.queue_version(), before.wrapping_add(1));
+        assert_eq!(
+            queue_titles(&player)[player.playback_index],
+            "Laughing Stock 2"
+        );
+
+        // removing the current song moves on to the one after the range
+        run(&mut player, "delete 2:").unwrap();
+        assert_eq!(player.queue.len(), 2);
+        assert_eq!(player.playback_index, 1);
+        run(&mut player, "delete 1:1").unwrap();
+        assert_eq!(
+            run(&mut player, "delete 1:3").unwrap_err().code,
+            ACK_ERROR_ARG
+        );
+        assert_eq!(
+            queue_titles(&player),
+            ["Things We Lost 1", "Laughing Stock 1"]
+        );
+    }
+
+    async fn read_response(lines: &mut (impl AsyncBufRead + Unpin)) -> String {
+        let mut response = String::new();
+        loop {
+            let mut line = String::new();
+            let read = time::timeout(Duration::from_secs(5), lines.read_line(&mut line))
+                .await
+                .expect("no response")
+                .unwrap();
+            assert!(read > 0, "connection closed after {:?}", response);
+            response.push_str(&line);
+            if line == "OK\n" || line.starts_with("ACK ") {
+                return response;
+            }
+        }
+    }
+
+    async fn connect(
+        address: std::net::SocketAddr,
+    ) -> (BufReader<OwnedReadHalf>, tokio::net::tcp::OwnedWriteHalf) {
+        let (reader, writer) = TcpStream::connect(address).await.unwrap().into_split();
+        let mut reader = BufReader::new(reader);
+        let mut greeting = String::new();
+        reader.read_line(&mut greeting).await.unwrap();
+        assert_eq!(greeting, format!("OK MPD {}\n", PROTOCOL_VERSION));
+        (reader, writer)
+    }
+
+    #[tokio::test]
+    async fn serves_clients_over_tcp() {
+        let player = Arc::new(Mutex::new(test_player()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(accept_clients(Arc::clone(&player), listener));
+
+        let (mut reader, mut writer) = connect(address).await;
+        let (mut idle_reader, mut idle_writer) = connect(address).await;
+
+        writer.write_all(b"status\n").await.unwrap();
+        let status = read_response(&mut reader).await;
+        assert!(status.contains("playlistlength: 0\n"), "{}", status);
+
+        // the idling client hears about what the other one does
+        idle_writer.write_all(b"idle playlist\n").await.unwrap();
+        writer.write_all(b"add \"Talk Talk\"\n").await.unwrap();
+        assert_eq!(read_response(&mut reader).await, "OK\n");
+        assert_eq!(
+            read_response(&mut idle_reader).await,
+            "changed: playlist\nOK\n"
+        );
+
+        writer
+            .write_all(b"command_list_ok_begin\nmove 0 2\nplaylist\ncommand_list_end\n")
+            .await
+            .unwrap();
+        let response = read_response(&mut reader).await;
+        assert_eq!(
+            response,
+            format!(
+                "list_OK\n0:file: {0}/02.flac\n1:file: {0}/03.flac\n2:file: {0}/01.flac\nlist_OK\nOK\n",
+                "Talk Talk/Laughing Stock"
+            )
+        );
+
+        idle_writer.write_all(b"idle mixer\n").await.unwrap();
+        idle_writer.write_all(b"noidle\n").await.unwrap();
+        assert_eq!(read_response(&mut idle_reader).await, "OK\n");
+
+        writer.write_all(b"move 9 0\n").await.unwrap();
+        assert!(read_response(&mut reader)
+            .await
+            .starts_with("ACK [2@0] {move}"));
+
+        // overly long lines end the connection
+        let long_line = format!("find title {}\n", "x".repeat(MAX_LINE_LENGTH));
+        writer.write_all(long_line.as_bytes()).await.unwrap();
+        let mut rest = String::new();
+        let read = time::timeout(Duration::from_secs(5), reader.read_line(&mut rest))
+            .await
+            .expect("connection stayed open");
+        assert!(matches!(read, Ok(0) | Err(_)), "{:?}", rest);
+    }
+}
@@ -0,0 +1,302 @@
+use std::path::Path;
+
+use super::song_uri;
+use crate::library::Song;
+
+/// How deeply expressions may nest, clients never need more than a few levels
+const MAX_DEPTH: usize = 16;
+
+/// Tags clients can filter and list by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tag {
+    Any,
+    File,
+    Artist,
+    AlbumArtist,
+    Album,
+    Title,
+    Track,
+    Disc,
+    Genre,
+    Date,
+}
+
+/// The tags every song is described with, in the order they are sent
+pub const TAG_TYPES: [Tag; 8] = [
+    Tag::Artist,
+    Tag::AlbumArtist,
+    Tag::Album,
+    Tag::Title,
+    Tag::Track,
+    Tag::Disc,
+    Tag::Genre,
+    Tag::Date,
+];
+
+impl Tag {
+    pub fn parse(name: &str) -> Option<Tag> {
+        match name.to_lowercase().as_str() {
+            "any" => Some(Tag::Any),
+            "file" => Some(Tag::File),
+            "artist" => Some(Tag::Artist),
+            "albumartist" => Some(Tag::AlbumArtist),
+            "album" => Some(Tag::Album),
+            "title" => Some(Tag::Title),
+            "track" => Some(Tag::Track),
+            "disc" => Some(Tag::Disc),
+            "genre" => Some(Tag::Genre),
+            "date" => Some(Tag::Date),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tag::Any => "Any",
+            Tag::File => "file",
+            Tag::Artist => "Artist",
+            Tag::AlbumArtist => "AlbumArtist",
+            Tag::Album => "Album",
+            Tag::Title => "Title",
+            Tag::Track => "Track",
+            Tag::Disc => "Disc",
+            Tag::Genre => "Genre",
+            Tag::Date => "Date",
+        }
+    }
+
+    /// The song's values for the tag, empty if it does not have it. Songs can have several
+    /// artists, and `Any` is every value of every tag.
+    pub fn values(&self, song: &Song, music_dir: &Path) -> Vec<String> {
+        let text = |text: &str| match text.trim() {
+            "" => Vec::new(),
+            text => vec![text.replace('\n', " ")],
+        };
+        let number = |number: Option<u32>| number.map(|number| number.to_string());
+
+        match self {
+            Tag::Any => [Tag::File]
+                .iter()
+                .chain(TAG_TYPES.iter())
+                .flat_map(|tag| tag.values(song, music_dir))
+                .collect(),
+            Tag::File => vec![song_uri(song, music_dir)],
+            Tag::Artist => song
+                .artists
+                .iter()
+                .flat_map(|artist| text(&artist.name))
+                .collect(),
+            Tag::AlbumArtist => song
+                .album
+                .as_ref()
+                .map_or(Vec::new(), |album| text(&album.artist.name)),
+            Tag::Album => song
+                .album
+                .as_ref()
+                .map_or(Vec::new(), |album| text(&album.title)),
+            Tag::Title => text(&song.title),
+            Tag::Track => number(song.track_number).into_iter().collect(),
+            Tag::Disc => number(song.disc_number).into_iter().collect(),
+            Tag::Genre => text(&song.genre),
+            Tag::Date => (song.year > 0)
+                .then(|| song.year.to_string())
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Equals,
+    NotEquals,
+    Contains,
+    StartsWith,
+}
+
+/// Which songs `find`, `search`, `list` and friends are about
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Compare(Tag, Operator, String),
+    Not(Box<Filter>),
+    And(Vec<Filter>),
+}
+
+impl Filter {
+    /// Either one expression such as `((artist == 'Low') AND (date == '1977'))` or the older
+    /// `artist Low date 1977` pairs, which are compared with `pair_operator`
+    pub fn parse(args: &[String], pair_operator: Operator) -> Result<Filter, String> {
+        if let [expression] = args {
+            if expression.trim_start().starts_with('(') {
+                let mut parser = Parser {
+                    chars: expression.chars().collect(),
+                    position: 0,
+                    depth: 0,
+                };
+                let filter = parser.expression()?;
+                parser.skip_whitespace();
+                if parser.position < parser.chars.len() {
+                    return Err(format!("unexpected text after filter {}", expression));
+                }
+                return Ok(filter);
+            }
+        }
+
+        if args.len() % 2 != 0 {
+            return Err("filters need a tag and a value".to_string());
+        }
+        let comparisons = args
+            .chunks(2)
+            .map(|pair| {
+                let tag = Tag::parse(&pair[0]).ok_or_else(|| format!("unknown tag {}", pair[0]))?;
+                Ok(Filter::Compare(tag, pair_operator, pair[1].clone()))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Filter::And(comparisons))
+    }
+
+    /// `ignore_case` is what `search` does and `find` does not
+    pub fn matches(&self, song: &Song, music_dir: &Path, ignore_case: bool) -> bool {
+        match self {
+            Filter::Compare(tag, operator, value) => {
+                let fold = |text: &str| match ignore_case {
+                    true => text.to_lowercase(),
+                    false => text.to_string(),
+                };
+                let value = fold(value);
+                let mut values: Vec<String> = tag
+                    .values(song, music_dir)
+                    .iter()
+                    .map(|text| fold(text))
+                    .collect();
+                // comparing with "" finds the songs that do not have the tag
+                if values.is_empty() {
+                    values.push(String::new());
+                }
+
+                match operator {
+                    Operator::Equals => values.contains(&value),
+                    Operator::NotEquals => values.iter().all(|text| *text != value),
+                    Operator::Contains => values.iter().any(|text| text.contains(&value)),
+                    Operator::StartsWith => values.iter().any(|text| text.starts_with(&value)),
+                }
+            }
+            Filter::Not(filter) => !filter.matches(song, music_dir, ignore_case),
+            Filter::And(filters) => filters
+                .iter()
+                .all(|filter| filter.matches(song, music_dir, ignore_case)),
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    depth: usize, // expressions currently being parsed
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(format!("expected {} in filter", expected)),
+        }
+    }
+
+    /// A word such as a tag name, an operator or `AND`
+    fn word(&mut self) -> String {
+        self.skip_whitespace();
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && c != '(' && c != ')' && c != '"' && c != '\'')
+        {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect()
+    }
+
+    /// `'...'` or `"..."`, with backslash escapes
+    fn quoted(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let quote = match self.peek() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => return Err("expected a quoted value in filter".to_string()),
+        };
+        self.position += 1;
+
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None => return Err("unterminated value in filter".to_string()),
+                Some(c) if c == quote => {
+                    self.position += 1;
+                    return Ok(value);
+                }
+                Some('\\') => {
+                    self.position += 1;
+                    value.extend(self.peek());
+                    self.position += 1;
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn expression(&mut self) -> Result<Filter, String> {
+        if self.depth == MAX_DEPTH {
+            return Err("filter nested too deeply".to_string());
+        }
+        self.depth += 1;
+        self.expect('(')?;
+        self.skip_whitespace();
+
+        let filter = match self.peek() {
+            Some('!') => {
+                self.position += 1;
+                Filter::Not(Box::new(self.expression()?))
+            }
+            Some('(') => {
+                let mut filters = vec![self.expression()?];
+                while self.word() == "AND" {
+                    filters.push(self.expression()?);
+                }
+                // `word` stopped at the closing parenthesis
+                Filter::And(filters)
+            }
+            _ => {
+                let name = self.word();
+                let tag = Tag::parse(&name).ok_or_else(|| format!("unknown tag {}", name))?;
+                let operator = match self.word().as_str() {
+                    "==" => Operator::Equals,
+                    "!=" => Operator::NotEquals,
+                    "contains" => Operator::Contains,
+                    "starts_with" => Operator::StartsWith,
+                    operator => return Err(format!("unsupported filter operator {}", operator)),
+                };
+                Filter::Compare(tag, operator, self.quoted()?)
+            }
+        };
+
+        self.expect(')')?;
+        self.depth -= 1;
+        Ok(filter)
+    }
+}
@@ -0,0 +1,740 @@
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, Method},
+    response::Response,
+    routing::get,
+    Form, Router,
+};
+use md5::{Digest, Md5};
+use parking_lot::Mutex;
+use serde_json::{json, Map, Value};
+use std::{cmp::Reverse, collections::HashMap, fs, path, sync::Arc};
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+use crate::library::{AlbumSummary, Artist, Library, SearchQuery, Song};
+use crate::player::Player;
+use crate::GlobalSettings;
+use response::{
+    Format, SubsonicError, ERROR_GENERIC, ERROR_MISSING_PARAMETER, ERROR_WRONG_CREDENTIALS,
+};
+
+mod media;
+mod response;
+
+/// How many results of each kind `search3` sends unless asked for more
+const DEFAULT_SEARCH_SIZE: usize = 20;
+/// How many albums `getAlbumList2` sends unless asked for more
+const DEFAULT_ALBUM_LIST_SIZE: usize = 10;
+/// The most results of a kind sent at once, clients page through the rest
+const MAX_LIST_SIZE: usize = 500;
+
+type SharedPlayer = Arc<Mutex<Player>>;
+
+/// Serves the core of the Subsonic API on `address` so that Subsonic and OpenSubsonic apps
+/// (DSub, Symfonium, Substreamer, ...) can browse the library and stream from it. Clients log
+/// in as `subsonic_username` with `subsonic_password`.
+pub async fn serve(player: SharedPlayer, address: String) -> Result<()> {
+    let listener = TcpListener::bind(&address)
+        .await
+        .map_err(|e| anyhow!("cannot listen on {}: {}", address, e))?;
+    println!("Subsonic API listening on {}", address);
+    let router = Router::new()
+        .route("/rest/:endpoint", get(handle).post(handle))
+        .with_state(player);
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+/// Request parameters, from the query and a form body. Some can be repeated, e.g.
+/// `songId=1&songId=2`.
+struct Params(Vec<(String, String)>);
+
+impl Params {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.0
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn require(&self, name: &str) -> Result<&str, SubsonicError> {
+        self.get(name).ok_or_else(|| {
+            SubsonicError::new(
+                ERROR_MISSING_PARAMETER,
+                format!("Required parameter is missing: {}", name),
+            )
+        })
+    }
+
+    fn id(&self, name: &str) -> Result<Uuid, SubsonicError> {
+        let id = self.require(name)?;
+        Uuid::parse_str(id).map_err(|_| SubsonicError::not_found(id))
+    }
+
+    fn ids(&self, name: &str) -> Result<Vec<Uuid>, SubsonicError> {
+        self.all(name)
+            .map(|id| Uuid::parse_str(id).map_err(|_| SubsonicError::not_found(id)))
+            .collect()
+    }
+
+    fn number(&self, name: &str, default: usize) -> usize {
+        self.get(name)
+            .and_then(|number| number.parse().ok())
+            .unwrap_or(default)
+    }
+}
+
+async fn handle(
+    State(player): State<SharedPlayer>,
+    Path(endpoint): Path<String>,
+    method: Method,
+    headers: HeaderMap,
+    Query(mut params): Query<Vec<(String, String)>>,
+    form: Option<Form<Vec<(String, String)>>>,
+) -> Response {
+    // posts may carry parameters in the query as well as in the body
+    if let (Method::POST, Some(Form(form))) = (method, form) {
+        params.extend(form);
+    }
+    let params = Params(params);
+    let format = Format::from_param(params.get("f"));
+    let endpoint = endpoint.strip_suffix(".view").unwrap_or(&endpoint);
+    let settings = player.lock().settings.clone();
+
+    let result = match authenticate(&params, &settings) {
+        Err(e) => Err(e),
+        Ok(()) if matches!(endpoint, "stream" | "download" | "getCoverArt") => {
+            match media(&player, endpoint, &params, &headers, &settings).await {
+                Ok(response) => return response,
+                Err(e) => Err(e),
+            }
+        }
+        Ok(()) => answer(&player, endpoint, &params, &settings),
+    };
+    match result {
+        Ok(payload) => response::ok(format, payload),
+        Err(e) => response::failed(format, e),
+    }
+}
+
+/// Either `t`, the md5 of the password followed by the salt `s`, or the password itself in
+/// `p`, as it is or hex encoded after `enc:`
+fn authenticate(params: &Params, settings: &GlobalSettings) -> Result<(), SubsonicError> {
+    let user = params.require("u")?;
+    let password = &settings.subsonic_password;
+    let valid = match (params.get("t"), params.get("s"), params.get("p")) {
+        (Some(token), Some(salt), _) => {
+            let expected = format!("{:x}", Md5::digest(format!("{}{}", password, salt)));
+            constant_time_eq(&token.to_ascii_lowercase(), &expected)
+        }
+        (_, _, Some(given)) => {
+            decode_password(given).is_some_and(|given| constant_time_eq(&given, password))
+        }
+        (Some(_), None, None) => return Err(params.require("s").unwrap_err()),
+        _ => return Err(params.require("t").unwrap_err()),
+    };
+    if user != settings.subsonic_username || !valid {
+        return Err(SubsonicError::new(
+            ERROR_WRONG_CREDENTIALS,
+            "Wrong username or password",
+        ));
+    }
+    Ok(())
+}
+
+/// Compares every byte whatever the first difference, so the time taken does not tell how
+/// much of a guessed token or password was right
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn decode_password(given: &str) -> Option<String> {
+    let Some(hex) = given.strip_prefix("enc:") else {
+        return Some(given.to_string());
+    };
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// `stream`, `download` and `getCoverArt`, which answer with the media itself
+async fn media(
+    player: &SharedPlayer,
+    endpoint: &str,
+    params: &Params,
+    headers: &HeaderMap,
+    settings: &GlobalSettings,
+) -> Result<Response, SubsonicError> {
+    let id = params.id("id")?;
+    let song = {
+        let library = player.lock().library();
+        let library = library.lock();
+        match library.songs.get(&id) {
+            Some(song) => Some(song.clone()),
+            // covers are also asked for by album
+            None if endpoint == "getCoverArt" => library.album_songs(id).into_iter().next(),
+            None => None,
+        }
+    }
+    .ok_or_else(|| SubsonicError::not_found("song"))?;
+
+    match endpoint {
+        "getCoverArt" => media::cover_response(&song),
+        _ => media::stream(&song, params, headers, settings, endpoint == "download").await,
+    }
+}
+
+fn answer(
+    player: &SharedPlayer,
+    endpoint: &str,
+    params: &Params,
+    settings: &GlobalSettings,
+) -> Result<Map<String, Value>, SubsonicError> {
+    if endpoint == "scrobble" {
+        return scrobble(player, params);
+    }
+
+    let library = player.lock().library();
+    let mut library = library.lock();
+    let music_dir = path::Path::new(&settings.folder_to_scan);
+    let (name, payload) = match endpoint {
+        "ping" => return Ok(Map::new()),
+        "getLicense" => ("license", json!({ "valid": true })),
+        "getMusicFolders" => (
+            "musicFolders",
+            json!({ "musicFolder": [{ "id": 1, "name": "Music" }] }),
+        ),
+        "getArtists" => ("artists", artist_index(&library)),
+        "getArtist" => ("artist", artist_with_albums(&library, params.id("id")?)?),
+        "getAlbum" => (
+            "album",
+            album_with_songs(&library, params.id("id")?, music_dir)?,
+        ),
+        "getSong" => {
+            let song = library
+                .songs
+                .get(&params.id("id")?)
+                .ok_or_else(|| SubsonicError::not_found("song"))?;
+            ("song", song_entry(song, music_dir))
+        }
+        "getAlbumList2" => ("albumList2", album_list(&library, params)?),
+        "search3" => ("searchResult3", search(&library, params, music_dir)),
+        "getPlaylists" => ("playlists", playlists(&library, settings)),
+        "getPlaylist" => (
+            "playlist",
+            playlist_with_songs(&library, params.id("id")?, settings)?,
+        ),
+        "createPlaylist" => {
+            let id = create_playlist(&mut library, params)?;
+            library.save_to_file(&settings.library_file)?;
+            ("playlist", playlist_with_songs(&library, id, settings)?)
+        }
+        "updatePlaylist" => {
+            update_playlist(&mut library, params)?;
+            library.save_to_file(&settings.library_file)?;
+            return Ok(Map::new());
+        }
+        "deletePlaylist" => {
+            library.delete_playlist(params.id("id")?)?;
+            library.save_to_file(&settings.library_file)?;
+            return Ok(Map::new());
+        }
+        _ => {
+            return Err(SubsonicError::new(
+                ERROR_GENERIC,
+                format!("{} is not supported", endpoint),
+            ))
+        }
+    };
+    Ok(Map::from_iter([(name.to_string(), payload)]))
+}
+
+/// Plays on a phone count like plays here. `submission=false` only says what is playing
+/// there, which is not tracked.
+fn scrobble(player: &SharedPlayer, params: &Params) -> Result<Map<String, Value>, SubsonicError> {
+    let ids = params.ids("id")?;
+    if ids.is_empty() {
+        params.require("id")?;
+    }
+    if params.get("submission") != Some("false") {
+        let mut player = player.lock();
+        for id in ids {
+            player.count_play(id)?;
+        }
+    }
+    Ok(Map::new())
+}
+
+/// The file extension, which is what Subsonic calls the suffix
+fn suffix(song: &Song) -> String {
+    song.file_path
+        .extension()
+        .map_or(String::new(), |extension| {
+            extension.to_string_lossy().to_lowercase()
+        })
+}
+
+/// Songs show their album's cover, songs without an album their own
+fn cover_id(song: &Song) -> Uuid {
+    song.album.as_ref().map_or(song.id, |album| album.id)
+}
+
+fn song_entry(song: &Song, music_dir: &path::Path) -> Value {
+    let suffix = suffix(song);
+    let path = song
+        .file_path
+        .strip_prefix(music_dir)
+        .unwrap_or(&song.file_path);
+    // cue sheet tracks share their file, so its size says nothing about them
+    let size = match song.cue_range {
+        None => fs::metadata(&song.file_path).ok().map(|file| file.len()),
+        Some(_) => None,
+    };
+    let artist = song
+        .artists
+        .iter()
+        .map(|artist| artist.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    json!({
+        "id": song.id,
+        "parent": song.album.as_ref().map(|album| album.id),
+        "isDir": false,
+        "title": song.title,
+        "album": song.album.as_ref().map(|album| &album.title),
+        "artist": artist,
+        "track": song.track_number,
+        "discNumber": song.disc_number,
+        "year": (song.year > 0).then_some(song.year),
+        "genre": (!song.genre.is_empty()).then_some(&song.genre),
+        "coverArt": cover_id(song),
+        "size": size,
+        "contentType": media::content_type(&suffix),
+        "suffix": suffix,
+        "duration": song.duration.as_secs(),
+        "path": path.to_string_lossy(),
+        "playCount": song.play_count,
+        "userRating": song.rating,
+        "albumId": song.album.as_ref().map(|album| album.id),
+        "artistId": song.artists.first().map(|artist| artist.id),
+        "type": "music",
+        "mediaType": "song",
+    })
+}
+
+fn album_entry(summary: &AlbumSummary) -> Value {
+    json!({
+        "id": summary.album.id,
+        "name": summary.album.title,
+        "artist": summary.album.artist.name,
+        "artistId": summary.album.artist.id,
+        "coverArt": summary.album.id,
+        "songCount": summary.song_count,
+        "duration": summary.duration.as_secs(),
+        "year": (summary.year > 0).then_some(summary.year),
+    })
+}
+
+fn artist_entry((artist, album_count): &(Artist, usize)) -> Value {
+    json!({ "id": artist.id, "name": artist.name, "albumCount": album_count })
+}
+
+/// Artists grouped by the first letter of their name
+fn artist_index(library: &Library) -> Value {
+    let mut index: Vec<(String, Vec<Value>)> = Vec::new();
+    for artist in library.artists_with_album_counts() {
+        let letter = match artist.0.name.chars().next() {
+            Some(c) if c.is_alphabetic() => c.to_uppercase().to_string(),
+            _ => "#".to_string(),
+        };
+        match index.last_mut() {
+            Some((last, artists)) if *last == letter => artists.push(artist_entry(&artist)),
+            _ => index.push((letter, vec![artist_entry(&artist)])),
+        }
+    }
+
+    let index: Vec<Value> = index
+        .into_iter()
+        .map(|(letter, artists)| json!({ "name": letter, "artist": artists }))
+        .collect();
+    json!({ "ignoredArticles": "", "index": index })
+}
+
+fn artist_with_albums(library: &Library, id: Uuid) -> Result<Value, SubsonicError> {
+    let artist = library
+        .artists_with_album_counts()
+        .into_iter()
+        .find(|(artist, _)| artist.id == id)
+        .ok_or_else(|| SubsonicError::not_found("artist"))?;
+    let albums: Vec<Value> = library.artist_albums(id).iter().map(album_entry).collect();
+
+    let mut entry = artist_entry(&artist);
+    entry["album"] = json!(albums);
+    Ok(entry)
+}
+
+fn album_with_songs(
+    library: &Library,
+    id: Uuid,
+    music_dir: &path::Path,
+) -> Result<Value, SubsonicError> {
+    let summary = library
+        .all_albums()
+        .into_iter()
+        .find(|summary| summary.album.id == id)
+        .ok_or_else(|| SubsonicError::not_found("album"))?;
+    let songs: Vec<Value> = library
+        .album_songs(id)
+        .iter()
+        .map(|song| song_entry(song, music_dir))
+        .collect();
+
+    let mut entry = album_entry(&summary);
+    entry["song"] = json!(songs);
+    Ok(entry)
+}
+
+/// The album lists of a client's home screen. Nothing is starred and recent plays are not
+/// tracked, so those lists are empty.
+fn album_list(library: &Library, params: &Params) -> Result<Value, SubsonicError> {
+    // when each album was last added to, how often its songs were played and their ratings
+    let mut stats: HashMap<Uuid, (u64, u32, u32)> = HashMap::new();
+    for song in library.songs.values() {
+        if let Some(album) = &song.album {
+            let (added, plays, rating) = stats.entry(album.id).or_default();
+            *added = (*added).max(song.date_added);
+            *plays += song.play_count;
+            *rating += song.rating.unwrap_or(0) as u32;
+        }
+    }
+    let stat = |summary: &AlbumSummary| stats.get(&summary.album.id).copied().unwrap_or_default();
+
+    let mut albums = library.all_albums();
+    match params.require("type")? {
+        "random" => albums.sort_by_cached_key(|_| Uuid::new_v4()),
+        "newest" => albums.sort_by_key(|summary| Reverse(stat(summary).0)),
+        "frequent" => albums.sort_by_key(|summary| Reverse(stat(summary).1)),
+        "highest" => albums.sort_by_key(|summary| Reverse(stat(summary).2)),
+        "alphabeticalByName" => {
+            albums.sort_by_cached_key(|summary| summary.album.title.to_lowercase())
+        }
+        "alphabeticalByArtist" => albums.sort_by_cached_key(|summary| {
+            (
+                summary.album.artist.name.to_lowercase(),
+                summary.album.title.to_lowercase(),
+            )
+        }),
+        "byYear" => {
+            let from = params.number("fromYear", 0);
+            let to = params.number("toYear", u16::MAX as usize);
+            albums
+                .retain(|summary| (from.min(to)..=from.max(to)).contains(&(summary.year as usize)));
+            if from > to {
+                albums.reverse();
+            }
+        }
+        "byGenre" => albums = library.genre_albums(params.require("genre")?),
+        "recent" | "starred" => albums.clear(),
+        list => {
+            return Err(SubsonicError::new(
+                ERROR_GENERIC,
+                format!("unknown album list type {}", list),
+            ))
+        }
+    }
+
+    let albums: Vec<Value> = albums
+        .iter()
+        .skip(params.number("offset", 0))
+        .take(
+            params
+                .number("size", DEFAULT_ALBUM_LIST_SIZE)
+                .min(MAX_LIST_SIZE),
+        )
+        .map(album_entry)
+        .collect();
+    Ok(json!({ "album": albums }))
+}
+
+/// Songs as the search box finds them, albums and artists by name. An empty query finds
+/// everything, which is how some clients copy the whole library.
+fn search(library: &Library, params: &Params, music_dir: &path::Path) -> Value {
+    let query = params
+        .get("query")
+        .unwrap_or_default()
+        .trim()
+        .trim_matches('"');
+    let needle = query.to_lowercase();
+    let page = |kind: &str| {
+        let offset = params.number(&format!("{}Offset", kind), 0);
+        let count = params.number(&format!("{}Count", kind), DEFAULT_SEARCH_SIZE);
+        (offset, count.min(MAX_LIST_SIZE))
+    };
+
+    let (offset, count) = page("artist");
+    let artists: Vec<Value> = library
+        .artists_with_album_counts()
+        .iter()
+        .filter(|(artist, _)| artist.name.to_lowercase().contains(&needle))
+        .skip(offset)
+        .take(count)
+        .map(artist_entry)
+        .collect();
+
+    let (offset, count) = page("album");
+    let albums: Vec<Value> = library
+        .all_albums()
+        .iter()
+        .filter(|summary| {
+            summary.album.title.to_lowercase().contains(&needle)
+                || summary.album.artist.name.to_lowercase().contains(&needle)
+        })
+        .skip(offset)
+        .take(count)
+        .map(album_entry)
+        .collect();
+
+    let (offset, count) = page("song");
+    let songs: Vec<Value> = library
+        .search(&SearchQuery::parse(query))
+        .iter()
+        .filter_map(|id| library.songs.get(id))
+        .skip(offset)
+        .take(count)
+        .map(|song| song_entry(song, music_dir))
+        .collect();
+
+    json!({ "artist": artists, "album": albums, "song": songs })
+}
+
+fn playlist_entry(id: Uuid, name: &str, songs: &[Song], readonly: bool, owner: &str) -> Value {
+    let duration: u64 = songs.iter().map(|song| song.duration.as_secs()).sum();
+    json!({
+        "id": id,
+        "name": name,
+        "songCount": songs.len(),
+        "duration": duration,
+        "owner": owner,
+        "public": false,
+        "readonly": readonly,
+        "coverArt": songs.first().map(cover_id),
+    })
+}
+
+/// Playlists and smart playlists, which cannot be edited
+fn playlists(library: &Library, settings: &GlobalSettings) -> Value {
+    let regular = library
+        .playlists
+        .iter()
+        .map(|playlist| (playlist.id, &playlist.name, false));
+    let smart = library
+        .smart_playlists
+        .iter()
+        .map(|playlist| (playlist.id, &playlist.name, true));
+
+    let playlists: Vec<Value> = regular
+        .chain(smart)
+        .map(|(id, name, readonly)| {
+            let songs = library.playlist_songs(id).unwrap_or_default();
+            playlist_entry(id, name, &songs, readonly, &settings.subsonic_username)
+        })
+        .collect();
+    json!({ "playlist": playlists })
+}
+
+fn playlist_with_songs(
+    library: &Library,
+    id: Uuid,
+    settings: &GlobalSettings,
+) -> Result<Value, SubsonicError> {
+    let songs = library
+        .playlist_songs(id)
+        .map_err(|_| SubsonicError::not_found("playlist"))?;
+    let (name, readonly) = match library.smart_playlist(id) {
+        Some(smart_playlist) => (smart_playlist.name.clone(), true),
+        None => (library.playlist_name(id)?, false),
+    };
+
+    let music_dir = path::Path::new(&settings.folder_to_scan);
+    let mut entry = playlist_entry(id, &name, &songs, readonly, &settings.subsonic_username);
+    entry["entry"] = songs
+        .iter()
+        .map(|song| song_entry(song, music_dir))
+        .collect();
+    Ok(entry)
+}
+
+/// Creates a playlist named `name`, or replaces the songs of `playlistId`
+fn create_playlist(library: &mut Library, params: &Params) -> Result<Uuid, SubsonicError> {
+    let song_ids = params.ids("songId")?;
+    if params.get("playlistId").is_none() {
+        let name = params.require("name")?.to_string();
+        return Ok(library.create_playlist(name, song_ids));
+    }
+
+    let id = params.id("playlistId")?;
+    let playlist = library
+        .playlists
+        .iter_mut()
+        .find(|playlist| playlist.id == id)
+        .ok_or_else(|| SubsonicError::not_found("playlist"))?;
+    playlist.song_ids = song_ids;
+    if let Some(name) = params.get("name") {
+        playlist.name = name.to_string();
+    }
+    Ok(id)
+}
+
+fn update_playlist(library: &mut Library, params: &Params) -> Result<(), SubsonicError> {
+    let id = params.id("playlistId")?;
+    if library.smart_playlist(id).is_some() {
+        return Err(SubsonicError::new(
+            ERROR_GENERIC,
+            "smart playlists follow their query and cannot be edited",
+        ));
+    }
+
+    if let Some(name) = params.get("name") {
+        library.rename_playlist(id, name.to_string())?;
+    }
+    let mut removed: Vec<usize> = params
+        .all("songIndexToRemove")
+        .filter_map(|index| index.parse().ok())
+        .collect();
+    removed.sort_unstable();
+    removed.dedup();
+    for index in removed.into_iter().rev() {
+        library.remove_from_playlist(id, index)?;
+    }
+    for song_id in params.ids("songIdToAdd")? {
+        library.add_to_playlist(id, song_id)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> GlobalSettings {
+        GlobalSettings {
+            subsonic_username: String::from("jukebox"),
+            subsonic_password: String::from("s3cret"),
+            ..GlobalSettings::default()
+        }
+    }
+
+    fn params(params: &[(&str, &str)]) -> Params {
+        Params(
+            params
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    fn error_code(params: &[(&str, &str)]) -> Option<u32> {
+        authenticate(&self::params(params), &settings())
+            .err()
+            .map(|e| e.code)
+    }
+
+    #[test]
+    fn accepts_salted_tokens() {
+        // md5("s3cret" + "c19b2d")
+        let token = "a34b73cdd2cd20e8d06d1bff5f11cd3b";
+        assert_eq!(
+            error_code(&[("u", "jukebox"), ("t", token), ("s", "c19b2d")]),
+            None
+        );
+        let upper = token.to_ascii_uppercase();
+        assert_eq!(
+            error_code(&[("u", "jukebox"), ("t", &upper), ("s", "c19b2d")]),
+            None
+        );
+
+        let wrong = [("u", "jukebox"), ("t", token), ("s", "other salt")];
+        assert_eq!(error_code(&wrong), Some(ERROR_WRONG_CREDENTIALS));
+        let short = [("u", "jukebox"), ("t", &token[1..]), ("s", "c19b2d")];
+        assert_eq!(error_code(&short), Some(ERROR_WRONG_CREDENTIALS));
+    }
+
+    #[test]
+    fn accepts_plain_and_hex_passwords() {
+        assert_eq!(error_code(&[("u", "jukebox"), ("p", "s3cret")]), None);
+        assert_eq!(
+            error_code(&[("u", "jukebox"), ("p", "enc:733363726574")]),
+            None
+        );
+
+        for wrong in [
+            "s3cre",
+            "S3CRET",
+            "enc:73336372657",
+            "enc:7333637265zz",
+            "enc:",
+            "",
+        ] {
+            assert_eq!(
+                error_code(&[("u", "jukebox"), ("p", wrong)]),
+                Some(ERROR_WRONG_CREDENTIALS),
+                "{}",
+                wrong
+            );
+        }
+        assert_eq!(
+            decode_password("enc:6a756b65626f78").as_deref(),
+            Some("jukebox")
+        );
+        assert_eq!(decode_password("enc:ff").as_deref(), None);
+    }
+
+    #[test]
+    fn rejects_unknown_users_and_missing_parameters() {
+        assert_eq!(
+            error_code(&[("u", "someone"), ("p", "s3cret")]),
+            Some(ERROR_WRONG_CREDENTIALS)
+        );
+        assert_eq!(
+            error_code(&[("p", "s3cret")]),
+            Some(ERROR_MISSING_PARAMETER)
+        );
+        assert_eq!(
+            error_code(&[("u", "jukebox")]),
+            Some(ERROR_MISSING_PARAMETER)
+        );
+        // a token is no use without its salt
+        let token = "a34b73cdd2cd20e8d06d1bff5f11cd3b";
+        assert_eq!(
+            error_code(&[("u", "jukebox"), ("t", token)]),
+            Some(ERROR_MISSING_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq("", ""));
+        assert!(constant_time_eq("abc", "abc"));
+        assert!(!constant_time_eq("abc", "abd"));
+        assert!(!constant_time_eq("abc", "abcd"));
+    }
+}
@@ -0,0 +1,362 @@
+use anyhow::anyhow;
+use axum::{
+    body::Body,
+    http::{
+        header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use std::{io::SeekFrom, path::Path, process::Stdio, time::Duration};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    process::Command,
+};
+use tokio_util::io::ReaderStream;
+
+use super::response::{SubsonicError, ERROR_GENERIC};
+use super::{suffix, Params};
+use crate::library::{cover_art, Song};
+use crate::GlobalSettings;
+
+/// Bit rate of transcoded streams when the client does not ask for one, in kbit/s
+const DEFAULT_BIT_RATE: u32 = 192;
+
+/// File types that are always worth transcoding when the client limits the bit rate
+const LOSSLESS_SUFFIXES: [&str; 2] = ["flac", "wav"];
+
+pub fn content_type(suffix: &str) -> &'static str {
+    match suffix {
+        "mp3" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "ogg" | "opus" => "audio/ogg",
+        "wav" => "audio/wav",
+        "aac" | "acc" | "m4a" => "audio/aac",
+        _ => "application/octet-stream",
+    }
+}
+
+/// `stream` sends the file as it is unless the client asks for another format or a lower bit
+/// rate, or the song is a cue sheet track that has to be cut out of its file, which fails
+/// without a transcoder. `download` always sends the file as it is.
+pub async fn stream(
+    song: &Song,
+    params: &Params,
+    headers: &HeaderMap,
+    settings: &GlobalSettings,
+    download: bool,
+) -> Result<Response, SubsonicError> {
+    let suffix = suffix(song);
+    let format = params
+        .get("format")
+        .filter(|format| !format.is_empty() && *format != "raw");
+    let max_bit_rate = params
+        .get("maxBitRate")
+        .and_then(|rate| rate.parse::<u32>().ok())
+        .filter(|rate| *rate > 0);
+    let time_offset = params
+        .get("timeOffset")
+        .and_then(|offset| offset.parse::<f64>().ok())
+        .and_then(|offset| Duration::try_from_secs_f64(offset).ok())
+        .unwrap_or_default();
+
+    let transcode = !download
+        && params.get("format") != Some("raw")
+        && (song.cue_range.is_some()
+            || format.is_some_and(|format| format != suffix)
+            || (max_bit_rate.is_some() && LOSSLESS_SUFFIXES.contains(&suffix.as_str()))
+            || !time_offset.is_zero());
+    if transcode && !settings.subsonic_transcoder.is_empty() {
+        let format = format.unwrap_or("mp3");
+        let bit_rate = max_bit_rate.unwrap_or(DEFAULT_BIT_RATE);
+        match transcoded(song, format, bit_rate, time_offset, settings) {
+            Ok(response) => return Ok(response),
+            Err(e) if song.cue_range.is_some() => return Err(e.into()),
+            // the client can still play the original
+            Err(e) => println!("Transcoding failed, sending the file as it is: {}", e),
+        }
+    }
+    // the file holds the whole album, which is not what the client asked for
+    if song.cue_range.is_some() && !download {
+        return Err(SubsonicError::new(
+            ERROR_GENERIC,
+            "cue sheet tracks can only be streamed transcoded, set a subsonic_transcoder",
+        ));
+    }
+
+    let range = headers.get(RANGE).and_then(|range| range.to_str().ok());
+    file_response(&song.file_path, content_type(&suffix), range).await
+}
+
+/// Sends the file, or the byte range a client asked for to seek
+async fn file_response(
+    path: &Path,
+    content_type: &str,
+    range: Option<&str>,
+) -> Result<Response, SubsonicError> {
+    let not_readable = |e: std::io::Error| anyhow!("cannot read {}: {}", path.display(), e);
+    let mut file = File::open(path).await.map_err(not_readable)?;
+    let length = file.metadata().await.map_err(not_readable)?.len();
+
+    let requested = range.and_then(|range| parse_range(range, length));
+    let (start, end) = requested.unwrap_or((0, length));
+    file.seek(SeekFrom::Start(start))
+        .await
+        .map_err(not_readable)?;
+    let body = Body::from_stream(ReaderStream::new(file.take(end - start)));
+
+    let status = match requested {
+        Some(_) => StatusCode::PARTIAL_CONTENT,
+        None => StatusCode::OK,
+    };
+    let mut response = (
+        status,
+        [
+            (CONTENT_TYPE, content_type.to_string()),
+            (CONTENT_LENGTH, (end - start).to_string()),
+            (ACCEPT_RANGES, "bytes".to_string()),
+        ],
+        body,
+    )
+        .into_response();
+    if requested.is_some() {
+        let content_range = format!("bytes {}-{}/{}", start, end - 1, length);
+        if let Ok(value) = content_range.parse() {
+            response.headers_mut().insert(CONTENT_RANGE, value);
+        }
+    }
+    Ok(response)
+}
+
+/// `bytes=START-END`, `bytes=START-` or `bytes=-SUFFIX_LENGTH` as a start and exclusive end
+fn parse_range(range: &str, length: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => (length.saturating_sub(suffix.parse().ok()?), length),
+        (start, "") => (start.parse().ok()?, length),
+        (start, end) => (
+            start.parse().ok()?,
+            end.parse::<u64>().ok()?.saturating_add(1),
+        ),
+    };
+    let end = end.min(length);
+    (start < end).then_some((start, end))
+}
+
+/// Pipes the song through the configured transcoder (ffmpeg or something that takes the same
+/// arguments) while it is being sent
+fn transcoded(
+    song: &Song,
+    format: &str,
+    bit_rate: u32,
+    time_offset: Duration,
+    settings: &GlobalSettings,
+) -> anyhow::Result<Response> {
+    let (container, codec, content_type) = match format {
+        "mp3" => ("mp3", "libmp3lame", "audio/mpeg"),
+        "opus" => ("ogg", "libopus", "audio/ogg"),
+        "ogg" => ("ogg", "libvorbis", "audio/ogg"),
+        format => return Err(anyhow!("cannot transcode to {}", format)),
+    };
+
+    let start = song.start_offset() + time_offset;
+    let mut command = Command::new(&settings.subsonic_transcoder);
+    command.args(["-v", "error", "-ss", &format!("{:.3}", start.as_secs_f64())]);
+    if let Some(end) = song.cue_range.and_then(|range| range.end) {
+        let length = end.saturating_sub(start);
+        command.args(["-t", &format!("{:.3}", length.as_secs_f64())]);
+    }
+    command
+        .arg("-i")
+        .arg(&song.file_path)
+        .args(["-map", "0:a:0", "-c:a", codec])
+        .args(["-b:a", &format!("{}k", bit_rate), "-f", container, "pipe:1"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| anyhow!("cannot run {}: {}", settings.subsonic_transcoder, e))?;
+    let output = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("no output from {}", settings.subsonic_transcoder))?;
+    // the transcoder stops by itself once the client hangs up and the pipe closes
+    tokio::spawn(async move {
+        let _ = child.wait().await;
+    });
+
+    Ok((
+        [(CONTENT_TYPE, content_type)],
+        Body::from_stream(ReaderStream::new(output)),
+    )
+        .into_response())
+}
+
+pub fn cover_response(song: &Song) -> Result<Response, SubsonicError> {
+    let cover = cover_art(song).ok_or_else(|| SubsonicError::not_found("cover art"))?;
+    let content_type = match cover.as_slice() {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [b'G', b'I', b'F', ..] => "image/gif",
+        _ => "image/jpeg",
+    };
+    Ok(([(CONTENT_TYPE, content_type)], cover).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::CueRange;
+    use axum::body::to_bytes;
+    use std::{env, fs};
+    use uuid::Uuid;
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-", 1000), Some((0, 1000)));
+        assert_eq!(parse_range("bytes=100-199", 1000), Some((100, 200)));
+        assert_eq!(parse_range("bytes= 999 - ", 1000), Some((999, 1000)));
+        // the end is cut off at the end of the file
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some((900, 1000)));
+        // the last bytes, however many there are
+        assert_eq!(parse_range("bytes=-500", 1000), Some((500, 1000)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some((0, 1000)));
+
+        for range in [
+            "bytes=1000-",
+            "bytes=2000-3000",
+            "bytes=-0",
+            "bytes=200-100",
+            "bytes=0-1,5-6",
+            "bytes=5",
+            "bytes=a-b",
+            "bytes=--5",
+            "items=0-1",
+            "",
+        ] {
+            assert_eq!(parse_range(range, 1000), None, "{}", range);
+        }
+        assert_eq!(parse_range("bytes=0-", 0), None);
+    }
+
+    fn song(dir: &Path, cue_range: Option<CueRange>) -> Song {
+        let file_path = dir.join("album.flac");
+        fs::write(&file_path, b"0123456789").unwrap();
+        Song {
+            file_path,
+            cue_range,
+            ..Song::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_files_and_byte_ranges() {
+        let dir = env::temp_dir().join(format!("jukebox-media-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let song = song(&dir, None);
+        let settings = GlobalSettings::default();
+        let no_params = Params(Vec::new());
+
+        let response = stream(&song, &no_params, &HeaderMap::new(), &settings, false)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "audio/flac");
+        assert_eq!(
+            to_bytes(response.into_body(), 100).await.unwrap(),
+            "0123456789"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, "bytes=-3".parse().unwrap());
+        let response = stream(&song, &no_params, &headers, &settings, false)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes 7-9/10");
+        assert_eq!(response.headers()[CONTENT_LENGTH], "3");
+        assert_eq!(to_bytes(response.into_body(), 100).await.unwrap(), "789");
+
+        // a range that cannot be served gets the whole file
+        headers.insert(RANGE, "bytes=50-".parse().unwrap());
+        let response = stream(&song, &no_params, &headers, &settings, false)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn streams_cue_tracks_only_transcoded() {
+        let dir = env::temp_dir().join(format!("jukebox-media-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let song = song(
+            &dir,
+            Some(CueRange {
+                start: Duration::from_secs(60),
+                end: Some(Duration::from_secs(120)),
+            }),
+        );
+        let no_params = Params(Vec::new());
+        let raw = Params(vec![(String::from("format"), String::from("raw"))]);
+
+        let without_transcoder = GlobalSettings {
+            subsonic_transcoder: String::new(),
+            ..GlobalSettings::default()
+        };
+        let error = stream(
+            &song,
+            &no_params,
+            &HeaderMap::new(),
+            &without_transcoder,
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.code, ERROR_GENERIC);
+        assert!(
+            stream(&song, &raw, &HeaderMap::new(), &without_transcoder, false)
+                .await
+                .is_err()
+        );
+
+        let broken_transcoder = GlobalSettings {
+            subsonic_transcoder: dir.join("no-such-ffmpeg").to_string_lossy().to_string(),
+            ..GlobalSettings::default()
+        };
+        let error = stream(
+            &song,
+            &no_params,
+            &HeaderMap::new(),
+            &broken_transcoder,
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            error.message.contains("no-such-ffmpeg"),
+            "{}",
+            error.message
+        );
+
+        // downloads are the file itself
+        let response = stream(
+            &song,
+            &no_params,
+            &HeaderMap::new(),
+            &without_transcoder,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            to_bytes(response.into_body(), 100).await.unwrap(),
+            "0123456789"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use axum::{
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+use quick_xml::escape::escape;
+use serde_json::{json, Map, Value};
+use std::fmt::Write as _;
+
+/// The version of the Subsonic API the answers follow
+const API_VERSION: &str = "1.16.1";
+
+pub const ERROR_GENERIC: u32 = 0;
+pub const ERROR_MISSING_PARAMETER: u32 = 10;
+pub const ERROR_WRONG_CREDENTIALS: u32 = 40;
+pub const ERROR_NOT_FOUND: u32 = 70;
+
+#[derive(Debug)]
+pub struct SubsonicError {
+    pub(super) code: u32,
+    pub(super) message: String,
+}
+
+impl SubsonicError {
+    pub fn new(code: u32, message: impl Into<String>) -> SubsonicError {
+        SubsonicError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn not_found(what: &str) -> SubsonicError {
+        SubsonicError::new(ERROR_NOT_FOUND, format!("{} not found", what))
+    }
+}
+
+impl From<anyhow::Error> for SubsonicError {
+    fn from(e: anyhow::Error) -> SubsonicError {
+        SubsonicError::new(ERROR_GENERIC, e.to_string())
+    }
+}
+
+/// Clients ask for JSON with `f=json`, XML is the default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Xml,
+    Json,
+}
+
+impl Format {
+    pub fn from_param(param: Option<&str>) -> Format {
+        match param {
+            Some("json") => Format::Json,
+            _ => Format::Xml,
+        }
+    }
+}
+
+/// `payload` holds the endpoint's element by name, e.g. `{"album": {...}}`, or nothing
+pub fn ok(format: Format, payload: Map<String, Value>) -> Response {
+    respond(format, "ok", payload)
+}
+
+pub fn failed(format: Format, error: SubsonicError) -> Response {
+    let mut payload = Map::new();
+    payload.insert(
+        "error".to_string(),
+        json!({ "code": error.code, "message": error.message }),
+    );
+    respond(format, "failed", payload)
+}
+
+fn respond(format: Format, status: &str, mut body: Map<String, Value>) -> Response {
+    body.insert("status".to_string(), json!(status));
+    body.insert("version".to_string(), json!(API_VERSION));
+    body.insert("type".to_string(), json!("jukebox"));
+    body.insert(
+        "serverVersion".to_string(),
+        json!(env!("CARGO_PKG_VERSION")),
+    );
+    body.insert("openSubsonic".to_string(), json!(true));
+    let mut body = Value::Object(body);
+    remove_nulls(&mut body);
+
+    match format {
+        Format::Json => (
+            [(CONTENT_TYPE, "application/json")],
+            json!({ "subsonic-response": body }).to_string(),
+        )
+            .into_response(),
+        Format::Xml => {
+            body["xmlns"] = json!("http://subsonic.org/restapi");
+            let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+            write_element(&mut xml, "subsonic-response", &body);
+            ([(CONTENT_TYPE, "text/xml; charset=utf-8")], xml).into_response()
+        }
+    }
+}
+
+/// Missing optional fields are left out rather than sent as null
+fn remove_nulls(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            fields.retain(|_, value| !value.is_null());
+            fields.values_mut().for_each(remove_nulls);
+        }
+        Value::Array(items) => items.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
+}
+
+/// Scalar fields become attributes, objects and arrays of objects become child elements
+fn write_element(xml: &mut String, name: &str, value: &Value) {
+    let Value::Object(fields) = value else {
+        return;
+    };
+
+    let _ = write!(xml, "<{}", name);
+    let mut children = Vec::new();
+    for (key, value) in fields {
+        let text = match value {
+            Value::String(text) => text.clone(),
+            Value::Number(number) => number.to_string(),
+            Value::Bool(flag) => flag.to_string(),
+            Value::Object(_) => {
+                children.push((key, value));
+                continue;
+            }
+            Value::Array(items) => {
+                children.extend(items.iter().map(|item| (key, item)));
+                continue;
+            }
+            Value::Null => continue,
+        };
+        let _ = write!(xml, " {}=\"{}\"", key, escape(&text));
+    }
+
+    if children.is_empty() {
+        xml.push_str("/>");
+        return;
+    }
+    xml.push('>');
+    for (key, child) in children {
+        write_element(xml, key, child);
+    }
+    let _ = write!(xml, "</{}>", name);
+}
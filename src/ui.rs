@@ -1,24 +1,33 @@
 use components::{
-    centered_button, centered_title, change_ui, library_controls, library_song_list,
-    playback_controls, playback_queue, text_h5,
+    album_header, album_list, artist_list, centered_title, change_ui, genre_list,
+    library_composition, library_controls, listening_summary, playback_controls, playback_queue,
+    playlist_detail, playlist_list, practice_controls, rating_controls, resume_controls,
+    search_bar, shortcut_list, smart_playlist_detail, smart_query_input, song_table,
+    song_table_controls, text_h4, text_h5, theme_selector, track_list, visible_rows,
+    wrapped_controls,
 };
-use iced::widget::text_input;
-/// REQUIRED for macros despite being "unused"
-use iced::Application;
+pub use components::{format_duration, search_input_id, song_table_id};
+use iced::widget::{button, text_input};
 use iced::{
-    widget::{column, container, row, scrollable, text},
+    widget::{column, container, row, scrollable},
     Alignment, Element, Length,
 };
 
 use crate::library::Song;
+use crate::Jukebox;
 use crate::Message;
-use crate::{GlobalSettings, Jukebox};
+pub use theme::{load_user_themes, theme_files_state, Theme as ColorTheme, USER_THEMES_DIR};
+use uuid::Uuid;
 
 mod components;
 mod theme;
 
 // pub fn ui<'a>() -> Element<'a, Message> {}
 
+fn navbar<'a>(jb: &Jukebox) -> Element<'a, Message> {
+    change_ui(!jb.back_history.is_empty(), !jb.forward_history.is_empty())
+}
+
 pub fn loading_ui<'a>() -> Element<'a, Message> {
     container(row![centered_title("Loading...".into())])
         .center_x()
@@ -28,26 +37,63 @@ pub fn loading_ui<'a>() -> Element<'a, Message> {
         .into()
 }
 
-pub fn main_ui<'a>(jb: Jukebox) -> Element<'a, Message> {
-    let (now_playing, _current) = jb
-        .playback_queue
-        .lock()
-        .get(jb.playback_index)
-        .unwrap_or(&(Song::default(), true))
-        .clone();
+pub fn main_ui<'a>(jb: &Jukebox) -> Element<'a, Message> {
+    let player = jb.player.lock();
+    let song_at = |index| player.queue.get(index).map(|(song, _)| song.clone());
+    let (now_playing, next_up) = (
+        song_at(player.playback_index),
+        song_at(player.playback_index + 1),
+    );
+    let cover = now_playing.as_ref().and_then(|song| jb.cover_art(song));
+    let current = now_playing.clone().unwrap_or_default();
+
+    let navbar = navbar(jb);
 
-    let navbar = change_ui();
+    let visible = visible_rows(
+        jb.song_table_offset,
+        jb.song_table_height,
+        jb.search_results.len(),
+    );
+    let songs: Vec<Song> = {
+        let library = jb.music_library.lock();
+        jb.search_results[visible.clone()]
+            .iter()
+            .filter_map(|id| library.songs.get(id).cloned())
+            .collect()
+    };
 
-    let left_col =
-        column![playback_queue(jb.playback_queue.lock().clone())].align_items(Alignment::Start);
+    let left_col = column![playback_queue(player.queue.clone())].align_items(Alignment::Start);
     let right_col = column![
-        library_controls(),
-        // theme_selector(&jb.theme),
-        library_song_list(jb.music_library.lock().songs.clone())
+        library_controls(&jb.playlist_path),
+        search_bar(&jb.search_query, jb.search_results.len()),
+        song_table_controls(jb.selected_songs.len(), jb.selected_playlist.is_some()),
+        song_table(
+            songs,
+            visible.start,
+            jb.search_results.len(),
+            &jb.selected_songs,
+            jb.song_sort
+        )
     ]
     .align_items(Alignment::Start);
 
-    let global_layout = column![row![left_col, right_col], playback_controls(now_playing)];
+    let global_layout = column![
+        row![left_col, right_col],
+        practice_controls(&current.bookmarks, player.loop_points, &jb.bookmark_name),
+        row![
+            resume_controls(current.remember_position, current.resume_position),
+            rating_controls(current.rating),
+        ]
+        .spacing(16),
+        playback_controls(
+            now_playing,
+            cover,
+            player.current_position(),
+            next_up,
+            player.sleep_timer_remaining(),
+            player.stop_after
+        )
+    ];
 
     container(column![navbar, global_layout])
         .height(Length::Shrink)
@@ -55,14 +101,70 @@ pub fn main_ui<'a>(jb: Jukebox) -> Element<'a, Message> {
         .into()
 }
 
-pub fn settings_ui<'a>(settings: GlobalSettings) -> Element<'a, Message> {
+pub fn playlists_ui<'a>(jb: &Jukebox) -> Element<'a, Message> {
+    let navbar = navbar(jb);
+    let library = jb.music_library.lock();
+
+    let left_col = playlist_list(
+        &library.playlists,
+        &library.smart_playlists,
+        jb.selected_playlist,
+        &jb.playlist_name,
+        &jb.playlist_path,
+    );
+    let selected = jb.selected_playlist;
+    let right_col = if let Some(playlist) = selected.and_then(|id| library.playlist(id).ok()) {
+        let songs = playlist
+            .song_ids
+            .iter()
+            .map(|id| library.songs.get(id).cloned())
+            .collect();
+        playlist_detail(playlist, songs)
+    } else if let Some(smart_playlist) = selected.and_then(|id| library.smart_playlist(id)) {
+        let songs = library
+            .playlist_songs(smart_playlist.id)
+            .unwrap_or_default();
+        smart_playlist_detail(
+            smart_playlist,
+            songs,
+            &jb.smart_query,
+            jb.smart_query_error.as_deref(),
+        )
+    } else {
+        column![
+            centered_title("No playlist selected".into()),
+            smart_query_input(&jb.smart_query, jb.smart_query_error.as_deref()),
+            button("new smart playlist").on_press(Message::CreateSmartPlaylist),
+        ]
+        .padding(12)
+        .spacing(4)
+        .into()
+    };
+
+    container(column![navbar, row![left_col, right_col]])
+        .height(Length::Fill)
+        .width(Length::Fill)
+        .into()
+}
+
+pub fn settings_ui<'a>(jb: &Jukebox) -> Element<'a, Message> {
     // TODO convert to macro later so that it does not need to be manually updated with every change to GlobalSettings
-    let mut new_settings = settings;
+    let new_settings = jb.global_settings.clone();
+
+    let navbar = navbar(jb);
 
-    let navbar = change_ui();
+    let themes = ColorTheme::all_with(&jb.user_themes)
+        .into_iter()
+        .map(|theme| theme.name)
+        .collect();
 
     let items = scrollable(
         column![]
+            .push(theme_selector(
+                themes,
+                &new_settings.theme,
+                &jb.theme_errors,
+            ))
             .push(row![
                 text_h5("Folder to scan:".into()),
                 text_input("settings.folder_to_scan", &new_settings.folder_to_scan)
@@ -76,6 +178,24 @@ pub fn settings_ui<'a>(settings: GlobalSettings) -> Element<'a, Message> {
                     // .on_input(Message::SaveSettings(new_settings))
                     .padding(10)
                     .size(20),
+            ])
+            .push(row![
+                text_h5("Resume songs longer than (minutes):".into()),
+                text_input(
+                    "settings.resume_min_duration_mins",
+                    &new_settings.resume_min_duration_mins.to_string()
+                )
+                .padding(10)
+                .size(20),
+            ])
+            .push(row![
+                text_h5("Always resume genres:".into()),
+                text_input(
+                    "settings.resume_genres",
+                    &new_settings.resume_genres.join(", ")
+                )
+                .padding(10)
+                .size(20),
             ]), // .push(centered_button(
                 // "save settings".into(),
                 // Message::SaveSettings(new_settings),
@@ -85,3 +205,118 @@ pub fn settings_ui<'a>(settings: GlobalSettings) -> Element<'a, Message> {
 
     container(column![navbar, items]).into()
 }
+
+pub fn artists_ui<'a>(jb: &Jukebox) -> Element<'a, Message> {
+    let artists = jb.music_library.lock().artists_with_album_counts();
+
+    container(column![
+        navbar(jb),
+        centered_title("Artists".into()),
+        artist_list(artists)
+    ])
+    .height(Length::Fill)
+    .width(Length::Fill)
+    .into()
+}
+
+pub fn artist_ui<'a>(jb: &Jukebox, id: Uuid) -> Element<'a, Message> {
+    let library = jb.music_library.lock();
+    let name = library
+        .artists
+        .get(&id)
+        .map_or("Unknown artist".to_string(), |artist| artist.name.clone());
+    let singles = library.artist_singles(id);
+
+    let mut page = column![centered_title(name), album_list(library.artist_albums(id))];
+    if !singles.is_empty() {
+        page = page.push(text_h4("Songs".into())).push(track_list(singles));
+    }
+
+    container(column![navbar(jb), page.padding(12)])
+        .height(Length::Fill)
+        .width(Length::Fill)
+        .into()
+}
+
+pub fn album_ui<'a>(jb: &Jukebox, id: Uuid) -> Element<'a, Message> {
+    let (album, songs) = {
+        let library = jb.music_library.lock();
+        (library.albums.get(&id).cloned(), library.album_songs(id))
+    };
+    let Some(album) = album else {
+        return container(column![navbar(jb), centered_title("Unknown album".into())]).into();
+    };
+
+    let year = songs.iter().map(|song| song.year).max().unwrap_or(0);
+    let duration = songs.iter().map(|song| song.duration).sum();
+    let cover = songs.first().and_then(|song| jb.cover_art(song));
+
+    container(column![
+        navbar(jb),
+        album_header(&album, cover, year, duration),
+        track_list(songs)
+    ])
+    .height(Length::Fill)
+    .width(Length::Fill)
+    .into()
+}
+
+pub fn genres_ui<'a>(jb: &Jukebox) -> Element<'a, Message> {
+    let genres = jb.music_library.lock().genres();
+
+    container(column![
+        navbar(jb),
+        centered_title("Genres".into()),
+        genre_list(genres)
+    ])
+    .height(Length::Fill)
+    .width(Length::Fill)
+    .into()
+}
+
+pub fn genre_ui<'a>(jb: &Jukebox, genre: &str) -> Element<'a, Message> {
+    let albums = jb.music_library.lock().genre_albums(genre);
+
+    container(column![
+        navbar(jb),
+        row![
+            centered_title(genre.to_string()),
+            button("enqueue all").on_press(Message::EnqueueGenre(genre.to_string())),
+        ]
+        .spacing(12)
+        .align_items(Alignment::Center),
+        album_list(albums)
+    ])
+    .height(Length::Fill)
+    .width(Length::Fill)
+    .padding(12)
+    .into()
+}
+
+pub fn shortcut_help_ui<'a>(jb: &Jukebox) -> Element<'a, Message> {
+    container(shortcut_list(jb.keymap.help(), &jb.keymap.errors))
+        .center_x()
+        .center_y()
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .into()
+}
+
+pub fn stats_ui<'a>(jb: &Jukebox) -> Element<'a, Message> {
+    let content = column![
+        listening_summary(&jb.listening_stats, jb.stats_period),
+        library_composition(&jb.library_composition),
+        wrapped_controls(jb.wrapped_year, &jb.wrapped_path),
+    ]
+    .spacing(24);
+
+    container(column![
+        navbar(jb),
+        centered_title("Stats".into()),
+        scrollable(content).height(Length::Fill)
+    ])
+    .height(Length::Fill)
+    .width(Length::Fill)
+    .padding(12)
+    .into()
+}
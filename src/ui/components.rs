@@ -1,18 +1,29 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashSet, VecDeque};
+use std::ops::Range;
+use std::time::Duration;
 
-use iced::widget::Space;
+use iced::Alignment;
 use iced::{
-    alignment,
-    widget::{button, column, container, pick_list, row, scrollable, text, text_input},
-    Element, Length, Theme,
+    theme,
+    widget::{
+        button, checkbox, column, container, image, pick_list, progress_bar, row, scrollable, text,
+        text_input, Space,
+    },
+    Element, Length,
 };
-/// Application is REQUIRED for macros despite being "unused"
-use iced::{Alignment, Application};
 use uuid::Uuid;
 
-use crate::library::{Artist, Song};
+use super::theme::USER_THEMES_DIR;
+use crate::library::{
+    unix_now, Album, AlbumSummary, Artist, Bookmark, LibraryComposition, ListeningStats, Playlist,
+    Ranked, SmartPlaylist, Song, SongField, StatsPeriod,
+};
+use crate::player::{LoopPoints, PlayerCommand, StopAfter};
 use crate::{Message, UIState};
 
+/// Sleep timer lengths offered in the playback controls, in minutes
+const SLEEP_TIMER_PRESETS: [u64; 4] = [15, 30, 60, 90];
+
 pub fn centered_title<'a>(string: String) -> Element<'a, Message> {
     container(text_h1(string))
         // .width(Length::Fill)
@@ -35,9 +46,11 @@ pub fn centered_button<'a>(string: String, message: Message) -> Element<'a, Mess
 pub fn text_h1<'a>(string: String) -> Element<'a, Message> {
     text(string).size(48).line_height(1.6).into()
 }
+#[allow(dead_code)]
 pub fn text_h2<'a>(string: String) -> Element<'a, Message> {
     text(string).size(42).line_height(1.6).into()
 }
+#[allow(dead_code)]
 pub fn text_h3<'a>(string: String) -> Element<'a, Message> {
     text(string).size(38).line_height(1.6).into()
 }
@@ -54,32 +67,239 @@ pub fn text_p<'a>(string: String) -> Element<'a, Message> {
     text(string).size(16).line_height(1.6).into()
 }
 
-pub fn playback_controls<'a>(now_playing: Song) -> Element<'a, Message> {
+/// Formats a duration as `m:ss`, or `h:mm:ss` once it reaches an hour
+pub fn format_duration(duration: Duration) -> String {
+    let total_secs = duration.as_secs();
+    let (hours, minutes, seconds) = (total_secs / 3600, total_secs / 60 % 60, total_secs % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Formats a file size in bytes with the largest unit that keeps it at least 1
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+pub fn playback_controls<'a>(
+    now_playing: Option<Song>,
+    cover: Option<image::Handle>,
+    position: Duration,
+    next_up: Option<Song>,
+    sleep_remaining: Option<Duration>,
+    stop_after: Option<StopAfter>,
+) -> Element<'a, Message> {
+    let next_up = match next_up {
+        Some(song) => format!(
+            "Next: {} - {}",
+            song.title,
+            song.artists.first().unwrap_or(&Artist::default()).name
+        ),
+        None => String::from("Next: end of queue"),
+    };
+
     column![
-        text_h4(now_playing.title),
+        now_playing_panel(now_playing, cover, position),
+        text_p(next_up),
         row![
-            text_p(now_playing.album.unwrap_or_default().title),
-            text_p(
-                now_playing
-                    .artists
-                    .first()
-                    .unwrap_or(&Artist::default())
-                    .name
-                    .clone()
+            button("previous song").on_press(Message::Player(PlayerCommand::PreviousSong)),
+            button("play or pause").on_press(Message::Player(PlayerCommand::TogglePlayback)),
+            button("next song").on_press(Message::Player(PlayerCommand::NextSong)),
+        ]
+        .spacing(2),
+        sleep_controls(sleep_remaining, stop_after),
+    ]
+    .width(Length::Fill)
+    .align_items(Alignment::Center)
+    .spacing(2)
+    .padding(8)
+    .into()
+}
+
+/// Cover, tags and a progress bar with elapsed and remaining time
+fn now_playing_panel<'a>(
+    now_playing: Option<Song>,
+    cover: Option<image::Handle>,
+    position: Duration,
+) -> Element<'a, Message> {
+    let Some(song) = now_playing else {
+        return text_h4("Nothing playing".into());
+    };
+
+    let position = position.min(song.duration);
+    let remaining = song.duration.saturating_sub(position);
+    let album = song.album.map_or(String::new(), |album| album.title);
+    let artist = song
+        .artists
+        .first()
+        .unwrap_or(&Artist::default())
+        .name
+        .clone();
+
+    row![
+        cover_image(cover, 96.0),
+        column![
+            text_h4(song.title),
+            text_p(format!("{} - {}", artist, album)),
+            row![
+                text_p(format_duration(position)),
+                progress_bar(
+                    0.0..=song.duration.as_secs_f32().max(f32::EPSILON),
+                    position.as_secs_f32()
+                )
+                .width(Length::Fixed(400.0))
+                .height(Length::Fixed(8.0)),
+                text_p(format!("-{}", format_duration(remaining))),
+            ]
+            .spacing(8)
+            .align_items(Alignment::Center),
+        ]
+        .spacing(2),
+    ]
+    .spacing(12)
+    .align_items(Alignment::Center)
+    .into()
+}
+
+pub fn sleep_controls<'a>(
+    sleep_remaining: Option<Duration>,
+    stop_after: Option<StopAfter>,
+) -> Element<'a, Message> {
+    let status = match (sleep_remaining, stop_after) {
+        (Some(remaining), _) => format!("Sleeping in {}", format_duration(remaining)),
+        (None, Some(stop_after)) => format!("Stopping after {}", stop_after),
+        (None, None) => String::from("No sleep timer"),
+    };
+
+    let timer_buttons = SLEEP_TIMER_PRESETS.iter().fold(
+        row![text_p("Sleep:".into())].spacing(2),
+        |row, minutes| {
+            row.push(
+                button(text(format!("{} min", minutes))).on_press(Message::Player(
+                    PlayerCommand::SetSleepTimer(Some(*minutes)),
+                )),
             )
+        },
+    );
+
+    column![
+        text_h6(status),
+        row![
+            timer_buttons,
+            button("cancel timer").on_press(Message::Player(PlayerCommand::SetSleepTimer(None))),
         ]
-        .spacing(8),
+        .spacing(2),
         row![
-            button("previous song").on_press(Message::PreviousSong),
-            button("play or pause").on_press(Message::TogglePlayback),
-            button("next song").on_press(Message::NextSong),
+            button("stop after track").on_press(Message::Player(PlayerCommand::SetStopAfter(
+                Some(StopAfter::Track)
+            ))),
+            button("stop after album").on_press(Message::Player(PlayerCommand::SetStopAfter(
+                Some(StopAfter::Album)
+            ))),
+            button("keep playing").on_press(Message::Player(PlayerCommand::SetStopAfter(None))),
         ]
-        .spacing(2)
+        .spacing(2),
+    ]
+    .align_items(Alignment::Center)
+    .spacing(2)
+    .into()
+}
+
+/// A–B looping and bookmarks for the current song
+pub fn practice_controls<'a>(
+    bookmarks: &[Bookmark],
+    loop_points: LoopPoints,
+    bookmark_name: &str,
+) -> Element<'a, Message> {
+    let show_point = |point: Option<Duration>| point.map_or(String::from("-"), format_duration);
+
+    let loop_row = row![
+        text_p(format!(
+            "Loop A: {}  B: {}",
+            show_point(loop_points.start),
+            show_point(loop_points.end)
+        )),
+        button("set A").on_press(Message::Player(PlayerCommand::SetLoopStart)),
+        button("set B").on_press(Message::Player(PlayerCommand::SetLoopEnd)),
+        button("clear loop").on_press(Message::Player(PlayerCommand::ClearLoop)),
+    ]
+    .align_items(Alignment::Center)
+    .spacing(2);
+
+    let new_bookmark = row![
+        text_input("bookmark name", bookmark_name)
+            .on_input(Message::BookmarkNameChanged)
+            .on_submit(Message::AddBookmark)
+            .width(Length::Fixed(200.0)),
+        button("add bookmark").on_press(Message::AddBookmark),
+    ]
+    .spacing(2);
+
+    let bookmark_list =
+        bookmarks
+            .iter()
+            .enumerate()
+            .fold(row![].spacing(2), |row, (index, bookmark)| {
+                row.push(
+                    button(text(format!(
+                        "{} ({})",
+                        bookmark.name,
+                        format_duration(bookmark.position)
+                    )))
+                    .on_press(Message::Player(PlayerCommand::SeekTo(bookmark.position))),
+                )
+                .push(button("x").on_press(Message::RemoveBookmark(index)))
+            });
+
+    column![
+        loop_row,
+        new_bookmark,
+        scrollable(bookmark_list).direction(scrollable::Direction::Horizontal(
+            scrollable::Properties::default()
+        ))
     ]
     .width(Length::Fill)
     .align_items(Alignment::Center)
     .spacing(2)
-    .padding(8)
+    .padding(4)
+    .into()
+}
+
+pub fn resume_controls<'a>(
+    remember_position: Option<bool>,
+    resume_position: Option<Duration>,
+) -> Element<'a, Message> {
+    let status = match remember_position {
+        None => "Remember position: auto",
+        Some(true) => "Remember position: always",
+        Some(false) => "Remember position: never",
+    };
+    let saved = resume_position.map_or(String::new(), |position| {
+        format!("(saved at {})", format_duration(position))
+    });
+
+    row![
+        text_p(status.into()),
+        text_p(saved),
+        button("auto").on_press(Message::SetRememberPosition(None)),
+        button("always").on_press(Message::SetRememberPosition(Some(true))),
+        button("never").on_press(Message::SetRememberPosition(Some(false))),
+    ]
+    .align_items(Alignment::Center)
+    .spacing(4)
     .into()
 }
 
@@ -90,11 +310,11 @@ pub fn playback_queue<'a>(queue: VecDeque<(Song, bool)>) -> Element<'a, Message>
             .iter()
             .fold(column![].spacing(0.25), |column, (song, is_current)| {
                 column.push(text_p(format!(
-                    "{} - {} ({:?}) : {}",
+                    "{}{} - {} ({})",
+                    if *is_current { "▶ " } else { "" },
                     song.title,
                     song.artists.first().unwrap_or(&Artist::default()).name,
-                    song.duration,
-                    is_current
+                    format_duration(song.duration)
                 )))
             },)
     ]
@@ -104,7 +324,7 @@ pub fn playback_queue<'a>(queue: VecDeque<(Song, bool)>) -> Element<'a, Message>
     .into()
 }
 
-pub fn library_controls<'a>() -> Element<'a, Message> {
+pub fn library_controls<'a>(playlist_path: &str) -> Element<'a, Message> {
     let layout = column![
         centered_title("library controls".into()),
         row![
@@ -112,7 +332,16 @@ pub fn library_controls<'a>() -> Element<'a, Message> {
             button("add test song").on_press(Message::AddTestSongToQueue),
         ]
         .spacing(2),
-    ];
+        row![
+            text_input("playlist file (.m3u, .m3u8, .pls, .xspf)", playlist_path)
+                .on_input(Message::PlaylistPathChanged)
+                .width(Length::Fixed(300.0)),
+            button("import playlist").on_press(Message::ImportPlaylist),
+            button("export queue").on_press(Message::ExportQueue),
+        ]
+        .spacing(2),
+    ]
+    .spacing(2);
 
     container(layout)
         .height(Length::Shrink)
@@ -123,45 +352,664 @@ pub fn library_controls<'a>() -> Element<'a, Message> {
         .into()
 }
 
-pub fn library_song_list<'a>(songs: HashMap<Uuid, Song>) -> Element<'a, Message> {
-    container(scrollable(songs.iter().fold(
-        column![],
-        |column, (id, song)| {
-            column.push(centered_button(
-                format!(
-                    "{} - {} ({:?})",
-                    song.title,
-                    song.artists.first().unwrap_or(&Artist::default()).name,
-                    song.duration
-                ),
-                Message::PickSong(*id),
-            ))
+pub fn search_input_id() -> text_input::Id {
+    text_input::Id::new("search")
+}
+
+pub fn search_bar<'a>(query: &str, matches: usize) -> Element<'a, Message> {
+    column![
+        text_input("search (artist:bowie year:1970..1979)", query)
+            .id(search_input_id())
+            .on_input(Message::SearchChanged)
+            .width(Length::Fixed(400.0)),
+        text_p(format!("{} songs", matches)),
+    ]
+    .spacing(2)
+    .padding(4)
+    .into()
+}
+
+/// Height of one song table row, fixed so the visible rows can be worked out from the scroll offset
+pub const SONG_ROW_HEIGHT: f32 = 30.0;
+
+/// Column, header and width in pixels
+const SONG_TABLE_COLUMNS: [(SongField, &str, f32); 10] = [
+    (SongField::Title, "title", 240.0),
+    (SongField::Artist, "artist", 160.0),
+    (SongField::Album, "album", 160.0),
+    (SongField::Track, "#", 40.0),
+    (SongField::Duration, "time", 60.0),
+    (SongField::Year, "year", 50.0),
+    (SongField::Genre, "genre", 100.0),
+    (SongField::Plays, "plays", 50.0),
+    (SongField::Skips, "skips", 50.0),
+    (SongField::DaysAgoPlayed, "last played", 110.0),
+];
+
+pub fn song_table_id() -> scrollable::Id {
+    scrollable::Id::new("song_table")
+}
+
+/// Rows that are (partly) on screen, given the table's scroll offset and height
+pub fn visible_rows(offset: f32, height: f32, total: usize) -> Range<usize> {
+    let first = (offset.max(0.0) / SONG_ROW_HEIGHT) as usize;
+    let last = ((offset.max(0.0) + height) / SONG_ROW_HEIGHT).ceil() as usize + 1;
+    first.min(total)..last.min(total)
+}
+
+pub fn song_table_controls<'a>(selected: usize, can_add_to_playlist: bool) -> Element<'a, Message> {
+    let has_selection = selected > 0;
+    row![
+        text_p(format!("{} selected", selected)),
+        button("enqueue").on_press_maybe(has_selection.then_some(Message::EnqueueSelection)),
+        button("add to playlist").on_press_maybe(
+            (has_selection && can_add_to_playlist).then_some(Message::AddSelectionToPlaylist)
+        ),
+        button("select all").on_press(Message::SelectAllSongs),
+        button("clear").on_press_maybe(has_selection.then_some(Message::ClearSongSelection)),
+    ]
+    .spacing(4)
+    .padding(4)
+    .align_items(Alignment::Center)
+    .into()
+}
+
+/// Only `songs` (the rows starting at `first_row`) are built, the rest of the table is empty
+/// space of the right height so the scrollbar still covers all `total_rows`
+pub fn song_table<'a>(
+    songs: Vec<Song>,
+    first_row: usize,
+    total_rows: usize,
+    selected: &HashSet<Uuid>,
+    sort: Option<(SongField, bool)>,
+) -> Element<'a, Message> {
+    let header = SONG_TABLE_COLUMNS.iter().fold(
+        row![Space::with_width(Length::Fixed(SONG_ROW_HEIGHT))],
+        |header, (field, name, width)| {
+            let label = match sort {
+                Some((sorted, false)) if sorted == *field => format!("{} ▲", name),
+                Some((sorted, true)) if sorted == *field => format!("{} ▼", name),
+                _ => name.to_string(),
+            };
+            header.push(
+                button(text(label))
+                    .on_press(Message::SortSongsBy(*field))
+                    .style(theme::Button::Text)
+                    .padding(2)
+                    .width(Length::Fixed(*width)),
+            )
         },
-    )))
+    );
+
+    let rows_after = total_rows.saturating_sub(first_row + songs.len());
+    let rows = songs
+        .iter()
+        .fold(
+            column![Space::with_height(Length::Fixed(
+                first_row as f32 * SONG_ROW_HEIGHT
+            ))],
+            |rows, song| rows.push(song_row(song, selected.contains(&song.id))),
+        )
+        .push(Space::with_height(Length::Fixed(
+            rows_after as f32 * SONG_ROW_HEIGHT,
+        )));
+
+    container(column![
+        header,
+        scrollable(rows)
+            .id(song_table_id())
+            .on_scroll(Message::SongTableScrolled)
+            .height(Length::Fill),
+    ])
     .height(Length::Fill)
     .padding(12)
-    .max_width(700)
     .into()
 }
 
-// pub fn theme_selector<'a>(current_theme: &'a Theme) -> Element<'a, Message> {
-//     let choose_theme = column![
-//         text("Theme:"),
-//         pick_list(Theme::ALL, Some(current_theme), Message::ThemeChanged).width(Length::Fill),
-//     ]
-//     .spacing(10);
-//     container(choose_theme).into()
-// }
+fn song_row<'a>(song: &Song, is_selected: bool) -> Element<'a, Message> {
+    let id = song.id;
+    let cells = SONG_TABLE_COLUMNS
+        .iter()
+        .fold(row![], |cells, (field, _, width)| {
+            cells.push(
+                text(fit_to_width(&song_cell(song, *field), *width)).width(Length::Fixed(*width)),
+            )
+        });
+
+    row![
+        checkbox("", is_selected).on_toggle(move |_| Message::ToggleSongSelected(id)),
+        button(cells)
+            .on_press(Message::PickSong(id))
+            .style(theme::Button::Text)
+            .padding(2),
+    ]
+    .height(Length::Fixed(SONG_ROW_HEIGHT))
+    .align_items(Alignment::Center)
+    .into()
+}
+
+fn song_cell(song: &Song, field: SongField) -> String {
+    match field {
+        SongField::Title => song.title.clone(),
+        SongField::Artist => song
+            .artists
+            .first()
+            .unwrap_or(&Artist::default())
+            .name
+            .clone(),
+        SongField::Album => song
+            .album
+            .as_ref()
+            .map_or(String::new(), |album| album.title.clone()),
+        SongField::Track => song
+            .track_number
+            .map_or(String::new(), |number| number.to_string()),
+        SongField::Duration => format_duration(song.duration),
+        SongField::Year if song.year == 0 => String::new(),
+        SongField::Year => song.year.to_string(),
+        SongField::Genre => song.genre.clone(),
+        SongField::Plays => song.play_count.to_string(),
+        SongField::Skips => song.skip_count.to_string(),
+        SongField::DaysAgoPlayed if song.last_played == 0 => String::new(),
+        SongField::DaysAgoPlayed => match unix_now().saturating_sub(song.last_played) / 86400 {
+            0 => String::from("today"),
+            1 => String::from("yesterday"),
+            days => format!("{} days ago", days),
+        },
+        _ => String::new(),
+    }
+}
+
+/// Text does not get clipped, so cut it off before it would wrap onto a second line
+fn fit_to_width(value: &str, width: f32) -> String {
+    let max_chars = (width / 9.0) as usize;
+    if value.chars().count() <= max_chars {
+        return value.to_string();
+    }
+    let mut fitted: String = value.chars().take(max_chars.saturating_sub(1)).collect();
+    fitted.push('…');
+    fitted
+}
+
+pub fn playlist_list<'a>(
+    playlists: &[Playlist],
+    smart_playlists: &[SmartPlaylist],
+    selected: Option<Uuid>,
+    playlist_name: &str,
+    playlist_path: &str,
+) -> Element<'a, Message> {
+    let entries = playlists
+        .iter()
+        .map(|playlist| (playlist.id, &playlist.name, playlist.song_ids.len(), ""))
+        .chain(smart_playlists.iter().map(|playlist| {
+            (
+                playlist.id,
+                &playlist.name,
+                playlist.song_ids.len(),
+                " [smart]",
+            )
+        }));
+    let list = entries.fold(column![].spacing(2), |column, (id, name, len, kind)| {
+        let marker = if selected == Some(id) { "> " } else { "" };
+        column.push(centered_button(
+            format!("{}{} ({}){}", marker, name, len, kind),
+            Message::SelectPlaylist(id),
+        ))
+    });
 
-pub fn change_ui<'a>() -> Element<'a, Message> {
+    column![
+        centered_title("Playlists".into()),
+        text_input("playlist name", playlist_name)
+            .on_input(Message::PlaylistNameChanged)
+            .on_submit(Message::CreatePlaylist),
+        row![
+            button("new playlist").on_press(Message::CreatePlaylist),
+            button("save queue as playlist").on_press(Message::SaveQueueAsPlaylist),
+        ]
+        .spacing(2),
+        text_input("playlist file (.m3u, .m3u8, .pls, .xspf)", playlist_path)
+            .on_input(Message::PlaylistPathChanged),
+        button("import playlist").on_press(Message::ImportPlaylist),
+        scrollable(list).height(Length::Fill),
+    ]
+    .padding(12)
+    .max_width(350)
+    .spacing(4)
+    .into()
+}
+
+/// `songs` lines up with `playlist.song_ids`, `None` marking songs missing from the library
+pub fn playlist_detail<'a>(playlist: &Playlist, songs: Vec<Option<Song>>) -> Element<'a, Message> {
+    let last = songs.len().saturating_sub(1);
+    let tracks =
+        songs
+            .into_iter()
+            .enumerate()
+            .fold(column![].spacing(2), |column, (index, song)| {
+                let label = match song {
+                    Some(song) => format!(
+                        "{}. {} - {} ({})",
+                        index + 1,
+                        song.title,
+                        song.artists.first().unwrap_or(&Artist::default()).name,
+                        format_duration(song.duration)
+                    ),
+                    None => format!("{}. (missing from library)", index + 1),
+                };
+                column.push(
+                    row![
+                        container(text_p(label)).width(Length::Fill),
+                        button("up").on_press_maybe(
+                            (index > 0).then(|| Message::MoveInPlaylist(index, index - 1))
+                        ),
+                        button("down").on_press_maybe(
+                            (index < last).then(|| Message::MoveInPlaylist(index, index + 1))
+                        ),
+                        button("x").on_press(Message::RemoveFromPlaylist(index)),
+                    ]
+                    .align_items(Alignment::Center)
+                    .spacing(2),
+                )
+            });
+
+    column![
+        centered_title(playlist.name.clone()),
+        row![
+            button("play now").on_press(Message::PlayPlaylist),
+            button("enqueue").on_press(Message::EnqueuePlaylist),
+            button("rename").on_press(Message::RenamePlaylist),
+            button("duplicate").on_press(Message::DuplicatePlaylist),
+            button("export").on_press(Message::ExportPlaylist),
+            button("delete").on_press(Message::DeletePlaylist),
+        ]
+        .spacing(2),
+        scrollable(tracks).height(Length::Fill),
+    ]
+    .padding(12)
+    .spacing(4)
+    .width(Length::Fill)
+    .into()
+}
+
+pub fn smart_query_input<'a>(query: &str, error: Option<&str>) -> Element<'a, Message> {
+    column![
+        text_input(
+            "smart rule, e.g. genre = Jazz AND year < 1970 SORT BY year DESC LIMIT 50",
+            query
+        )
+        .on_input(Message::SmartQueryChanged),
+        text_p(
+            error
+                .map(|e| format!("Invalid rule: {}", e))
+                .unwrap_or_else(|| {
+                    String::from(
+                    "Fields: title artist album genre track year duration rating plays added played (days ago) skips. \
+                     Operators: = != ~ !~ < <= > >="
+                )
+                })
+        ),
+    ]
+    .spacing(2)
+    .into()
+}
+
+pub fn smart_playlist_detail<'a>(
+    smart_playlist: &SmartPlaylist,
+    songs: Vec<Song>,
+    query: &str,
+    query_error: Option<&str>,
+) -> Element<'a, Message> {
+    let tracks = songs
+        .iter()
+        .enumerate()
+        .fold(column![].spacing(2), |column, (index, song)| {
+            column.push(text_p(format!(
+                "{}. {} - {} ({})",
+                index + 1,
+                song.title,
+                song.artists.first().unwrap_or(&Artist::default()).name,
+                format_duration(song.duration)
+            )))
+        });
+
+    column![
+        centered_title(smart_playlist.name.clone()),
+        text_p(format!("Rule: {}", smart_playlist.query)),
+        row![
+            button("play now").on_press(Message::PlayPlaylist),
+            button("enqueue").on_press(Message::EnqueuePlaylist),
+            button("rename").on_press(Message::RenamePlaylist),
+            button("duplicate").on_press(Message::DuplicatePlaylist),
+            button("export").on_press(Message::ExportPlaylist),
+            button("delete").on_press(Message::DeletePlaylist),
+        ]
+        .spacing(2),
+        smart_query_input(query, query_error),
+        row![
+            button("update rule").on_press(Message::UpdateSmartQuery),
+            button("new smart playlist").on_press(Message::CreateSmartPlaylist),
+        ]
+        .spacing(2),
+        scrollable(tracks).height(Length::Fill),
+    ]
+    .padding(12)
+    .spacing(4)
+    .width(Length::Fill)
+    .into()
+}
+
+pub fn rating_controls<'a>(rating: Option<u8>) -> Element<'a, Message> {
+    let stars = rating.unwrap_or(0);
+    (1..=5)
+        .fold(row![text_p("Rating:".into())], |row, star| {
+            let label = if star <= stars { "★" } else { "☆" };
+            row.push(button(text(label)).on_press(Message::RateSong(Some(star))))
+        })
+        .push(button("clear").on_press(Message::RateSong(None)))
+        .align_items(Alignment::Center)
+        .spacing(2)
+        .into()
+}
+
+pub fn theme_selector<'a>(
+    themes: Vec<String>,
+    current_theme: &str,
+    errors: &[String],
+) -> Element<'a, Message> {
+    let choose_theme = row![
+        text_h5("Theme:".into()),
+        pick_list(
+            themes,
+            Some(current_theme.to_string()),
+            Message::ThemeChanged
+        )
+        .width(Length::Fixed(300.0)),
+        button("export theme").on_press(Message::ExportTheme),
+    ]
+    .spacing(10)
+    .align_items(Alignment::Center);
+
+    let layout = errors.iter().fold(
+        column![
+            choose_theme,
+            text_p(format!(
+                "Themes in {}/*.toml are loaded automatically, exporting writes the current theme there.",
+                USER_THEMES_DIR
+            )),
+        ]
+        .spacing(4),
+        |layout, error| layout.push(text_p(format!("Theme error: {}", error))),
+    );
+    container(layout).into()
+}
+
+pub fn shortcut_list<'a>(
+    shortcuts: Vec<(String, &str)>,
+    errors: &[String],
+) -> Element<'a, Message> {
+    let list = shortcuts
+        .into_iter()
+        .fold(column![].spacing(2), |list, (binding, description)| {
+            list.push(row![
+                text(binding).width(Length::Fixed(160.0)),
+                text(description.to_string()),
+            ])
+        });
+    let list = errors.iter().fold(list, |list, error| {
+        list.push(text_p(format!("Keymap error: {}", error)))
+    });
+
+    column![
+        centered_title("Keyboard shortcuts".into()),
+        list,
+        text_p("Change them in the [keymap] table of Settings.toml.".into()),
+        button("close").on_press(Message::ShowShortcutHelp(false)),
+    ]
+    .spacing(8)
+    .padding(12)
+    .into()
+}
+
+pub fn artist_list<'a>(artists: Vec<(Artist, usize)>) -> Element<'a, Message> {
+    let list = artists
+        .into_iter()
+        .fold(column![], |list, (artist, album_count)| {
+            list.push(
+                button(text(format!(
+                    "{} ({} album{})",
+                    artist.name,
+                    album_count,
+                    if album_count == 1 { "" } else { "s" }
+                )))
+                .on_press(Message::ChangeUI(UIState::Artist(artist.id)))
+                .style(theme::Button::Text),
+            )
+        });
+
+    scrollable(list).height(Length::Fill).into()
+}
+
+pub fn album_list<'a>(albums: Vec<AlbumSummary>) -> Element<'a, Message> {
+    let list = albums.into_iter().fold(column![], |list, summary| {
+        let year = match summary.year {
+            0 => String::new(),
+            year => format!(" ({})", year),
+        };
+        list.push(
+            row![
+                button(text(format!(
+                    "{}{} - {}, {} songs, {}",
+                    summary.album.title,
+                    year,
+                    summary.album.artist.name,
+                    summary.song_count,
+                    format_duration(summary.duration)
+                )))
+                .on_press(Message::ChangeUI(UIState::Album(summary.album.id)))
+                .style(theme::Button::Text),
+                button("enqueue").on_press(Message::EnqueueAlbum(summary.album.id)),
+            ]
+            .spacing(4)
+            .align_items(Alignment::Center),
+        )
+    });
+
+    scrollable(list).height(Length::Fill).into()
+}
+
+pub fn album_header<'a>(
+    album: &Album,
+    cover: Option<image::Handle>,
+    year: u16,
+    duration: Duration,
+) -> Element<'a, Message> {
+    let year = match year {
+        0 => String::new(),
+        year => year.to_string(),
+    };
+
+    row![
+        cover_image(cover, 200.0),
+        column![
+            text_h4(album.title.clone()),
+            button(text(album.artist.name.clone()))
+                .on_press(Message::ChangeUI(UIState::Artist(album.artist.id)))
+                .style(theme::Button::Text),
+            text_p(format!("{}  {}", year, format_duration(duration))),
+            button("enqueue album").on_press(Message::EnqueueAlbum(album.id)),
+        ]
+        .spacing(4),
+    ]
+    .spacing(12)
+    .padding(12)
+    .into()
+}
+
+/// A square cover, or a placeholder of the same size
+fn cover_image<'a>(cover: Option<image::Handle>, size: f32) -> Element<'a, Message> {
+    match cover {
+        Some(handle) => image(handle)
+            .width(Length::Fixed(size))
+            .height(Length::Fixed(size))
+            .into(),
+        None => container(text_p("no cover".into()))
+            .width(Length::Fixed(size))
+            .height(Length::Fixed(size))
+            .center_x()
+            .center_y()
+            .into(),
+    }
+}
+
+/// Songs with their disc and track numbers, clicking one enqueues it
+pub fn track_list<'a>(songs: Vec<Song>) -> Element<'a, Message> {
+    let has_discs = songs.iter().any(|song| song.disc_number.unwrap_or(1) > 1);
+    let list = songs.into_iter().fold(column![], |list, song| {
+        let number = match (song.disc_number, song.track_number) {
+            (Some(disc), Some(track)) if has_discs => format!("{}-{:02}. ", disc, track),
+            (_, Some(track)) => format!("{:02}. ", track),
+            _ => String::new(),
+        };
+        list.push(
+            button(text(format!(
+                "{}{} - {}",
+                number,
+                song.title,
+                format_duration(song.duration)
+            )))
+            .on_press(Message::PickSong(song.id))
+            .style(theme::Button::Text),
+        )
+    });
+
+    scrollable(list).height(Length::Fill).into()
+}
+
+pub fn genre_list<'a>(genres: Vec<(String, usize)>) -> Element<'a, Message> {
+    let list = genres
+        .into_iter()
+        .fold(column![], |list, (genre, song_count)| {
+            list.push(
+                button(text(format!("{} ({} songs)", genre, song_count)))
+                    .on_press(Message::ChangeUI(UIState::Genre(genre)))
+                    .style(theme::Button::Text),
+            )
+        });
+
+    scrollable(list).height(Length::Fill).into()
+}
+
+pub fn change_ui<'a>(can_go_back: bool, can_go_forward: bool) -> Element<'a, Message> {
     let button_box = row![
+        button("<").on_press_maybe(can_go_back.then_some(Message::NavigateBack)),
+        button(">").on_press_maybe(can_go_forward.then_some(Message::NavigateForward)),
+        button("?").on_press(Message::ShowShortcutHelp(true)),
         centered_button(
             "Dont press this one".into(),
             Message::ChangeUI(UIState::Loading)
         ),
         centered_button("Main".into(), Message::ChangeUI(UIState::Main)),
+        centered_button("Playlists".into(), Message::ChangeUI(UIState::Playlists)),
+        centered_button("Artists".into(), Message::ChangeUI(UIState::Artists)),
+        centered_button("Genres".into(), Message::ChangeUI(UIState::Genres)),
+        centered_button("Stats".into(), Message::ChangeUI(UIState::Stats)),
         centered_button("Settings".into(), Message::ChangeUI(UIState::Settings)),
     ]
     .width(Length::Fill);
     container(button_box).into()
 }
+
+/// Totals for the period and its top artists, albums, songs and genres side by side
+pub fn listening_summary<'a>(stats: &ListeningStats, period: StatsPeriod) -> Element<'a, Message> {
+    let choose_period = row![
+        text_h5("Listening".into()),
+        pick_list(StatsPeriod::ALL, Some(period), Message::StatsPeriodChanged),
+    ]
+    .spacing(10)
+    .align_items(Alignment::Center);
+
+    let top_lists = row![
+        top_list("Artists", &stats.top_artists),
+        top_list("Albums", &stats.top_albums),
+        top_list("Songs", &stats.top_songs),
+        top_list("Genres", &stats.top_genres),
+    ]
+    .spacing(12);
+
+    column![
+        choose_period,
+        text_p(format!(
+            "{} plays, {} skips, {} listened",
+            stats.plays,
+            stats.skips,
+            format_duration(stats.listened)
+        )),
+        top_lists,
+    ]
+    .spacing(4)
+    .into()
+}
+
+fn top_list<'a>(title: &str, ranked: &[Ranked]) -> Element<'a, Message> {
+    let list = ranked.iter().enumerate().fold(
+        column![text_h6(title.to_string())],
+        |list, (index, entry)| {
+            list.push(text(format!(
+                "{}. {} ({})",
+                index + 1,
+                entry.name,
+                entry.plays
+            )))
+        },
+    );
+    let list = if ranked.is_empty() {
+        list.push(text("nothing played"))
+    } else {
+        list
+    };
+    list.width(Length::FillPortion(1)).spacing(2).into()
+}
+
+/// Song count, length and size of the library, with songs per format and bitrate
+pub fn library_composition<'a>(composition: &LibraryComposition) -> Element<'a, Message> {
+    let counts = |title: &str, counts: &[(String, usize)]| {
+        counts.iter().fold(
+            column![text_h6(title.to_string())].width(Length::FillPortion(1)),
+            |list, (name, count)| list.push(text(format!("{}: {} songs", name, count))),
+        )
+    };
+
+    column![
+        text_h5("Library".into()),
+        text_p(format!(
+            "{} songs, {}, {}",
+            composition.songs,
+            format_duration(composition.duration),
+            format_size(composition.size)
+        )),
+        row![
+            counts("Formats", &composition.formats),
+            counts("Bitrates", &composition.bitrates),
+        ]
+        .spacing(12),
+    ]
+    .spacing(4)
+    .into()
+}
+
+/// Picks the year of the yearly summary and where it is exported to
+pub fn wrapped_controls<'a>(year: i32, path: &str) -> Element<'a, Message> {
+    row![
+        text_h5("Year in review".into()),
+        button("<").on_press(Message::WrappedYearChanged(year - 1)),
+        text_p(year.to_string()),
+        button(">").on_press(Message::WrappedYearChanged(year + 1)),
+        text_input(&format!("wrapped-{}.json", year), path)
+            .on_input(Message::WrappedPathChanged)
+            .width(Length::Fixed(300.0)),
+        button("export as JSON").on_press(Message::ExportWrapped),
+    ]
+    .spacing(10)
+    .align_items(Alignment::Center)
+    .into()
+}
@@ -1,5 +1,15 @@
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use std::cmp::Ordering;
+use std::{
+    cmp::Ordering,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// User themes, one TOML file each, in the same format that "export theme" writes.
+/// A user theme with the name of a built-in one replaces it.
+pub const USER_THEMES_DIR: &str = "themes";
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Theme {
@@ -67,6 +77,60 @@ impl Theme {
         ]
     }
 
+    /// Built-in themes, with user themes replacing or added to them, sorted by name
+    pub fn all_with(user_themes: &[Theme]) -> Vec<Theme> {
+        let mut themes: Vec<Theme> = Theme::all()
+            .into_iter()
+            .map(|(_, theme)| theme)
+            .filter(|theme| !user_themes.contains(theme))
+            .chain(user_themes.iter().cloned())
+            .collect();
+        themes.sort();
+        themes
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Theme> {
+        let theme: Theme = toml::from_str(contents)?;
+        if theme.name.trim().is_empty() {
+            return Err(anyhow!("the theme needs a name"));
+        }
+        Ok(theme)
+    }
+
+    /// Writes the theme to `dir`, named after the theme, and returns the file's path
+    pub fn export(&self, dir: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let file_name: String = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = dir.join(file_name).with_extension("toml");
+        fs::write(&path, toml::to_string(self)?)?;
+        Ok(path)
+    }
+
+    /// Bright surface is the text color, the normal colors are used for widgets
+    pub fn to_iced(&self) -> iced::Theme {
+        let palette = self.palette;
+        iced::Theme::custom(
+            self.name.clone(),
+            iced::theme::Palette {
+                background: to_iced_color(palette.base.background),
+                text: to_iced_color(palette.bright.surface),
+                primary: to_iced_color(palette.normal.primary),
+                success: to_iced_color(palette.bright.secondary),
+                danger: to_iced_color(palette.normal.error),
+            },
+        )
+    }
+
     pub fn dark() -> Theme {
         Theme {
             name: "Dark".to_string(),
@@ -429,7 +493,7 @@ impl Theme {
 }
 
 fn hex_to_color(hex: &str) -> Option<iced_native::Color> {
-    if hex.len() == 7 {
+    if hex.len() == 7 && hex.is_ascii() {
         let hash = &hex[0..1];
         let r = u8::from_str_radix(&hex[1..3], 16);
         let g = u8::from_str_radix(&hex[3..5], 16);
@@ -449,13 +513,18 @@ fn hex_to_color(hex: &str) -> Option<iced_native::Color> {
     None
 }
 
+fn to_iced_color(color: iced_native::Color) -> iced::Color {
+    let iced_native::Color { r, g, b, a } = color;
+    iced::Color { r, g, b, a }
+}
+
 fn color_to_hex(color: &iced_native::Color) -> String {
     let mut color_str = String::from("#");
 
     let iced_native::Color { r, g, b, .. } = color;
-    color_str.push_str(&format!("{:02X}", (r * 255.0) as u8));
-    color_str.push_str(&format!("{:02X}", (g * 255.0) as u8));
-    color_str.push_str(&format!("{:02X}", (b * 255.0) as u8));
+    color_str.push_str(&format!("{:02X}", (r * 255.0).round() as u8));
+    color_str.push_str(&format!("{:02X}", (g * 255.0).round() as u8));
+    color_str.push_str(&format!("{:02X}", (b * 255.0).round() as u8));
 
     color_str
 }
@@ -468,7 +537,7 @@ impl PartialEq for Theme {
 
 impl PartialOrd for Theme {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.name.cmp(&other.name))
+        Some(self.cmp(other))
     }
 }
 
@@ -525,3 +594,54 @@ mod serde_color {
         serializer.serialize_str(&color_to_hex(color))
     }
 }
+
+/// Every `.toml` theme in `dir`, together with an error message for each file that could not
+/// be used, so one broken file does not hide the others
+pub fn load_user_themes(dir: &Path) -> (Vec<Theme>, Vec<String>) {
+    let mut themes: Vec<Theme> = Vec::new();
+    let mut errors = Vec::new();
+
+    for path in theme_files(dir) {
+        let theme = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| Theme::from_toml(&contents));
+        match theme {
+            Ok(theme) if themes.contains(&theme) => errors.push(format!(
+                "{}: another file already defines a theme named {}",
+                path.display(),
+                theme.name
+            )),
+            Ok(theme) => themes.push(theme),
+            Err(e) => errors.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    (themes, errors)
+}
+
+/// Paths and modification times of the theme files, compared to notice edits while running
+pub fn theme_files_state(dir: &Path) -> Vec<(PathBuf, Option<SystemTime>)> {
+    theme_files(dir)
+        .into_iter()
+        .map(|path| {
+            let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+fn theme_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new(); // no themes directory is the same as no user themes
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "toml")
+        })
+        .collect();
+    paths.sort();
+    paths
+}